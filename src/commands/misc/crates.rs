@@ -1,6 +1,6 @@
 use color_eyre::eyre::Result;
 use poise::{CreateReply, serenity_prelude::CreateEmbed};
-use reqwest::StatusCode;
+use reqwest::{Client, StatusCode};
 use serde::Deserialize;
 use serenity::all::{CreateEmbedAuthor, CreateEmbedFooter, Timestamp};
 
@@ -49,6 +49,36 @@ struct Publisher {
     url: String,
 }
 
+/// What @grok gets to know about a crate.
+pub struct CrateSummary {
+    pub description: String,
+    pub version: String,
+    pub license: Option<String>,
+    pub repository: Option<String>,
+    pub yanked: bool,
+}
+
+/// Looks up `name` on crates.io, `None` if there is no such crate.
+pub async fn crate_summary(client: &Client, name: &str) -> Result<Option<CrateSummary>> {
+    let resp = client
+        .get(format!("{CRATES_API_URL}/crates/{name}"))
+        .send()
+        .await?;
+    if resp.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+
+    let resp = resp.error_for_status()?.json::<_Crate>().await?;
+    let version = resp.versions.into_iter().next();
+    Ok(Some(CrateSummary {
+        description: resp.c.description,
+        version: resp.c.max_stable_version,
+        license: version.as_ref().and_then(|v| v.license.clone()),
+        repository: resp.c.repository.or(resp.c.homepage),
+        yanked: version.is_some_and(|v| v.yanked),
+    }))
+}
+
 #[poise::command(
     slash_command,
     install_context = "Guild|User",
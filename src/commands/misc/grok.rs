@@ -0,0 +1,477 @@
+use color_eyre::eyre::{Result, eyre};
+use poise::serenity_prelude::{ChannelId, CreateEmbed, GuildId, Role};
+use poise::{ChoiceParameter as _, CreateReply};
+use std::fmt::Write as _;
+
+use crate::llm::{self, Access, AllowKind, GuildOverrides, Provider, Usage};
+use crate::types::Context;
+
+#[derive(Debug, Clone, Copy, poise::ChoiceParameter)]
+pub enum Setting {
+    Endpoint,
+    #[name = "API key"]
+    ApiKey,
+    Model,
+    Vision,
+    Temperature,
+    #[name = "Max tokens"]
+    MaxTokens,
+    #[name = "System prompt"]
+    SystemPrompt,
+}
+
+/// Configure the model behind @grok in this server
+#[allow(clippy::unused_async)]
+#[poise::command(
+    slash_command,
+    rename = "grok-config",
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    subcommands(
+        "show", "enable", "disable", "threads", "allow", "disallow", "set", "reset"
+    )
+)]
+pub async fn grok_config(_: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+fn guild_id(ctx: Context<'_>) -> Result<GuildId> {
+    ctx.guild_id()
+        .ok_or_else(|| eyre!("This command can only be used in a server."))
+}
+
+/// Only enough of a key to tell which one it is.
+fn mask_key(key: &str) -> String {
+    let chars: Vec<char> = key.chars().collect();
+    if chars.len() <= 8 {
+        return "••••".to_string();
+    }
+    let tail: String = chars[chars.len() - 4..].iter().collect();
+    format!("••••{tail}")
+}
+
+/// Show which model @grok uses here
+#[poise::command(slash_command, guild_only, required_permissions = "ADMINISTRATOR")]
+pub async fn show(ctx: Context<'_>) -> Result<()> {
+    let guild_id = guild_id(ctx)?;
+    let overrides = GuildOverrides::load(guild_id.get())?.unwrap_or_default();
+    let provider = Provider::resolve(&crate::config::get().grok, overrides.clone());
+    let access = Access::load(guild_id.get())?;
+
+    // Marks the settings that come from this server rather than the defaults.
+    let origin = |overridden: bool| if overridden { " (server)" } else { "" };
+
+    let mut description = String::new();
+    let _ = writeln!(
+        description,
+        "**Enabled**: {}",
+        if access.enabled { "yes" } else { "no" }
+    );
+    let _ = writeln!(
+        description,
+        "**Threads**: {}",
+        if access.threads { "yes" } else { "no" }
+    );
+    let _ = writeln!(
+        description,
+        "**Channels**: {}",
+        if access.channels.is_empty() {
+            "all".to_string()
+        } else {
+            access
+                .channels
+                .iter()
+                .map(|id| format!("<#{id}>"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        }
+    );
+    let _ = writeln!(
+        description,
+        "**Roles**: {}\n",
+        if access.roles.is_empty() {
+            "everyone".to_string()
+        } else {
+            access
+                .roles
+                .iter()
+                .map(|id| format!("<@&{id}>"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        }
+    );
+    let _ = writeln!(
+        description,
+        "**Endpoint**: `{}`{}",
+        provider.endpoint,
+        origin(overrides.endpoint.is_some())
+    );
+    let _ = writeln!(
+        description,
+        "**API key**: {}{}",
+        provider
+            .api_key
+            .as_deref()
+            .map_or_else(|| "none".to_string(), mask_key),
+        origin(overrides.api_key.is_some())
+    );
+    let _ = writeln!(
+        description,
+        "**Model**: `{}`{}",
+        provider.model,
+        origin(overrides.model.is_some())
+    );
+    let _ = writeln!(
+        description,
+        "**Vision**: {}{}",
+        if provider.vision { "yes" } else { "no" },
+        origin(overrides.vision.is_some())
+    );
+    let _ = writeln!(
+        description,
+        "**Temperature**: {}{}",
+        provider
+            .temperature
+            .map_or_else(|| "model default".to_string(), |t| t.to_string()),
+        origin(overrides.temperature.is_some())
+    );
+    let _ = writeln!(
+        description,
+        "**Max tokens**: {}{}",
+        provider
+            .max_tokens
+            .map_or_else(|| "model default".to_string(), |t| t.to_string()),
+        origin(overrides.max_tokens.is_some())
+    );
+    let _ = writeln!(
+        description,
+        "**Context window**: {} tokens",
+        provider.context_tokens
+    );
+    let _ = writeln!(
+        description,
+        "**System prompt**: {}{}",
+        if provider.system_prompt.is_some() {
+            "custom"
+        } else {
+            "built-in"
+        },
+        origin(overrides.system_prompt.is_some())
+    );
+
+    ctx.send(
+        CreateReply::default()
+            .embed(
+                CreateEmbed::new()
+                    .title("@grok configuration")
+                    .description(description)
+                    .color(0x00DE_A586),
+            )
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Let @grok answer in this server
+#[poise::command(slash_command, guild_only, required_permissions = "ADMINISTRATOR")]
+pub async fn enable(ctx: Context<'_>) -> Result<()> {
+    let guild_id = guild_id(ctx)?;
+    Access::set_enabled(guild_id.get(), true)?;
+
+    ctx.send(
+        CreateReply::default()
+            .content(
+                "✅ @grok will answer in this server. Limit it to some channels or roles with `/grok-config allow`.",
+            )
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Stop @grok from answering in this server
+#[poise::command(slash_command, guild_only, required_permissions = "ADMINISTRATOR")]
+pub async fn disable(ctx: Context<'_>) -> Result<()> {
+    let guild_id = guild_id(ctx)?;
+    Access::set_enabled(guild_id.get(), false)?;
+
+    ctx.send(
+        CreateReply::default()
+            .content("✅ @grok won't answer in this server anymore.")
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Have @grok open a thread for each conversation
+#[poise::command(slash_command, guild_only, required_permissions = "ADMINISTRATOR")]
+pub async fn threads(
+    ctx: Context<'_>,
+    #[description = "answer in a thread where every message is part of the conversation"]
+    enabled: bool,
+) -> Result<()> {
+    let guild_id = guild_id(ctx)?;
+    if !Access::set_threads(guild_id.get(), enabled)? {
+        return Err(eyre!(
+            "@grok isn't enabled in this server, turn it on with `/grok-config enable` first."
+        ));
+    }
+
+    let content = if enabled {
+        "✅ @grok will open a thread for each conversation and answer every message in it."
+    } else {
+        "✅ @grok will reply in place again. Threads it already opened keep working."
+    };
+    ctx.send(CreateReply::default().content(content).ephemeral(true))
+        .await?;
+    Ok(())
+}
+
+fn outcome(changed: bool, allowed: bool) -> &'static str {
+    match (changed, allowed) {
+        (true, true) => "✅ Allowed:",
+        (true, false) => "✅ No longer allowed:",
+        (false, true) => "Already allowed:",
+        (false, false) => "Wasn't allowed anyway:",
+    }
+}
+
+/// Adds or removes the given channel and role from the allow-lists, and
+/// describes what changed.
+fn update_allowed(
+    guild_id: GuildId,
+    channel: Option<ChannelId>,
+    role: Option<&Role>,
+    allowed: bool,
+) -> Result<String> {
+    if channel.is_none() && role.is_none() {
+        return Err(eyre!("Pick a channel, a role or both"));
+    }
+
+    let mut content = String::new();
+    if let Some(channel) = channel {
+        let changed =
+            Access::set_allowed(guild_id.get(), AllowKind::Channel, channel.get(), allowed)?;
+        let _ = writeln!(content, "{} <#{channel}>", outcome(changed, allowed));
+    }
+    if let Some(role) = role {
+        let changed = Access::set_allowed(guild_id.get(), AllowKind::Role, role.id.get(), allowed)?;
+        let _ = writeln!(content, "{} <@&{}>", outcome(changed, allowed), role.id);
+    }
+    Ok(content)
+}
+
+/// Limit @grok to a channel or to members with a role
+#[poise::command(slash_command, guild_only, required_permissions = "ADMINISTRATOR")]
+pub async fn allow(
+    ctx: Context<'_>,
+    #[description = "channel to answer in, threads included"] channel: Option<ChannelId>,
+    #[description = "role whose members may ask @grok"] role: Option<Role>,
+) -> Result<()> {
+    let guild_id = guild_id(ctx)?;
+    let content = update_allowed(guild_id, channel, role.as_ref(), true)?;
+
+    ctx.send(CreateReply::default().content(content).ephemeral(true))
+        .await?;
+    Ok(())
+}
+
+/// Take a channel or role off @grok's allow-lists
+#[poise::command(slash_command, guild_only, required_permissions = "ADMINISTRATOR")]
+pub async fn disallow(
+    ctx: Context<'_>,
+    #[description = "channel to take off the list"] channel: Option<ChannelId>,
+    #[description = "role to take off the list"] role: Option<Role>,
+) -> Result<()> {
+    let guild_id = guild_id(ctx)?;
+    let mut content = update_allowed(guild_id, channel, role.as_ref(), false)?;
+    let access = Access::load(guild_id.get())?;
+    if access.channels.is_empty() && access.roles.is_empty() {
+        content.push_str("\nThe allow-lists are empty, so @grok answers anyone in any channel.");
+    }
+
+    ctx.send(CreateReply::default().content(content).ephemeral(true))
+        .await?;
+    Ok(())
+}
+
+/// Override how @grok talks to its model in this server
+#[poise::command(slash_command, guild_only, required_permissions = "ADMINISTRATOR")]
+pub async fn set(
+    ctx: Context<'_>,
+    #[description = "base URL of an OpenAI-compatible API, e.g. https://api.example.com/v1"]
+    endpoint: Option<String>,
+    #[description = "API key sent as a bearer token"] api_key: Option<String>,
+    #[description = "model name"] model: Option<String>,
+    #[description = "whether the model understands images"] vision: Option<bool>,
+    #[description = "sampling temperature"]
+    #[min = 0.0]
+    #[max = 2.0]
+    temperature: Option<f64>,
+    #[description = "most tokens to generate per reply"]
+    #[min = 1]
+    max_tokens: Option<u32>,
+    #[description = "replaces the built-in system prompt"] system_prompt: Option<String>,
+) -> Result<()> {
+    let guild_id = guild_id(ctx)?;
+
+    if let Some(endpoint) = &endpoint {
+        llm::check_guild_endpoint(&crate::config::get().grok, endpoint).await?;
+    }
+
+    let changed = endpoint.is_some()
+        || api_key.is_some()
+        || model.is_some()
+        || vision.is_some()
+        || temperature.is_some()
+        || max_tokens.is_some()
+        || system_prompt.is_some();
+    if !changed {
+        ctx.send(
+            CreateReply::default()
+                .content("Nothing to change, pick at least one setting.")
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let mut overrides = GuildOverrides::load(guild_id.get())?.unwrap_or_default();
+    let trimmed = |value: Option<String>| value.map(|v| v.trim().to_string());
+
+    if let Some(endpoint) = trimmed(endpoint) {
+        // A key set for another endpoint shouldn't follow the server to a
+        // new one.
+        if api_key.is_none() && overrides.endpoint.as_ref() != Some(&endpoint) {
+            overrides.api_key = None;
+        }
+        overrides.endpoint = Some(endpoint);
+    }
+    if let Some(api_key) = trimmed(api_key) {
+        overrides.api_key = Some(api_key);
+    }
+    if let Some(model) = trimmed(model) {
+        overrides.model = Some(model);
+    }
+    overrides.vision = vision.or(overrides.vision);
+    overrides.temperature = temperature.or(overrides.temperature);
+    overrides.max_tokens = max_tokens.or(overrides.max_tokens);
+    if let Some(system_prompt) = trimmed(system_prompt) {
+        overrides.system_prompt = Some(system_prompt);
+    }
+
+    if overrides.uses_disallowed_model(&crate::config::get().grok) {
+        return Err(eyre!(
+            "This bot only lets servers pick some models on its own API. Set an endpoint or API key of your own, or ask the bot's operator to allow the model."
+        ));
+    }
+
+    overrides.save(guild_id.get())?;
+    ctx.send(
+        CreateReply::default()
+            .content("✅ Updated the @grok configuration, see `/grok-config show`.")
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Go back to the default for one setting, or all of them
+#[poise::command(slash_command, guild_only, required_permissions = "ADMINISTRATOR")]
+pub async fn reset(
+    ctx: Context<'_>,
+    #[description = "setting to reset (defaults to all of them)"] setting: Option<Setting>,
+) -> Result<()> {
+    let guild_id = guild_id(ctx)?;
+    let mut overrides = GuildOverrides::load(guild_id.get())?.unwrap_or_default();
+
+    match setting {
+        None => overrides = GuildOverrides::default(),
+        Some(Setting::Endpoint) => {
+            overrides.endpoint = None;
+            overrides.api_key = None;
+        }
+        Some(Setting::ApiKey) => overrides.api_key = None,
+        Some(Setting::Model) => overrides.model = None,
+        Some(Setting::Vision) => overrides.vision = None,
+        Some(Setting::Temperature) => overrides.temperature = None,
+        Some(Setting::MaxTokens) => overrides.max_tokens = None,
+        Some(Setting::SystemPrompt) => overrides.system_prompt = None,
+    }
+    overrides.save(guild_id.get())?;
+
+    let content = match setting {
+        None => "✅ @grok uses the default configuration again.".to_string(),
+        Some(Setting::Endpoint) => {
+            "✅ @grok uses the default endpoint and API key again.".to_string()
+        }
+        Some(setting) => format!("✅ Reset {} to the default.", setting.name().to_lowercase()),
+    };
+    ctx.send(CreateReply::default().content(content).ephemeral(true))
+        .await?;
+    Ok(())
+}
+
+/// See who has been asking @grok the most in this server
+#[poise::command(
+    slash_command,
+    rename = "grok-usage",
+    guild_only,
+    default_member_permissions = "ADMINISTRATOR",
+    required_permissions = "ADMINISTRATOR"
+)]
+pub async fn grok_usage(
+    ctx: Context<'_>,
+    #[description = "how many days back to look, today included (defaults to 1)"]
+    #[min = 1]
+    #[max = 90]
+    days: Option<u32>,
+) -> Result<()> {
+    let guild_id = guild_id(ctx)?;
+    let days = days.unwrap_or(1).clamp(1, 90);
+    let usage = llm::usage_by_user(guild_id.get(), days)?;
+
+    let mut total = Usage::default();
+    let mut requests = 0;
+    let mut description = String::new();
+    for (index, user) in usage.iter().enumerate() {
+        total.add(user.usage);
+        requests += user.requests;
+        if index < 25 {
+            let _ = writeln!(
+                description,
+                "<@{}>: {} requests, {} prompt + {} completion tokens",
+                user.user_id, user.requests, user.usage.prompt_tokens, user.usage.completion_tokens
+            );
+        }
+    }
+    if usage.is_empty() {
+        description.push_str("Nobody has asked @grok anything.");
+    } else {
+        let _ = write!(
+            description,
+            "\n**Total**: {requests} requests, {} prompt + {} completion tokens",
+            total.prompt_tokens, total.completion_tokens
+        );
+    }
+
+    let title = if days == 1 {
+        "@grok usage today".to_string()
+    } else {
+        format!("@grok usage over the last {days} days")
+    };
+    ctx.send(
+        CreateReply::default()
+            .embed(
+                CreateEmbed::new()
+                    .title(title)
+                    .description(description)
+                    .color(0x00DE_A586),
+            )
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
@@ -1,4 +1,5 @@
 pub mod avatarsync;
 pub mod crates;
+pub mod grok;
 pub mod starboard;
 pub mod typst;
@@ -89,6 +89,27 @@ impl World for MathWorld {
     }
 }
 
+/// Renders a typst math expression to a PNG. Compiling is slow enough that
+/// this should be run on a blocking thread.
+pub fn render_math(expression: &str) -> Result<Vec<u8>> {
+    let world = MathWorld::new(expression);
+    let document = typst::compile::<PagedDocument>(&world)
+        .output
+        .map_err(|diagnostics| {
+            let messages: Vec<String> = diagnostics.iter().map(|d| d.message.to_string()).collect();
+            eyre!("Compilation error:\n{}", messages.join("\n"))
+        })?;
+
+    if document.pages.is_empty() {
+        return Err(eyre!("Compilation produced no pages"));
+    }
+    let page = &document.pages[0];
+    let pixmap = typst_render::render(page, 9.0);
+    pixmap
+        .encode_png()
+        .map_err(|e| eyre!("PNG encoding failed: {e}"))
+}
+
 #[poise::command(
     slash_command,
     install_context = "Guild|User",
@@ -100,26 +121,7 @@ pub async fn typst(
 ) -> Result<()> {
     ctx.defer().await?;
 
-    let result = tokio::task::spawn_blocking(move || {
-        let world = MathWorld::new(&expression);
-        let document = typst::compile::<PagedDocument>(&world)
-            .output
-            .map_err(|diagnostics| {
-                let messages: Vec<String> =
-                    diagnostics.iter().map(|d| d.message.to_string()).collect();
-                eyre!("Compilation error:\n{}", messages.join("\n"))
-            })?;
-
-        if document.pages.is_empty() {
-            return Err(eyre!("Compilation produced no pages"));
-        }
-        let page = &document.pages[0];
-        let pixmap = typst_render::render(page, 9.0);
-        pixmap
-            .encode_png()
-            .map_err(|e| eyre!("PNG encoding failed: {e}"))
-    })
-    .await??;
+    let result = tokio::task::spawn_blocking(move || render_math(&expression)).await??;
 
     let attachment = CreateAttachment::bytes(result, "math.png");
     ctx.send(CreateReply::default().attachment(attachment))
@@ -0,0 +1,272 @@
+use color_eyre::eyre::Result;
+use poise::CreateReply;
+use poise::serenity_prelude::{
+    ChannelId, Context as SerenityContext, CreateEmbed, CreateEmbedFooter, CreateMessage,
+};
+use std::fmt::Write as _;
+use tracing::error;
+
+use crate::commands::nix::nixpkg::{autocomplete_channel, resolve_channel};
+use crate::commands::nix::version;
+use crate::nixpkgs_db::{
+    self,
+    diff::{self, ChangeKind, ChannelUpdate, PackageChange},
+};
+use crate::types::Context;
+use crate::utils::DB;
+
+const UPDATE_INTERVAL_SECS: u64 = 43_200;
+/// Changes listed per page of `/nixpkgs-changes`.
+const PAGE_SIZE: usize = 20;
+/// How many packages of each kind an announcement lists by name.
+const ANNOUNCE_SAMPLE: usize = 10;
+
+/// Show what changed in the latest release of a nixpkgs channel
+#[poise::command(
+    slash_command,
+    rename = "nixpkgs-changes",
+    install_context = "Guild|User",
+    interaction_context = "Guild|BotDm|PrivateChannel"
+)]
+pub async fn nixpkgs_changes(
+    ctx: Context<'_>,
+    #[description = "channel to show (defaults to the first configured channel)"]
+    #[autocomplete = "autocomplete_channel"]
+    channel: Option<String>,
+    #[description = "only show one kind of change"] kind: Option<ChangeKind>,
+) -> Result<()> {
+    ctx.defer().await?;
+
+    let channel = resolve_channel(channel.as_deref())?;
+    let Some(update) = diff::latest(&channel.name)? else {
+        ctx.say(format!(
+            "No releases of `{}` have been recorded yet.",
+            channel.name
+        ))
+        .await?;
+        return Ok(());
+    };
+
+    let kinds = kind.map_or_else(|| ChangeKind::ALL.to_vec(), |kind| vec![kind]);
+    let lines: Vec<String> = kinds
+        .iter()
+        .flat_map(|kind| update.changes_of(*kind))
+        .map(|change| format!("{} {}", change.kind.label(), format_change(change)))
+        .collect();
+
+    let header = summary(&update);
+    if lines.is_empty() {
+        let embed = CreateEmbed::new()
+            .title(format!("Changes in `{}`", channel.name))
+            .description(format!("{header}\n\nNothing to show."))
+            .color(0x00DE_A586);
+        ctx.send(CreateReply::default().embed(embed)).await?;
+        return Ok(());
+    }
+
+    let pages: Vec<String> = lines
+        .chunks(PAGE_SIZE)
+        .map(|chunk| format!("**{}**\n{header}\n\n{}", channel.name, chunk.join("\n")))
+        .collect();
+
+    if let [page] = pages.as_slice() {
+        let embed = CreateEmbed::new()
+            .title(format!("Changes in `{}`", channel.name))
+            .description(page)
+            .color(0x00DE_A586);
+        ctx.send(CreateReply::default().embed(embed)).await?;
+    } else {
+        let pages: Vec<&str> = pages.iter().map(String::as_str).collect();
+        poise::builtins::paginate(ctx, &pages).await?;
+    }
+
+    Ok(())
+}
+
+/// Post a summary of every nixpkgs channel bump in this server
+#[poise::command(slash_command, required_permissions = "ADMINISTRATOR")]
+pub async fn nixpkgs_announce_enable(
+    ctx: Context<'_>,
+    #[description = "Channel to post channel bump summaries to"] channel: ChannelId,
+) -> Result<()> {
+    let Some(guild_id) = ctx.guild_id() else {
+        ctx.send(
+            CreateReply::default()
+                .content("This command can only be used in a server.")
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    };
+
+    {
+        let conn = DB.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO nixpkgs_announce_config (guild_id, channel_id) VALUES (?, ?)",
+            [guild_id.get().cast_signed(), channel.get().cast_signed()],
+        )?;
+    }
+
+    ctx.send(
+        CreateReply::default()
+            .content(format!(
+                "✅ nixpkgs channel bumps will be announced in <#{channel}>."
+            ))
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Stop announcing nixpkgs channel bumps in this server
+#[poise::command(slash_command, required_permissions = "ADMINISTRATOR")]
+pub async fn nixpkgs_announce_disable(ctx: Context<'_>) -> Result<()> {
+    let Some(guild_id) = ctx.guild_id() else {
+        ctx.send(
+            CreateReply::default()
+                .content("This command can only be used in a server.")
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    };
+
+    {
+        let conn = DB.lock().unwrap();
+        conn.execute(
+            "DELETE FROM nixpkgs_announce_config WHERE guild_id = ?",
+            [guild_id.get().cast_signed()],
+        )?;
+    }
+
+    ctx.send(
+        CreateReply::default()
+            .content("✅ nixpkgs channel bumps will no longer be announced in this server.")
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
+fn format_change(change: &PackageChange) -> String {
+    let old = change.old_version.as_deref().unwrap_or("?");
+    let new = change.new_version.as_deref().unwrap_or("?");
+    match change.kind {
+        ChangeKind::Removed => format!("`{}` {old}", change.package_name),
+        ChangeKind::Updated => format!("`{}` {old} → {new}", change.package_name),
+        ChangeKind::Added | ChangeKind::Broken | ChangeKind::Insecure => {
+            format!("`{}` {new}", change.package_name)
+        }
+    }
+}
+
+/// The release hashes, when the bump was seen and how many changes of each
+/// kind it brought.
+fn summary(update: &ChannelUpdate) -> String {
+    let short = |hash: &str| hash.chars().take(8).collect::<String>();
+
+    let mut text = format!(
+        "`{}` → `{}`, <t:{}:R>\n",
+        update
+            .from_hash
+            .as_deref()
+            .map_or_else(|| "?".to_string(), short),
+        short(&update.to_hash),
+        update.created_at
+    );
+
+    let counts: Vec<String> = ChangeKind::ALL
+        .iter()
+        .map(|kind| {
+            format!(
+                "**{}** {}",
+                update.changes_of(*kind).count(),
+                kind.label().to_lowercase()
+            )
+        })
+        .collect();
+    text.push_str(&counts.join(" · "));
+    text
+}
+
+fn announcement(update: &ChannelUpdate) -> CreateEmbed {
+    let mut description = summary(update);
+
+    for kind in [ChangeKind::Broken, ChangeKind::Insecure, ChangeKind::Added] {
+        let changes: Vec<&PackageChange> = update.changes_of(kind).collect();
+        if changes.is_empty() {
+            continue;
+        }
+
+        let _ = write!(description, "\n\n**{}**\n", kind.label());
+        for change in changes.iter().take(ANNOUNCE_SAMPLE) {
+            let _ = writeln!(description, "- {}", format_change(change));
+        }
+        if changes.len() > ANNOUNCE_SAMPLE {
+            let _ = writeln!(
+                description,
+                "- ...and {} more",
+                changes.len() - ANNOUNCE_SAMPLE
+            );
+        }
+    }
+
+    CreateEmbed::new()
+        .title(format!("`{}` has a new release", update.channel))
+        .description(description)
+        .footer(CreateEmbedFooter::new(
+            "Use /nixpkgs-changes for the full list",
+        ))
+        .color(0x00DE_A586)
+}
+
+/// Posts a summary of `update` to every server's announcement channel.
+async fn announce(serenity: &SerenityContext, update: &ChannelUpdate) {
+    let channels: Vec<u64> = tokio::task::block_in_place(|| {
+        let conn = DB.lock().unwrap();
+        let Ok(mut stmt) = conn.prepare("SELECT channel_id FROM nixpkgs_announce_config") else {
+            return Vec::new();
+        };
+        stmt.query_map([], |row| row.get::<_, i64>(0))
+            .map(|iter| {
+                iter.filter_map(std::result::Result::ok)
+                    .map(i64::cast_unsigned)
+                    .collect()
+            })
+            .unwrap_or_default()
+    });
+
+    let embed = announcement(update);
+    for channel_id in channels {
+        if let Err(err) = ChannelId::new(channel_id)
+            .send_message(serenity, CreateMessage::new().embed(embed.clone()))
+            .await
+        {
+            error!(channel = %update.channel, "failed to announce in {channel_id}: {err}");
+        }
+    }
+}
+
+/// Periodically refreshes every channel's package database, announcing each
+/// channel bump as it is picked up and checking the version trackers. The
+/// first round runs right away, so releases that came out while the bot was
+/// down are announced on startup.
+pub fn spawn_updater(serenity: SerenityContext) {
+    tokio::spawn(async move {
+        let mut interval =
+            tokio::time::interval(std::time::Duration::from_secs(UPDATE_INTERVAL_SECS));
+        loop {
+            interval.tick().await;
+            for channel in nixpkgs_db::channels() {
+                match nixpkgs_db::ensure_channel_database(channel).await {
+                    Ok(Some(update)) => announce(&serenity, &update).await,
+                    Ok(None) => {}
+                    Err(e) => error!(channel = %channel.name, "failed to update database: {e}"),
+                }
+                version::check_channel(&serenity, channel).await;
+            }
+        }
+    });
+}
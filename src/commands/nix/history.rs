@@ -0,0 +1,110 @@
+use crate::commands::nix::nixpkg::{autocomplete_channel, autocomplete_package, resolve_channel};
+use crate::nixpkgs_db::history::{self, VersionRecord};
+use crate::types::Context;
+use color_eyre::eyre::Result;
+use poise::{
+    CreateReply,
+    serenity_prelude::{CreateEmbed, CreateEmbedFooter},
+};
+use rusqlite::OptionalExtension;
+
+/// Versions listed per page of `/nixpkg-history`.
+const PAGE_SIZE: usize = 15;
+
+fn format_record(record: &VersionRecord) -> String {
+    let hash = record
+        .first_hash
+        .as_deref()
+        .map(|hash| format!(" (`{}`)", hash.chars().take(8).collect::<String>()))
+        .unwrap_or_default();
+
+    if record.baseline {
+        format!(
+            "`{}` already in the channel <t:{}:D>{hash}",
+            record.version, record.first_seen
+        )
+    } else {
+        format!(
+            "`{}` landed <t:{}:D> (<t:{}:R>){hash}",
+            record.version, record.first_seen, record.first_seen
+        )
+    }
+}
+
+/// Show when each version of a package landed in a nixpkgs channel
+#[poise::command(
+    slash_command,
+    rename = "nixpkg-history",
+    install_context = "Guild|User",
+    interaction_context = "Guild|BotDm|PrivateChannel"
+)]
+pub async fn nixpkg_history(
+    ctx: Context<'_>,
+    #[description = "package name"]
+    #[autocomplete = "autocomplete_package"]
+    package: String,
+    #[description = "channel to look in (defaults to the first configured channel)"]
+    #[autocomplete = "autocomplete_channel"]
+    channel: Option<String>,
+) -> Result<()> {
+    ctx.defer().await?;
+
+    let channel = resolve_channel(channel.as_deref())?;
+    let package = package.trim();
+    let records = history::versions(&channel.name, package)?;
+
+    let current: Option<String> = {
+        let db = channel.db()?;
+        db.query_row(
+            "SELECT version FROM packages WHERE package_name = ?1",
+            [package],
+            |row| row.get::<_, Option<String>>(0),
+        )
+        .optional()?
+        .flatten()
+    };
+
+    if records.is_empty() {
+        let description = match &current {
+            Some(version) => format!(
+                "`{package}` is at `{version}`, but no history has been recorded for it yet."
+            ),
+            None => format!("No history has been recorded for `{package}`."),
+        };
+        let embed = CreateEmbed::new()
+            .title(format!("History of `{package}`"))
+            .description(description)
+            .footer(CreateEmbedFooter::new(&channel.name))
+            .color(0x00DE_A586);
+        ctx.send(CreateReply::default().embed(embed)).await?;
+        return Ok(());
+    }
+
+    let header = match &current {
+        Some(version) => format!("Currently at `{version}`"),
+        None => "No longer in the channel".to_string(),
+    };
+    let lines: Vec<String> = records.iter().map(format_record).collect();
+    let pages: Vec<String> = lines
+        .chunks(PAGE_SIZE)
+        .map(|chunk| format!("{header}\n\n{}", chunk.join("\n")))
+        .collect();
+
+    if let [page] = pages.as_slice() {
+        let embed = CreateEmbed::new()
+            .title(format!("History of `{package}`"))
+            .description(page)
+            .footer(CreateEmbedFooter::new(&channel.name))
+            .color(0x00DE_A586);
+        ctx.send(CreateReply::default().embed(embed)).await?;
+    } else {
+        let pages: Vec<String> = pages
+            .iter()
+            .map(|page| format!("**{package}** in **{}**\n{page}", channel.name))
+            .collect();
+        let pages: Vec<&str> = pages.iter().map(String::as_str).collect();
+        poise::builtins::paginate(ctx, &pages).await?;
+    }
+
+    Ok(())
+}
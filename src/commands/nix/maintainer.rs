@@ -0,0 +1,173 @@
+use crate::commands::nix::nixpkg::{autocomplete_channel, resolve_channel};
+use crate::nixpkgs_db::{
+    self,
+    maintainers::{self, ListedPackage},
+};
+use crate::types::Context;
+use color_eyre::eyre::Result;
+use poise::{
+    CreateReply,
+    serenity_prelude::{CreateEmbed, CreateEmbedFooter},
+};
+use std::fmt::Write as _;
+
+/// Packages listed per page.
+const PAGE_SIZE: usize = 20;
+/// Discord shows at most 25 autocomplete choices.
+const MAX_COMPLETIONS: usize = 25;
+
+#[allow(clippy::unused_async)]
+async fn autocomplete_maintainer(_ctx: Context<'_>, partial: &str) -> Vec<String> {
+    let Ok(db) = nixpkgs_db::default_channel().db() else {
+        return Vec::new();
+    };
+    maintainers::complete_handles(&db, partial, MAX_COMPLETIONS).unwrap_or_default()
+}
+
+/// List every package someone maintains
+#[poise::command(
+    slash_command,
+    rename = "nixpkgs-maintainer",
+    install_context = "Guild|User",
+    interaction_context = "Guild|BotDm|PrivateChannel"
+)]
+pub async fn nixpkgs_maintainer(
+    ctx: Context<'_>,
+    #[description = "GitHub handle of the maintainer"]
+    #[autocomplete = "autocomplete_maintainer"]
+    github: String,
+    #[description = "channel to look in (defaults to the first configured channel)"]
+    #[autocomplete = "autocomplete_channel"]
+    channel: Option<String>,
+) -> Result<()> {
+    ctx.defer().await?;
+
+    let channel = resolve_channel(channel.as_deref())?;
+    let github = github.trim().trim_start_matches('@');
+    let packages = {
+        let db = channel.db()?;
+        maintainers::packages_of(&db, github)?
+    };
+
+    if packages.is_empty() {
+        let embed = CreateEmbed::new()
+            .title(format!("`{github}` doesn't maintain any packages"))
+            .footer(CreateEmbedFooter::new(&channel.name))
+            .color(0x00DE_A586);
+        ctx.send(CreateReply::default().embed(embed)).await?;
+        return Ok(());
+    }
+
+    let broken = packages.iter().filter(|package| package.broken).count();
+    let insecure = packages.iter().filter(|package| package.insecure).count();
+    let header = format!(
+        "**{}** packages · **{broken}** broken · **{insecure}** insecure",
+        packages.len()
+    );
+
+    send_listing(
+        ctx,
+        &format!("Packages maintained by `{github}`"),
+        &header,
+        &channel.name,
+        &packages,
+    )
+    .await
+}
+
+/// List packages without maintainers, to find something to adopt
+#[poise::command(
+    slash_command,
+    rename = "nixpkgs-orphans",
+    install_context = "Guild|User",
+    interaction_context = "Guild|BotDm|PrivateChannel"
+)]
+pub async fn nixpkgs_orphans(
+    ctx: Context<'_>,
+    #[description = "attribute path prefix, e.g. python3Packages."] prefix: String,
+    #[description = "channel to look in (defaults to the first configured channel)"]
+    #[autocomplete = "autocomplete_channel"]
+    channel: Option<String>,
+) -> Result<()> {
+    ctx.defer().await?;
+
+    let channel = resolve_channel(channel.as_deref())?;
+    let (packages, total) = {
+        let db = channel.db()?;
+        maintainers::orphans(&db, &prefix)?
+    };
+
+    if packages.is_empty() {
+        let embed = CreateEmbed::new()
+            .title(format!("No unmaintained packages start with `{prefix}`"))
+            .footer(CreateEmbedFooter::new(&channel.name))
+            .color(0x00DE_A586);
+        ctx.send(CreateReply::default().embed(embed)).await?;
+        return Ok(());
+    }
+
+    let mut header = format!("**{total}** packages without a maintainer");
+    if total > packages.len() {
+        let _ = write!(header, ", showing the first {}", packages.len());
+    }
+
+    send_listing(
+        ctx,
+        &format!("Unmaintained packages starting with `{prefix}`"),
+        &header,
+        &channel.name,
+        &packages,
+    )
+    .await
+}
+
+fn format_package(package: &ListedPackage) -> String {
+    let mut line = format!("`{}`", package.package_name);
+    if let Some(version) = &package.version {
+        line.push(' ');
+        line.push_str(version);
+    }
+    if package.broken {
+        line.push_str(" **broken**");
+    }
+    if package.insecure {
+        line.push_str(" **insecure**");
+    }
+    line
+}
+
+/// Sends `packages` as a single embed, or paginated when they don't fit on
+/// one page.
+async fn send_listing(
+    ctx: Context<'_>,
+    title: &str,
+    header: &str,
+    channel: &str,
+    packages: &[ListedPackage],
+) -> Result<()> {
+    let pages: Vec<String> = packages
+        .chunks(PAGE_SIZE)
+        .map(|chunk| {
+            let lines: Vec<String> = chunk.iter().map(format_package).collect();
+            format!("{header}\n\n{}", lines.join("\n"))
+        })
+        .collect();
+
+    if let [page] = pages.as_slice() {
+        let embed = CreateEmbed::new()
+            .title(title)
+            .description(page)
+            .footer(CreateEmbedFooter::new(channel))
+            .color(0x00DE_A586);
+        ctx.send(CreateReply::default().embed(embed)).await?;
+    } else {
+        let pages: Vec<String> = pages
+            .iter()
+            .map(|page| format!("**{title}**\n{page}"))
+            .collect();
+        let pages: Vec<&str> = pages.iter().map(String::as_str).collect();
+        poise::builtins::paginate(ctx, &pages).await?;
+    }
+
+    Ok(())
+}
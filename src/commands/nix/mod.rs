@@ -1,5 +1,12 @@
+pub mod changes;
+pub mod history;
+pub mod maintainer;
 #[allow(clippy::module_inception)]
 pub mod nix;
 pub mod nixpkg;
 pub mod nixpkgs;
+pub mod option;
+pub mod search;
 pub mod track;
+pub mod version;
+pub mod which;
@@ -1,13 +1,17 @@
+use crate::nixpkgs_db::{
+    self, Channel,
+    licenses::{self, License},
+    platforms::{self, COMMON_SYSTEMS, Platforms},
+    search,
+};
 use crate::types::Context;
 use color_eyre::eyre::{Result, eyre};
-use poise::{CreateReply, serenity_prelude::CreateEmbed};
-use rusqlite::{Connection, params};
-use std::sync::Mutex;
-
-static DB: std::sync::LazyLock<Mutex<Connection>> = std::sync::LazyLock::new(|| {
-    let db_path = crate::utils::get_data_dir().join("packages.db");
-    Mutex::new(Connection::open(db_path).expect("Failed to open database"))
-});
+use poise::{
+    CreateReply,
+    serenity_prelude::{CommandDataOption, CommandDataOptionValue, CreateEmbed, CreateEmbedFooter},
+};
+use rusqlite::{Connection, OptionalExtension, params};
+use std::fmt::Write as _;
 
 #[derive(Debug)]
 struct Package {
@@ -20,7 +24,8 @@ struct Package {
 struct PackageMeta {
     description: String,
     homepage: Option<String>,
-    license: License,
+    licenses: Vec<License>,
+    platforms: Platforms,
     maintainers: Vec<Maintainers>,
     position: String,
     broken: bool,
@@ -28,17 +33,217 @@ struct PackageMeta {
     unfree: bool,
 }
 
-#[derive(Debug)]
-struct License {
-    spdx_id: String,
-}
-
 #[derive(Debug)]
 struct Maintainers {
     name: String,
     github: String,
 }
 
+/// Looks up a package, its licenses, platforms and maintainers by exact
+/// attribute path.
+fn find_package(db: &Connection, package: &str) -> Result<Option<Package>> {
+    let mut stmt = db.prepare(
+        "SELECT pname, version, description, homepage,
+                position, broken, insecure, unfree
+         FROM packages WHERE package_name = ?1",
+    )?;
+
+    let Some(mut pkg) = stmt
+        .query_row(params![package], |row| {
+            Ok(Package {
+                // Every column but the attribute path may be NULL, which
+                // shouldn't read as the package not existing.
+                pname: row.get::<_, Option<String>>(0)?.unwrap_or_default(),
+                version: row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+                meta: PackageMeta {
+                    description: row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+                    homepage: row.get(3)?,
+                    licenses: Vec::new(),
+                    platforms: Platforms::default(),
+                    position: row
+                        .get::<_, Option<String>>(4)?
+                        .unwrap_or_else(|| "unknown".to_string()),
+                    broken: row.get::<_, Option<bool>>(5)?.unwrap_or_default(),
+                    insecure: row.get::<_, Option<bool>>(6)?.unwrap_or_default(),
+                    unfree: row.get::<_, Option<bool>>(7)?.unwrap_or_default(),
+                    maintainers: Vec::new(),
+                },
+            })
+        })
+        .optional()?
+    else {
+        return Ok(None);
+    };
+
+    pkg.meta.licenses = licenses::licenses_of(db, package)?;
+    pkg.meta.platforms = platforms::platforms_of(db, package)?.unwrap_or_default();
+
+    let mut maint_stmt =
+        db.prepare("SELECT name, github FROM maintainers WHERE package_name = ?1")?;
+
+    pkg.meta.maintainers = maint_stmt
+        .query_map(params![package], |row| {
+            Ok(Maintainers {
+                name: row
+                    .get::<_, Option<String>>(0)?
+                    .unwrap_or_else(|| "Unknown".to_string()),
+                github: row.get::<_, Option<String>>(1)?.unwrap_or_else(String::new),
+            })
+        })?
+        .filter_map(Result::ok)
+        .collect();
+
+    Ok(Some(pkg))
+}
+
+/// A license as a link to its text, with its SPDX id and any restrictions
+/// worth pointing out.
+fn format_license(license: &License) -> String {
+    let mut text = match &license.url {
+        Some(url) => format!("[{}]({url})", license.name()),
+        None => license.name().to_string(),
+    };
+    if let (Some(spdx_id), Some(_)) = (&license.spdx_id, &license.full_name) {
+        let _ = write!(text, " (`{spdx_id}`)");
+    }
+    if license.free == Some(false) {
+        text.push_str(", unfree");
+    }
+    if license.redistributable == Some(false) {
+        text.push_str(", not redistributable");
+    }
+    text
+}
+
+/// Discord rejects embed fields longer than 1024 characters; some are kept
+/// free for the note about the entries that didn't fit.
+const MAX_FIELD_CHARS: usize = 1000;
+
+/// Joins `entries` with `separator`, leaving off the ones that would take the
+/// field past Discord's limit.
+fn join_field(entries: &[String], separator: &str) -> String {
+    let mut field = String::new();
+    for (shown, entry) in entries.iter().enumerate() {
+        let separator = if shown == 0 { "" } else { separator };
+        if field.chars().count() + separator.chars().count() + entry.chars().count()
+            > MAX_FIELD_CHARS
+        {
+            let _ = write!(field, "{separator}…and {} more", entries.len() - shown);
+            break;
+        }
+        field.push_str(separator);
+        field.push_str(entry);
+    }
+    field
+}
+
+/// A line per common system, plus `system` if it isn't one of them, saying
+/// whether the package builds there.
+fn platform_matrix(platforms: &Platforms, system: Option<&str>) -> String {
+    if platforms.supported.is_empty() {
+        return "No platforms listed".to_string();
+    }
+
+    let extra = system.filter(|system| !COMMON_SYSTEMS.contains(system));
+    COMMON_SYSTEMS
+        .iter()
+        .copied()
+        .chain(extra)
+        .map(|system| {
+            let mark = if platforms.supports(system) == Some(true) {
+                "✅"
+            } else {
+                "❌"
+            };
+            format!("{mark} `{system}`")
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Discord shows at most this many autocomplete choices.
+const MAX_COMPLETIONS: usize = 25;
+
+/// The channel picked so far in the command being autocompleted, so
+/// suggestions come from it. Falls back to the default channel while the
+/// `channel` argument is empty or doesn't name one yet.
+pub fn picked_channel(ctx: Context<'_>) -> &'static Channel {
+    let poise::Context::Application(ctx) = ctx else {
+        return nixpkgs_db::default_channel();
+    };
+    resolve_channel(option_value(&ctx.interaction.data.options, "channel"))
+        .unwrap_or_else(|_| nixpkgs_db::default_channel())
+}
+
+/// The value of the string option called `name`, looking into subcommands.
+fn option_value<'a>(options: &'a [CommandDataOption], name: &str) -> Option<&'a str> {
+    options.iter().find_map(|option| match &option.value {
+        CommandDataOptionValue::String(value) if option.name == name => Some(value.as_str()),
+        CommandDataOptionValue::SubCommand(options)
+        | CommandDataOptionValue::SubCommandGroup(options) => option_value(options, name),
+        _ => None,
+    })
+}
+
+#[allow(clippy::unused_async)]
+pub async fn autocomplete_package(ctx: Context<'_>, partial: &str) -> Vec<String> {
+    let Ok(db) = picked_channel(ctx).db() else {
+        return Vec::new();
+    };
+    search::complete(&db, partial, MAX_COMPLETIONS).unwrap_or_default()
+}
+
+#[allow(clippy::unused_async)]
+async fn autocomplete_system(ctx: Context<'_>, partial: &str) -> Vec<String> {
+    let Ok(db) = picked_channel(ctx).db() else {
+        return Vec::new();
+    };
+    platforms::complete_systems(&db, partial, MAX_COMPLETIONS).unwrap_or_default()
+}
+
+#[allow(clippy::unused_async)]
+pub async fn autocomplete_channel(_ctx: Context<'_>, partial: &str) -> Vec<String> {
+    nixpkgs_db::channels()
+        .iter()
+        .filter(|channel| channel.name.contains(partial))
+        .map(|channel| channel.name.clone())
+        .collect()
+}
+
+/// The channel named by a command's `channel` argument, or the default channel
+/// if it was left out.
+pub fn resolve_channel(name: Option<&str>) -> Result<&'static Channel> {
+    match name {
+        Some(name) => nixpkgs_db::channel(name).ok_or_else(|| eyre!("Unknown channel `{name}`")),
+        None => Ok(nixpkgs_db::default_channel()),
+    }
+}
+
+/// One line per configured channel with the version of `package` it carries.
+fn channel_versions(package: &str) -> String {
+    nixpkgs_db::channels()
+        .iter()
+        .map(|channel| {
+            let version: Option<String> = channel.db().ok().and_then(|db| {
+                db.query_row(
+                    "SELECT version FROM packages WHERE package_name = ?1",
+                    params![package],
+                    |row| row.get(0),
+                )
+                .optional()
+                .ok()
+                .flatten()
+            });
+            format!(
+                "`{}`: {}",
+                channel.name,
+                version.as_deref().unwrap_or("not available")
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
 /// Get information about a Nix package
 #[poise::command(
     slash_command,
@@ -47,98 +252,126 @@ struct Maintainers {
 )]
 pub async fn nixpkg(
     ctx: Context<'_>,
-    #[description = "package name"] package: String,
+    #[description = "package name"]
+    #[autocomplete = "autocomplete_package"]
+    package: String,
+    #[description = "channel to look in (defaults to the first configured channel)"]
+    #[autocomplete = "autocomplete_channel"]
+    channel: Option<String>,
+    #[description = "system to check support for, e.g. aarch64-darwin"]
+    #[autocomplete = "autocomplete_system"]
+    system: Option<String>,
 ) -> Result<()> {
     ctx.defer().await?;
 
-    let (mut pkg, maintainers) = {
-        let db = DB.lock().unwrap();
-
-        let mut stmt = db.prepare(
-            "SELECT pname, version, description, homepage, license_spdx_id, 
-                    position, broken, insecure, unfree 
-             FROM packages WHERE package_name = ?1",
-        )?;
-
-        let pkg = stmt
-            .query_row(params![&package], |row| {
-                Ok(Package {
-                    pname: row.get(0)?,
-                    version: row.get(1)?,
-                    meta: PackageMeta {
-                        description: row.get(2)?,
-                        homepage: row.get(3)?,
-                        license: License {
-                            spdx_id: row
-                                .get::<_, Option<String>>(4)?
-                                .unwrap_or_else(|| "Unknown".to_string()),
-                        },
-                        position: row
-                            .get::<_, Option<String>>(5)?
-                            .unwrap_or_else(|| "unknown".to_string()),
-                        broken: row.get::<_, i32>(6)? != 0,
-                        insecure: row.get::<_, i32>(7)? != 0,
-                        unfree: row.get::<_, i32>(8)? != 0,
-                        maintainers: Vec::new(),
-                    },
-                })
-            })
-            .map_err(|_| eyre!("Package not found"))?;
-
-        let mut maint_stmt =
-            db.prepare("SELECT name, github FROM maintainers WHERE package_name = ?1")?;
-
-        let maintainers: Vec<Maintainers> = maint_stmt
-            .query_map(params![&package], |row| {
-                Ok(Maintainers {
-                    name: row
-                        .get::<_, Option<String>>(0)?
-                        .unwrap_or_else(|| "Unknown".to_string()),
-                    github: row.get::<_, Option<String>>(1)?.unwrap_or_else(String::new),
-                })
-            })?
-            .filter_map(Result::ok)
-            .collect();
-
-        (pkg, maintainers)
+    let channel = resolve_channel(channel.as_deref())?;
+    let system = system.as_deref().map(str::trim);
+
+    let lookup = {
+        let db = channel.db()?;
+        match find_package(&db, &package)? {
+            Some(pkg) => Ok(pkg),
+            None => Err(search::suggest(&db, &package, 5)?),
+        }
     };
 
-    pkg.meta.maintainers = maintainers;
+    let pkg = match lookup {
+        Ok(pkg) => pkg,
+        Err(suggestions) => {
+            let mut embed = CreateEmbed::new()
+                .title(format!("Package `{package}` not found"))
+                .footer(CreateEmbedFooter::new(&channel.name))
+                .color(0x00DE_A586);
+            if !suggestions.is_empty() {
+                embed = embed.description(format!(
+                    "Did you mean:\n{}",
+                    suggestions
+                        .iter()
+                        .map(|name| format!("- `{name}`"))
+                        .collect::<Vec<String>>()
+                        .join("\n")
+                ));
+            }
+            if nixpkgs_db::channels().len() > 1 {
+                embed = embed.field("versions", channel_versions(&package), false);
+            }
+            ctx.send(CreateReply::default().embed(embed)).await?;
+            return Ok(());
+        }
+    };
 
     let file = pkg.meta.position.split(':').next().unwrap_or("unknown");
 
-    let embed = CreateEmbed::new()
+    let mut description = pkg.meta.description;
+    if let Some(system) = system
+        && pkg.meta.platforms.supports(system) == Some(false)
+    {
+        description = format!("⚠️ **Not supported on `{system}`**\n\n{description}");
+    }
+
+    let mut embed = CreateEmbed::new()
         .title(format!("{} {}", pkg.pname, pkg.version))
         .url(format!(
             "https://github.com/nixos/nixpkgs/blob/master/{file}"
         ))
-        .description(pkg.meta.description)
+        .description(description)
         .field(
             "Homepage",
             pkg.meta.homepage.unwrap_or_else(|| "N/A".to_string()),
             false,
         )
-        .field("license", pkg.meta.license.spdx_id, true)
+        .field(
+            if pkg.meta.licenses.len() > 1 {
+                "licenses"
+            } else {
+                "license"
+            },
+            if pkg.meta.licenses.is_empty() {
+                "Unknown".to_string()
+            } else {
+                join_field(
+                    &pkg.meta
+                        .licenses
+                        .iter()
+                        .map(format_license)
+                        .collect::<Vec<String>>(),
+                    "\n",
+                )
+            },
+            false,
+        )
         .field("insecure", pkg.meta.insecure.to_string(), true)
         .field("unfree", pkg.meta.unfree.to_string(), true)
         .field("broken", pkg.meta.broken.to_string(), true)
+        .field(
+            "platforms",
+            platform_matrix(&pkg.meta.platforms, system),
+            false,
+        )
         .field(
             "maintainers",
             if pkg.meta.maintainers.is_empty() {
                 "None".to_string()
             } else {
-                pkg.meta
-                    .maintainers
-                    .iter()
-                    .filter(|m| !m.github.is_empty())
-                    .map(|m| format!("[{}](https://github.com/{})", m.name, m.github))
-                    .collect::<Vec<String>>()
-                    .join(", ")
+                join_field(
+                    &pkg.meta
+                        .maintainers
+                        .iter()
+                        .filter(|m| !m.github.is_empty())
+                        .map(|m| format!("[{}](https://github.com/{})", m.name, m.github))
+                        .collect::<Vec<String>>(),
+                    ", ",
+                )
             },
             false,
         )
+        .footer(CreateEmbedFooter::new(&channel.name))
         .color(0x00DE_A586);
 
+    if nixpkgs_db::channels().len() > 1 {
+        embed = embed.field("versions", channel_versions(&package), false);
+    }
+
     ctx.send(CreateReply::default().embed(embed)).await?;
 
     Ok(())
@@ -1,10 +1,11 @@
 use color_eyre::eyre::Result;
-use nixpkgs_track_lib::{branch_contains_commit, fetch_nixpkgs_pull_request};
 use poise::{CreateReply, serenity_prelude::CreateEmbed};
 use regex::Regex;
-use reqwest::Client;
 use std::fmt::Write as _;
+use tracing::warn;
 
+use crate::github::GitHub;
+use crate::nixpkgs_db::versions::compare_versions;
 use crate::types::Context;
 
 pub static ROLLING_BRANCHES: [&str; 6] = [
@@ -25,7 +26,7 @@ pub static STABLE_BRANCHES_TEMPLATE: [&str; 6] = [
 ];
 
 /// The stable release version (e.g. `24.11`) targeted by `base_ref`, if any.
-fn stable_version(base_ref: &str) -> Option<String> {
+pub fn stable_version(base_ref: &str) -> Option<String> {
     if ROLLING_BRANCHES.contains(&base_ref) {
         return None;
     }
@@ -99,20 +100,13 @@ pub fn resolve_target_branch(base_ref: &str, choice: Option<TargetBranch>) -> St
 }
 
 pub async fn branch_statuses(
-    client: Client,
-    github_token: &str,
+    github: &GitHub,
     branches: &[String],
     commit_sha: &str,
 ) -> Result<Vec<(String, bool)>> {
     let mut statuses = Vec::with_capacity(branches.len());
     for branch in branches {
-        let contains = branch_contains_commit(
-            crate::types::W(client.clone()),
-            branch,
-            commit_sha,
-            Some(github_token),
-        )
-        .await?;
+        let contains = github.contains_commit(branch, commit_sha).await?;
         statuses.push((branch.clone(), contains));
     }
     Ok(statuses)
@@ -131,6 +125,84 @@ pub fn format_branch_statuses(statuses: &[(String, bool)]) -> String {
     description
 }
 
+/// The branch at the same stage in `to_base_ref`'s series as `branch` is in
+/// `from_base_ref`'s, e.g. `nixos-24.11` for `nixos-unstable` when following
+/// a master PR to its `release-24.11` backport.
+pub fn equivalent_branch(branch: &str, from_base_ref: &str, to_base_ref: &str) -> Option<String> {
+    let index = tracked_branches_for(from_base_ref)
+        .iter()
+        .position(|b| b == branch)?;
+    tracked_branches_for(to_base_ref).into_iter().nth(index)
+}
+
+/// A merged backport of a PR to a stable release, and the branches it has
+/// reached if they could be checked.
+pub struct Backport {
+    pub pull_request: crate::github::PullRequest,
+    pub statuses: Option<Vec<(String, bool)>>,
+}
+
+/// How many backports, to the newest releases, are shown with their
+/// branches. Each one costs a request per branch.
+const MAX_SHOWN_BACKPORTS: usize = 2;
+
+/// The merged backports of PR `number` to the newest stable releases, newest
+/// release first.
+/// Backports are an extra on top of the PR itself, so failing to look them
+/// up is logged rather than treated as an error.
+pub async fn find_backports(github: &GitHub, number: u64) -> Vec<Backport> {
+    let mut candidates = match github.backports(number).await {
+        Ok(candidates) => candidates,
+        Err(err) => {
+            warn!("failed to look for backports of #{number}: {err}");
+            return Vec::new();
+        }
+    };
+    candidates.retain(|pull_request| stable_version(&pull_request.base.name).is_some());
+    candidates.sort_by(|a, b| {
+        compare_versions(
+            &stable_version(&b.base.name).unwrap_or_default(),
+            &stable_version(&a.base.name).unwrap_or_default(),
+        )
+    });
+    candidates.truncate(MAX_SHOWN_BACKPORTS);
+
+    let mut backports = Vec::new();
+    for pull_request in candidates {
+        let statuses = match (&pull_request.merge_commit_sha, pull_request.merged) {
+            (Some(commit_sha), true) => {
+                let branches = tracked_branches_for(&pull_request.base.name);
+                branch_statuses(github, &branches, commit_sha).await.ok()
+            }
+            _ => None,
+        };
+        backports.push(Backport {
+            pull_request,
+            statuses,
+        });
+    }
+    backports
+}
+
+/// Embed fields showing how far each backport has got.
+pub fn backport_fields(backports: &[Backport]) -> Vec<(String, String, bool)> {
+    backports
+        .iter()
+        .map(|backport| {
+            let pr = &backport.pull_request;
+            let value = match &backport.statuses {
+                Some(statuses) => format_branch_statuses(statuses),
+                None => "Couldn't check its branches".to_string(),
+            };
+            (
+                format!("Backport #{} to {}", pr.number, pr.base.name),
+                format!("[{}]({})\n{value}", pr.title, pr.html_url),
+                false,
+            )
+        })
+        .collect()
+}
+
 /// Track nixpkgs PRs
 #[poise::command(
     slash_command,
@@ -145,12 +217,8 @@ pub async fn nixpkgs(
 ) -> Result<()> {
     ctx.defer().await?;
 
-    let pull_request = fetch_nixpkgs_pull_request(
-        crate::types::W(ctx.data().client.clone()),
-        pr,
-        Some(&ctx.data().github_token),
-    )
-    .await?;
+    let github = GitHub::interactive(ctx.data().client.clone(), ctx.data().github_token.clone());
+    let pull_request = github.require_pull_request(pr).await?;
 
     let Some(commit_sha) = pull_request.merge_commit_sha else {
         ctx.say("This pull request is very old. I can't track it!")
@@ -158,22 +226,41 @@ pub async fn nixpkgs(
         return Ok(());
     };
 
-    let branches = tracked_branches_for(&pull_request.base.r#ref);
-    let statuses = branch_statuses(
-        ctx.data().client.clone(),
-        &ctx.data().github_token,
-        &branches,
-        &commit_sha,
-    )
-    .await?;
+    let branches = tracked_branches_for(&pull_request.base.name);
+    let statuses = branch_statuses(&github, &branches, &commit_sha).await?;
+
+    let backports = if stable_version(&pull_request.base.name).is_none() {
+        find_backports(&github, pr).await
+    } else {
+        Vec::new()
+    };
 
     let embed = CreateReply::default().embed(
         CreateEmbed::new()
             .title(format!("{} - #{}", pull_request.title, pull_request.number))
             .url(pull_request.html_url)
-            .description(format_branch_statuses(&statuses)),
+            .description(format_branch_statuses(&statuses))
+            .fields(backport_fields(&backports)),
     );
 
     ctx.send(embed).await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_branches_to_the_backport_release() {
+        assert_eq!(
+            equivalent_branch("nixos-unstable", "master", "release-24.11").as_deref(),
+            Some("nixos-24.11")
+        );
+        assert_eq!(
+            equivalent_branch("master", "staging", "staging-24.11").as_deref(),
+            Some("release-24.11")
+        );
+        assert_eq!(equivalent_branch("nope", "master", "release-24.11"), None);
+    }
+}
@@ -0,0 +1,161 @@
+use crate::commands::nix::nixpkg::{autocomplete_channel, resolve_channel};
+use crate::nixpkgs_db::{self, Channel, options};
+use crate::types::Context;
+use color_eyre::eyre::{Result, eyre};
+use poise::{
+    CreateReply,
+    serenity_prelude::{CreateEmbed, CreateEmbedFooter},
+};
+
+/// Discord shows at most 25 autocomplete choices.
+const MAX_COMPLETIONS: usize = 25;
+/// Longest description shown before it is cut short.
+const MAX_DESCRIPTION_CHARS: usize = 1500;
+/// Longest default or example value shown before it is cut short.
+const MAX_VALUE_CHARS: usize = 500;
+
+/// The first configured channel that has NixOS options; channels like
+/// `nixpkgs-unstable` only carry packages.
+fn default_options_channel() -> Option<&'static Channel> {
+    nixpkgs_db::channels()
+        .iter()
+        .find(|channel| channel.db().is_ok_and(|mut db| db.has_options()))
+}
+
+/// The channel named by the `channel` argument, or the default options channel.
+fn resolve_options_channel(name: Option<&str>) -> Result<&'static Channel> {
+    match name {
+        Some(_) => resolve_channel(name),
+        None => default_options_channel().ok_or_else(|| {
+            eyre!("None of the configured channels provide NixOS options, add a `nixos-*` channel")
+        }),
+    }
+}
+
+#[allow(clippy::unused_async)]
+async fn autocomplete_option(_ctx: Context<'_>, partial: &str) -> Vec<String> {
+    let Some(channel) = default_options_channel() else {
+        return Vec::new();
+    };
+    let Ok(db) = channel.db() else {
+        return Vec::new();
+    };
+    options::complete(&db, partial, MAX_COMPLETIONS).unwrap_or_default()
+}
+
+fn truncate(text: &str, max_chars: usize) -> String {
+    let text = text.trim();
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+
+    let truncated: String = text.chars().take(max_chars).collect();
+    format!("{}...", truncated.trim_end())
+}
+
+/// Get information about a NixOS option
+#[poise::command(
+    slash_command,
+    rename = "nixos-option",
+    install_context = "Guild|User",
+    interaction_context = "Guild|BotDm|PrivateChannel"
+)]
+pub async fn nixos_option(
+    ctx: Context<'_>,
+    #[description = "option name, e.g. services.nginx.enable"]
+    #[autocomplete = "autocomplete_option"]
+    name: String,
+    #[description = "channel to look in (defaults to the first NixOS channel)"]
+    #[autocomplete = "autocomplete_channel"]
+    channel: Option<String>,
+) -> Result<()> {
+    ctx.defer().await?;
+
+    let channel = resolve_options_channel(channel.as_deref())?;
+    let lookup = {
+        let db = channel.db()?;
+        match options::find(&db, &name)? {
+            Some(option) => Ok(option),
+            None => Err(options::complete(&db, &name, 5)?),
+        }
+    };
+
+    let option = match lookup {
+        Ok(option) => option,
+        Err(suggestions) => {
+            let mut embed = CreateEmbed::new()
+                .title(format!("Option `{}` not found", name.trim()))
+                .footer(CreateEmbedFooter::new(&channel.name))
+                .color(0x00DE_A586);
+            if !suggestions.is_empty() {
+                embed = embed.description(format!(
+                    "Did you mean:\n{}",
+                    suggestions
+                        .iter()
+                        .map(|name| format!("- `{name}`"))
+                        .collect::<Vec<String>>()
+                        .join("\n")
+                ));
+            }
+            ctx.send(CreateReply::default().embed(embed)).await?;
+            return Ok(());
+        }
+    };
+
+    let mut embed = CreateEmbed::new()
+        .title(&option.name)
+        .description(option.description.as_deref().map_or_else(
+            || "No description".to_string(),
+            |d| truncate(d, MAX_DESCRIPTION_CHARS),
+        ))
+        .field(
+            "type",
+            option.option_type.as_deref().unwrap_or("unknown"),
+            false,
+        )
+        .footer(CreateEmbedFooter::new(&channel.name))
+        .color(0x00DE_A586);
+
+    if let Some(declaration) = option.declarations.first() {
+        embed = embed.url(options::declaration_url(channel, declaration));
+    }
+    if let Some(default) = &option.default {
+        embed = embed.field(
+            "default",
+            format!("```nix\n{}\n```", truncate(default, MAX_VALUE_CHARS)),
+            false,
+        );
+    }
+    if let Some(example) = &option.example {
+        embed = embed.field(
+            "example",
+            format!("```nix\n{}\n```", truncate(example, MAX_VALUE_CHARS)),
+            false,
+        );
+    }
+    if option.read_only {
+        embed = embed.field("read-only", "true", true);
+    }
+    if !option.declarations.is_empty() {
+        embed = embed.field(
+            "declared in",
+            option
+                .declarations
+                .iter()
+                .map(|declaration| {
+                    format!(
+                        "[{}]({})",
+                        declaration.trim_start_matches("https://"),
+                        options::declaration_url(channel, declaration)
+                    )
+                })
+                .collect::<Vec<String>>()
+                .join("\n"),
+            false,
+        );
+    }
+
+    ctx.send(CreateReply::default().embed(embed)).await?;
+
+    Ok(())
+}
@@ -0,0 +1,115 @@
+use crate::commands::nix::nixpkg::{autocomplete_channel, picked_channel, resolve_channel};
+use crate::nixpkgs_db::{
+    self, licenses,
+    search::{self, LicenseFilter},
+};
+use crate::types::Context;
+use color_eyre::eyre::Result;
+use poise::{
+    CreateReply,
+    serenity_prelude::{CreateEmbed, CreateEmbedFooter},
+};
+use std::fmt::Write as _;
+
+/// Results shown per page.
+const PAGE_SIZE: usize = 10;
+/// Descriptions longer than this are cut short so a full page fits in an embed.
+const MAX_DESCRIPTION_CHARS: usize = 150;
+/// Discord shows at most 25 autocomplete choices.
+const MAX_COMPLETIONS: usize = 25;
+
+#[allow(clippy::unused_async)]
+async fn autocomplete_license(ctx: Context<'_>, partial: &str) -> Vec<String> {
+    let Ok(db) = picked_channel(ctx).db() else {
+        return Vec::new();
+    };
+    licenses::complete_spdx_ids(&db, partial, MAX_COMPLETIONS).unwrap_or_default()
+}
+
+/// Search nixpkgs packages by name and description
+#[poise::command(
+    slash_command,
+    rename = "nixpkgs-search",
+    install_context = "Guild|User",
+    interaction_context = "Guild|BotDm|PrivateChannel"
+)]
+pub async fn nixpkgs_search(
+    ctx: Context<'_>,
+    #[description = "what to search for"] query: String,
+    #[description = "channel to search (defaults to the first configured channel)"]
+    #[autocomplete = "autocomplete_channel"]
+    channel: Option<String>,
+    #[description = "only show packages whose licenses are all free"] free_only: Option<bool>,
+    #[description = "only show packages under this license (SPDX id)"]
+    #[autocomplete = "autocomplete_license"]
+    license: Option<String>,
+) -> Result<()> {
+    ctx.defer().await?;
+
+    let channel = resolve_channel(channel.as_deref())?;
+    let filter = LicenseFilter {
+        free_only: free_only.unwrap_or(false),
+        spdx_id: license,
+    };
+    let hits = {
+        let db = channel.db()?;
+        search::search(&db, &query, &filter)?
+    };
+
+    if hits.is_empty() {
+        let embed = CreateEmbed::new()
+            .title(format!("No packages matching `{query}`"))
+            .footer(CreateEmbedFooter::new(&channel.name))
+            .color(0x00DE_A586);
+        ctx.send(CreateReply::default().embed(embed)).await?;
+        return Ok(());
+    }
+
+    let pages: Vec<String> = hits
+        .chunks(PAGE_SIZE)
+        .enumerate()
+        .map(|(page, chunk)| {
+            let mut text = String::new();
+            for (i, hit) in chunk.iter().enumerate() {
+                let _ = write!(
+                    text,
+                    "{}. **{}**",
+                    page * PAGE_SIZE + i + 1,
+                    hit.package_name
+                );
+                if let Some(version) = &hit.version {
+                    let _ = write!(text, " `{version}`");
+                }
+                text.push('\n');
+                if let Some(description) = &hit.description {
+                    let _ = writeln!(text, "{}", truncate(description));
+                }
+            }
+            text
+        })
+        .collect();
+
+    if let [page] = pages.as_slice() {
+        let embed = CreateEmbed::new()
+            .title(format!("Packages matching `{query}`"))
+            .description(page)
+            .footer(CreateEmbedFooter::new(&channel.name))
+            .color(0x00DE_A586);
+        ctx.send(CreateReply::default().embed(embed)).await?;
+    } else {
+        let pages: Vec<&str> = pages.iter().map(String::as_str).collect();
+        poise::builtins::paginate(ctx, &pages).await?;
+    }
+
+    Ok(())
+}
+
+fn truncate(description: &str) -> String {
+    let description = description.trim();
+    if description.chars().count() <= MAX_DESCRIPTION_CHARS {
+        return description.to_string();
+    }
+
+    let truncated: String = description.chars().take(MAX_DESCRIPTION_CHARS).collect();
+    format!("{}...", truncated.trim_end())
+}
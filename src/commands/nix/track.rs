@@ -1,43 +1,59 @@
-use color_eyre::eyre::Result;
-use nixpkgs_track_lib::fetch_nixpkgs_pull_request;
+use color_eyre::eyre::{Result, eyre};
 use poise::CreateReply;
-use poise::serenity_prelude::{Context as SerenityContext, CreateEmbed, CreateMessage, UserId};
+use poise::serenity_prelude::{
+    AutocompleteChoice, ChannelId, Context as SerenityContext, CreateEmbed, CreateMessage,
+    EditMessage, MessageId, UserId,
+};
 use reqwest::Client;
+use rusqlite::OptionalExtension;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write as _;
+use tracing::error;
 
 use crate::commands::nix::nixpkgs::{
-    TargetBranch, branch_statuses, format_branch_statuses, resolve_target_branch,
-    tracked_branches_for,
+    TargetBranch, backport_fields, branch_statuses, equivalent_branch, find_backports,
+    format_branch_statuses, resolve_target_branch, stable_version, tracked_branches_for,
 };
+use crate::github::{BaseRef, Conditional, GitHub, PullRequest};
+use crate::nixpkgs_db::versions::compare_versions;
 use crate::types::Context;
 use crate::utils::DB;
 
 const POLL_INTERVAL_SECS: u64 = 600;
-const PER_PR_DELAY_SECS: u64 = 2;
 const TRACKING_TTL_SECS: i64 = 60 * 60 * 24 * 30;
+/// Room left in an embed description (4096 chars) for the PRs `list` shows,
+/// keeping some for the note about the ones that didn't fit.
+const MAX_LIST_CHARS: usize = 4000;
 
-/// Track a nixpkgs PR and get a DM when it lands in the chosen branch
+/// Track nixpkgs PRs and get a DM when they land
+#[allow(clippy::unused_async)]
 #[poise::command(
     slash_command,
     rename = "nixpkgs-track",
     install_context = "Guild|User",
-    interaction_context = "Guild|BotDm|PrivateChannel"
+    interaction_context = "Guild|BotDm|PrivateChannel",
+    subcommands("add", "list", "untrack", "retarget", "watch", "unwatch")
 )]
-pub async fn nixpkgs_track(
+pub async fn nixpkgs_track(_: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+/// Track a nixpkgs PR and get a DM when it lands in the chosen branch
+#[poise::command(slash_command)]
+pub async fn add(
     ctx: Context<'_>,
     #[description = "pr"]
     #[min = 0]
     pr: u64,
     #[description = "branch to wait for (defaults to nixpkgs-unstable or the release branch)"]
     branch: Option<TargetBranch>,
+    #[description = "wait for the PR's backport to the latest stable release instead"]
+    backport: Option<bool>,
 ) -> Result<()> {
     ctx.defer().await?;
 
-    let pull_request = fetch_nixpkgs_pull_request(
-        crate::types::W(ctx.data().client.clone()),
-        pr,
-        Some(&ctx.data().github_token),
-    )
-    .await?;
+    let github = GitHub::interactive(ctx.data().client.clone(), ctx.data().github_token.clone());
+    let pull_request = github.require_pull_request(pr).await?;
 
     let title = format!("{} - #{}", pull_request.title, pull_request.number);
     let url = pull_request.html_url.clone();
@@ -53,20 +69,33 @@ pub async fn nixpkgs_track(
         return Ok(());
     };
 
-    let target_branch = resolve_target_branch(&pull_request.base.r#ref, branch);
-    let branches = tracked_branches_for(&pull_request.base.r#ref);
-    let statuses = branch_statuses(
-        ctx.data().client.clone(),
-        &ctx.data().github_token,
-        &branches,
-        &commit_sha,
-    )
-    .await?;
+    let base_ref = &pull_request.base.name;
+    let is_stable = stable_version(base_ref).is_some();
+    // Backports land in the release branch first, so that is what to wait
+    // for unless told otherwise.
+    let follow_backport = backport.unwrap_or(false) && !is_stable;
+    let target_branch = if follow_backport {
+        resolve_target_branch(
+            base_ref,
+            Some(branch.unwrap_or(TargetBranch::MasterOrRelease)),
+        )
+    } else {
+        resolve_target_branch(base_ref, branch)
+    };
 
-    let reached_target = statuses
-        .iter()
-        .find(|(name, _)| *name == target_branch)
-        .is_some_and(|(_, contains)| *contains);
+    let branches = tracked_branches_for(base_ref);
+    let statuses = branch_statuses(&github, &branches, &commit_sha).await?;
+    let backports = if is_stable {
+        Vec::new()
+    } else {
+        find_backports(&github, pr).await
+    };
+
+    let reached_target = !follow_backport
+        && statuses
+            .iter()
+            .find(|(name, _)| *name == target_branch)
+            .is_some_and(|(_, contains)| *contains);
     let mut description = format_branch_statuses(&statuses);
 
     if reached_target {
@@ -81,32 +110,344 @@ pub async fn nixpkgs_track(
         tokio::task::block_in_place(|| {
             let conn = DB.lock().unwrap();
             conn.execute(
-                "INSERT OR REPLACE INTO tracked_prs (pr_number, user_id, channel_id, created_at, target_branch) VALUES (?, ?, ?, ?, ?)",
+                "INSERT OR REPLACE INTO tracked_prs (pr_number, user_id, channel_id, created_at, target_branch, follow_backport) VALUES (?, ?, ?, ?, ?, ?)",
                 rusqlite::params![
                     pr.cast_signed(),
                     user_id.cast_signed(),
                     channel_id.cast_signed(),
                     now,
                     target_branch,
+                    follow_backport,
                 ],
             )
         })?;
 
-        description.push_str(&format!(
-            "\nI'll DM you when this PR reaches `{target_branch}`."
-        ));
+        if !follow_backport {
+            description.push_str(&format!(
+                "\nI'll DM you when this PR reaches `{target_branch}`."
+            ));
+        } else if let Some(latest) = backports.first() {
+            let equivalent =
+                equivalent_branch(&target_branch, base_ref, &latest.pull_request.base.name)
+                    .unwrap_or_else(|| latest.pull_request.base.name.clone());
+            description.push_str(&format!(
+                "\nI'll DM you when backport #{} reaches `{equivalent}`.",
+                latest.pull_request.number
+            ));
+        } else {
+            description.push_str(&format!(
+                "\nThere's no merged backport yet. I'll keep looking, and DM you once it reaches the release's equivalent of `{target_branch}`."
+            ));
+        }
     }
 
     let embed = CreateReply::default().embed(
         CreateEmbed::new()
             .title(title)
             .url(url)
-            .description(description),
+            .description(description)
+            .fields(backport_fields(&backports)),
     );
     ctx.send(embed).await?;
     Ok(())
 }
 
+/// Autocompletes the PR numbers the invoking user is tracking.
+#[allow(clippy::unused_async)]
+async fn autocomplete_tracked_pr(ctx: Context<'_>, partial: &str) -> Vec<AutocompleteChoice> {
+    let partial = partial.trim().trim_start_matches('#');
+    load_tracked_rows_of(ctx.author().id.get())
+        .into_iter()
+        .filter(|row| row.pr_number.to_string().starts_with(partial))
+        .take(25)
+        .map(|row| {
+            AutocompleteChoice::new(
+                format!("#{} → {}", row.pr_number, row.target_branch),
+                row.pr_number,
+            )
+        })
+        .collect()
+}
+
+/// List the nixpkgs PRs you are tracking
+#[poise::command(slash_command)]
+pub async fn list(ctx: Context<'_>) -> Result<()> {
+    let rows = load_tracked_rows_of(ctx.author().id.get());
+
+    let description = if rows.is_empty() {
+        "You aren't tracking any PRs. Use `/nixpkgs-track add` to start.".to_string()
+    } else {
+        let mut description = String::new();
+        for (shown, row) in rows.iter().enumerate() {
+            let line = format!(
+                "[#{pr}](https://github.com/NixOS/nixpkgs/pull/{pr}) → {}`{}`, since <t:{}:R>, expires <t:{}:R>\n",
+                if row.follow_backport {
+                    "backport, "
+                } else {
+                    ""
+                },
+                row.target_branch,
+                row.created_at,
+                row.created_at + TRACKING_TTL_SECS,
+                pr = row.pr_number,
+            );
+            if description.chars().count() + line.chars().count() > MAX_LIST_CHARS {
+                let _ = write!(description, "…and {} more", rows.len() - shown);
+                break;
+            }
+            description.push_str(&line);
+        }
+        description
+    };
+
+    ctx.send(
+        CreateReply::default()
+            .embed(
+                CreateEmbed::new()
+                    .title("Tracked PRs")
+                    .description(description)
+                    .color(0x00DE_A586),
+            )
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Stop tracking a nixpkgs PR
+#[poise::command(slash_command)]
+pub async fn untrack(
+    ctx: Context<'_>,
+    #[description = "pr"]
+    #[autocomplete = "autocomplete_tracked_pr"]
+    pr: u64,
+) -> Result<()> {
+    let user_id = ctx.author().id.get();
+    let deleted = tokio::task::block_in_place(|| {
+        let conn = DB.lock().unwrap();
+        conn.execute(
+            "DELETE FROM tracked_prs WHERE pr_number = ? AND user_id = ?",
+            rusqlite::params![pr.cast_signed(), user_id.cast_signed()],
+        )
+    })?;
+
+    let content = if deleted > 0 {
+        format!("✅ No longer tracking #{pr}.")
+    } else {
+        format!("You aren't tracking #{pr}.")
+    };
+    ctx.send(CreateReply::default().content(content).ephemeral(true))
+        .await?;
+    Ok(())
+}
+
+/// Change the branch a tracked nixpkgs PR is waited for in
+#[poise::command(slash_command)]
+pub async fn retarget(
+    ctx: Context<'_>,
+    #[description = "pr"]
+    #[autocomplete = "autocomplete_tracked_pr"]
+    pr: u64,
+    #[description = "branch to wait for instead"] branch: TargetBranch,
+) -> Result<()> {
+    let user_id = ctx.author().id.get();
+    let Some(tracked) = load_tracked_rows_of(user_id)
+        .into_iter()
+        .find(|row| row.pr_number == pr)
+    else {
+        ctx.send(
+            CreateReply::default()
+                .content(format!(
+                    "You aren't tracking #{pr}. Use `/nixpkgs-track add` to start."
+                ))
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    };
+
+    ctx.defer_ephemeral().await?;
+
+    let github = GitHub::interactive(ctx.data().client.clone(), ctx.data().github_token.clone());
+    let pull_request = github.require_pull_request(pr).await?;
+    let target_branch = resolve_target_branch(&pull_request.base.name, Some(branch));
+
+    // A PR followed to its backport only has to reach the branch's
+    // equivalent in the release, which is checked once the backport is found.
+    let reached_target = match &pull_request.merge_commit_sha {
+        _ if tracked.follow_backport => false,
+        Some(commit_sha) => {
+            branch_statuses(&github, std::slice::from_ref(&target_branch), commit_sha)
+                .await?
+                .iter()
+                .any(|(_, contains)| *contains)
+        }
+        None => false,
+    };
+
+    let content = if reached_target {
+        delete_tracked(pr, user_id);
+        format!("#{pr} is already in `{target_branch}`, so I stopped tracking it.")
+    } else {
+        tokio::task::block_in_place(|| {
+            let conn = DB.lock().unwrap();
+            conn.execute(
+                "UPDATE tracked_prs SET target_branch = ? WHERE pr_number = ? AND user_id = ?",
+                rusqlite::params![target_branch, pr.cast_signed(), user_id.cast_signed()],
+            )
+        })?;
+        if tracked.follow_backport {
+            format!(
+                "✅ I'll DM you when the backport of #{pr} reaches the release's equivalent of `{target_branch}` instead."
+            )
+        } else {
+            format!("✅ I'll DM you when #{pr} reaches `{target_branch}` instead.")
+        }
+    };
+
+    ctx.send(CreateReply::default().content(content).ephemeral(true))
+        .await?;
+    Ok(())
+}
+
+/// Autocompletes the PR numbers watched in the invoking channel.
+#[allow(clippy::unused_async)]
+async fn autocomplete_watched_pr(ctx: Context<'_>, partial: &str) -> Vec<AutocompleteChoice> {
+    let partial = partial.trim().trim_start_matches('#');
+    let channel_id = ctx.channel_id().get();
+    load_watched_rows()
+        .into_iter()
+        .filter(|row| row.channel_id == channel_id)
+        .filter(|row| row.pr_number.to_string().starts_with(partial))
+        .take(25)
+        .map(|row| AutocompleteChoice::new(format!("#{}", row.pr_number), row.pr_number))
+        .collect()
+}
+
+/// The status message of a watched PR: every branch it goes through, ticked
+/// off as it reaches them.
+fn watch_status_embed(title: String, url: &str, statuses: &[(String, bool)]) -> CreateEmbed {
+    let mut description = format_branch_statuses(statuses);
+    if statuses.iter().all(|(_, contains)| *contains) {
+        description.push_str("\nReached every branch.");
+    }
+
+    CreateEmbed::new()
+        .title(title)
+        .url(url)
+        .description(description)
+        .color(0x00DE_A586)
+}
+
+/// The branches a PR has reached, in the form stored in `watched_prs.reached`.
+fn reached_branches(statuses: &[(String, bool)]) -> String {
+    statuses
+        .iter()
+        .filter(|(_, contains)| *contains)
+        .map(|(branch, _)| branch.as_str())
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+/// Watch a nixpkgs PR for this channel, with a status message updated as it lands in each branch
+#[poise::command(slash_command, guild_only, required_permissions = "MANAGE_MESSAGES")]
+pub async fn watch(
+    ctx: Context<'_>,
+    #[description = "pr"]
+    #[min = 0]
+    pr: u64,
+) -> Result<()> {
+    ctx.defer_ephemeral().await?;
+
+    // The command can be run from a user install, but the status message is
+    // posted and edited by the bot itself, which has to be in the server.
+    if ctx.guild().is_none() {
+        ctx.say("I need to be added to this server to keep a status message here.")
+            .await?;
+        return Ok(());
+    }
+
+    let github = GitHub::interactive(ctx.data().client.clone(), ctx.data().github_token.clone());
+    let pull_request = github.require_pull_request(pr).await?;
+
+    let Some(commit_sha) = pull_request.merge_commit_sha.clone() else {
+        ctx.say("This pull request is very old. I can't track it!")
+            .await?;
+        return Ok(());
+    };
+
+    let branches = tracked_branches_for(&pull_request.base.name);
+    let statuses = branch_statuses(&github, &branches, &commit_sha).await?;
+
+    if statuses.iter().all(|(_, contains)| *contains) {
+        ctx.say(format!(
+            "#{pr} has already reached every branch, nothing to watch."
+        ))
+        .await?;
+        return Ok(());
+    }
+
+    let channel_id = ctx.channel_id();
+    let message = channel_id
+        .send_message(
+            ctx.http(),
+            CreateMessage::new().embed(watch_status_embed(
+                format!("{} - #{}", pull_request.title, pull_request.number),
+                &pull_request.html_url,
+                &statuses,
+            )),
+        )
+        .await?;
+    let now = chrono::Utc::now().timestamp();
+
+    tokio::task::block_in_place(|| {
+        let conn = DB.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO watched_prs (pr_number, channel_id, message_id, reached, created_at) VALUES (?, ?, ?, ?, ?)",
+            rusqlite::params![
+                pr.cast_signed(),
+                channel_id.get().cast_signed(),
+                message.id.get().cast_signed(),
+                reached_branches(&statuses),
+                now,
+            ],
+        )
+    })?;
+
+    ctx.say(format!(
+        "✅ Watching #{pr} in this channel, the status message above will be kept up to date."
+    ))
+    .await?;
+    Ok(())
+}
+
+/// Stop watching a nixpkgs PR in this channel
+#[poise::command(slash_command, guild_only, required_permissions = "MANAGE_MESSAGES")]
+pub async fn unwatch(
+    ctx: Context<'_>,
+    #[description = "pr"]
+    #[autocomplete = "autocomplete_watched_pr"]
+    pr: u64,
+) -> Result<()> {
+    let channel_id = ctx.channel_id().get();
+    let deleted = tokio::task::block_in_place(|| {
+        let conn = DB.lock().unwrap();
+        conn.execute(
+            "DELETE FROM watched_prs WHERE pr_number = ? AND channel_id = ?",
+            rusqlite::params![pr.cast_signed(), channel_id.cast_signed()],
+        )
+    })?;
+
+    let content = if deleted > 0 {
+        format!("✅ No longer watching #{pr} in this channel.")
+    } else {
+        format!("#{pr} isn't being watched in this channel.")
+    };
+    ctx.send(CreateReply::default().content(content).ephemeral(true))
+        .await?;
+    Ok(())
+}
+
 #[derive(Debug, Clone)]
 struct TrackedRow {
     pr_number: u64,
@@ -114,23 +455,85 @@ struct TrackedRow {
     channel_id: u64,
     created_at: i64,
     target_branch: String,
+    /// Whether to switch to the PR's stable backport once there is one, and
+    /// wait for it to reach the equivalent of `target_branch`.
+    follow_backport: bool,
+}
+
+fn tracked_row(row: &rusqlite::Row) -> rusqlite::Result<TrackedRow> {
+    Ok(TrackedRow {
+        pr_number: row.get::<_, i64>(0)?.cast_unsigned(),
+        user_id: row.get::<_, i64>(1)?.cast_unsigned(),
+        channel_id: row.get::<_, i64>(2)?.cast_unsigned(),
+        created_at: row.get::<_, i64>(3)?,
+        target_branch: row.get::<_, String>(4)?,
+        follow_backport: row.get::<_, bool>(5)?,
+    })
 }
 
 fn load_tracked_rows() -> Vec<TrackedRow> {
     tokio::task::block_in_place(|| {
         let conn = DB.lock().unwrap();
         let Ok(mut stmt) = conn.prepare(
-            "SELECT pr_number, user_id, channel_id, created_at, target_branch FROM tracked_prs",
+            "SELECT pr_number, user_id, channel_id, created_at, target_branch, follow_backport
+             FROM tracked_prs",
+        ) else {
+            return Vec::new();
+        };
+        stmt.query_map([], tracked_row)
+            .map(|iter| iter.filter_map(std::result::Result::ok).collect::<Vec<_>>())
+            .unwrap_or_default()
+    })
+}
+
+/// The PRs `user_id` is tracking, oldest first.
+fn load_tracked_rows_of(user_id: u64) -> Vec<TrackedRow> {
+    tokio::task::block_in_place(|| {
+        let conn = DB.lock().unwrap();
+        let Ok(mut stmt) = conn.prepare(
+            "SELECT pr_number, user_id, channel_id, created_at, target_branch, follow_backport
+             FROM tracked_prs WHERE user_id = ? ORDER BY created_at",
         ) else {
             return Vec::new();
         };
+        stmt.query_map([user_id.cast_signed()], tracked_row)
+            .map(|iter| iter.filter_map(std::result::Result::ok).collect::<Vec<_>>())
+            .unwrap_or_default()
+    })
+}
+
+fn delete_tracked(pr_number: u64, user_id: u64) {
+    tokio::task::block_in_place(|| {
+        let conn = DB.lock().unwrap();
+        let _ = conn.execute(
+            "DELETE FROM tracked_prs WHERE pr_number = ? AND user_id = ?",
+            rusqlite::params![pr_number.cast_signed(), user_id.cast_signed()],
+        );
+    });
+}
+
+#[derive(Debug, Clone)]
+struct WatchedRow {
+    pr_number: u64,
+    channel_id: u64,
+    message_id: u64,
+    reached: String,
+}
+
+fn load_watched_rows() -> Vec<WatchedRow> {
+    tokio::task::block_in_place(|| {
+        let conn = DB.lock().unwrap();
+        let Ok(mut stmt) =
+            conn.prepare("SELECT pr_number, channel_id, message_id, reached FROM watched_prs")
+        else {
+            return Vec::new();
+        };
         stmt.query_map([], |row| {
-            Ok(TrackedRow {
+            Ok(WatchedRow {
                 pr_number: row.get::<_, i64>(0)?.cast_unsigned(),
-                user_id: row.get::<_, i64>(1)?.cast_unsigned(),
-                channel_id: row.get::<_, i64>(2)?.cast_unsigned(),
-                created_at: row.get::<_, i64>(3)?,
-                target_branch: row.get::<_, String>(4)?,
+                channel_id: row.get::<_, i64>(1)?.cast_unsigned(),
+                message_id: row.get::<_, i64>(2)?.cast_unsigned(),
+                reached: row.get::<_, String>(3)?,
             })
         })
         .map(|iter| iter.filter_map(std::result::Result::ok).collect::<Vec<_>>())
@@ -138,20 +541,125 @@ fn load_tracked_rows() -> Vec<TrackedRow> {
     })
 }
 
-fn delete_tracked(pr_number: u64, user_id: u64) {
+fn delete_watched(pr_number: u64, channel_id: u64) {
     tokio::task::block_in_place(|| {
         let conn = DB.lock().unwrap();
         let _ = conn.execute(
-            "DELETE FROM tracked_prs WHERE pr_number = ? AND user_id = ?",
-            rusqlite::params![pr_number.cast_signed(), user_id.cast_signed()],
+            "DELETE FROM watched_prs WHERE pr_number = ? AND channel_id = ?",
+            rusqlite::params![pr_number.cast_signed(), channel_id.cast_signed()],
+        );
+    });
+}
+
+fn update_watched(row: &WatchedRow, message_id: u64, reached: &str) {
+    tokio::task::block_in_place(|| {
+        let conn = DB.lock().unwrap();
+        let _ = conn.execute(
+            "UPDATE watched_prs SET message_id = ?, reached = ? WHERE pr_number = ? AND channel_id = ?",
+            rusqlite::params![
+                message_id.cast_signed(),
+                reached,
+                row.pr_number.cast_signed(),
+                row.channel_id.cast_signed(),
+            ],
         );
     });
 }
 
+/// What a poll has to do with the status message of a watched PR.
+#[derive(Debug, PartialEq, Eq)]
+struct WatchUpdate {
+    /// The branches reached now, to be stored in `watched_prs.reached`.
+    reached: String,
+    /// Whether the message is out of date and has to be edited.
+    edit: bool,
+    /// Whether every branch is reached and the watch is over.
+    done: bool,
+}
+
+/// Compares the branches a PR has reached with the ones its status message
+/// shows, `None` meaning the message is already up to date.
+fn plan_watch_update(shown: &str, statuses: &[(String, bool)]) -> Option<WatchUpdate> {
+    let reached = reached_branches(statuses);
+    let done = statuses.iter().all(|(_, contains)| *contains);
+    let edit = reached != shown;
+    (edit || done).then_some(WatchUpdate {
+        reached,
+        edit,
+        done,
+    })
+}
+
+/// Brings the status message of a watched PR up to date. The message is
+/// edited in place, or posted again if it has been deleted; once every branch
+/// is reached a short reply pings the channel, since edits don't.
+async fn update_watch_status(
+    serenity: &SerenityContext,
+    row: &WatchedRow,
+    pr: &PullRequest,
+    statuses: &[(String, bool)],
+) {
+    let Some(WatchUpdate {
+        reached,
+        edit,
+        done,
+    }) = plan_watch_update(&row.reached, statuses)
+    else {
+        return;
+    };
+
+    let channel = ChannelId::new(row.channel_id);
+    let embed = watch_status_embed(
+        format!("{} - #{}", pr.title, pr.number),
+        &pr.html_url,
+        statuses,
+    );
+    let mut message_id = MessageId::new(row.message_id);
+
+    if edit
+        && channel
+            .edit_message(
+                serenity,
+                message_id,
+                EditMessage::new().embed(embed.clone()),
+            )
+            .await
+            .is_err()
+    {
+        match channel
+            .send_message(serenity, CreateMessage::new().embed(embed))
+            .await
+        {
+            Ok(message) => message_id = message.id,
+            Err(err) => {
+                error!(
+                    "nixpkgs-track: error posting status of PR #{} in {}: {err}",
+                    row.pr_number, row.channel_id
+                );
+                return;
+            }
+        }
+    }
+
+    if done {
+        let _ = channel
+            .send_message(
+                serenity,
+                CreateMessage::new()
+                    .content(format!("#{} has reached every branch.", pr.number))
+                    .reference_message((channel, message_id)),
+            )
+            .await;
+        delete_watched(row.pr_number, row.channel_id);
+    } else {
+        update_watched(row, message_id.get(), &reached);
+    }
+}
+
 async fn notify_reached_target(
     serenity: &SerenityContext,
     row: &TrackedRow,
-    pr: &nixpkgs_track_lib::PullRequest,
+    pr: &PullRequest,
     statuses: &[(String, bool)],
 ) {
     let mut description = format_branch_statuses(statuses);
@@ -162,7 +670,18 @@ async fn notify_reached_target(
         .url(&pr.html_url)
         .description(description);
 
-    let user = UserId::new(row.user_id);
+    notify_user(serenity, row.user_id, row.channel_id, embed).await;
+}
+
+/// DMs `embed` to a user, falling back to mentioning them in `channel_id`
+/// (where they asked to be notified) if their DMs are closed.
+pub async fn notify_user(
+    serenity: &SerenityContext,
+    user_id: u64,
+    channel_id: u64,
+    embed: CreateEmbed,
+) {
+    let user = UserId::new(user_id);
     let dm_sent = match user.create_dm_channel(serenity).await {
         Ok(dm) => dm
             .send_message(serenity, CreateMessage::new().embed(embed.clone()))
@@ -172,101 +691,434 @@ async fn notify_reached_target(
     };
 
     if !dm_sent {
-        let channel = poise::serenity_prelude::ChannelId::new(row.channel_id);
+        let channel = ChannelId::new(channel_id);
         let _ = channel
             .send_message(
                 serenity,
                 CreateMessage::new()
-                    .content(format!("<@{}>", row.user_id))
+                    .content(format!("<@{user_id}>"))
                     .embed(embed),
             )
             .await;
     }
 }
 
-pub async fn poll_once(serenity: &SerenityContext) {
-    let rows = load_tracked_rows();
-    if rows.is_empty() {
-        return;
+/// What the poller last found out about a tracked PR, persisted so that a PR
+/// whose details and relevant branch heads haven't changed costs no API calls
+/// to check again.
+#[derive(Debug)]
+struct PrStatus {
+    pull_request: PullRequest,
+    etag: Option<String>,
+    /// Branches known to contain the merge commit. A commit never leaves a
+    /// branch, so these are not checked again.
+    reached: BTreeSet<String>,
+    /// For each branch not yet reached, the head it was last compared at.
+    checked_heads: BTreeMap<String, String>,
+}
+
+impl PrStatus {
+    fn statuses(&self) -> Vec<(String, bool)> {
+        tracked_branches_for(&self.pull_request.base.name)
+            .into_iter()
+            .map(|branch| {
+                let contains = self.reached.contains(&branch);
+                (branch, contains)
+            })
+            .collect()
     }
+}
 
-    let github_token = crate::config::get().github_token.clone();
-    let Ok(client) = Client::builder().user_agent("isabelroses/blahaj").build() else {
-        return;
-    };
+/// Parses `branch=sha` pairs separated by spaces, as stored in
+/// `tracked_pr_status.checked_heads`.
+fn parse_heads(heads: &str) -> BTreeMap<String, String> {
+    heads
+        .split_whitespace()
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(branch, sha)| (branch.to_string(), sha.to_string()))
+        .collect()
+}
 
-    let now = chrono::Utc::now().timestamp();
+fn format_heads(heads: &BTreeMap<String, String>) -> String {
+    heads
+        .iter()
+        .map(|(branch, sha)| format!("{branch}={sha}"))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn load_status(pr_number: u64) -> rusqlite::Result<Option<PrStatus>> {
+    tokio::task::block_in_place(|| {
+        let conn = DB.lock().unwrap();
+        conn.query_row(
+            "SELECT title, html_url, base_ref, merged, merge_commit_sha, etag, reached, checked_heads
+             FROM tracked_pr_status WHERE pr_number = ?",
+            [pr_number.cast_signed()],
+            |row| {
+                Ok(PrStatus {
+                    pull_request: PullRequest {
+                        number: pr_number,
+                        title: row.get(0)?,
+                        html_url: row.get(1)?,
+                        base: BaseRef { name: row.get(2)? },
+                        merged: row.get(3)?,
+                        merge_commit_sha: row.get(4)?,
+                    },
+                    etag: row.get(5)?,
+                    reached: row
+                        .get::<_, String>(6)?
+                        .split_whitespace()
+                        .map(str::to_string)
+                        .collect(),
+                    checked_heads: parse_heads(&row.get::<_, String>(7)?),
+                })
+            },
+        )
+        .optional()
+    })
+}
+
+fn save_status(status: &PrStatus) -> rusqlite::Result<()> {
+    let pr = &status.pull_request;
+    let reached: Vec<&str> = status.reached.iter().map(String::as_str).collect();
+    tokio::task::block_in_place(|| {
+        let conn = DB.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO tracked_pr_status
+             (pr_number, title, html_url, base_ref, merged, merge_commit_sha, etag, reached, checked_heads, updated_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            rusqlite::params![
+                pr.number.cast_signed(),
+                pr.title,
+                pr.html_url,
+                pr.base.name,
+                pr.merged,
+                pr.merge_commit_sha,
+                status.etag,
+                reached.join(" "),
+                format_heads(&status.checked_heads),
+                chrono::Utc::now().timestamp(),
+            ],
+        )?;
+        Ok(())
+    })
+}
+
+/// Forgets expired tracking, and the status of PRs nobody tracks any more.
+fn prune(now: i64) {
+    tokio::task::block_in_place(|| {
+        let conn = DB.lock().unwrap();
+        let cutoff = now - TRACKING_TTL_SECS;
+        let _ = conn.execute("DELETE FROM tracked_prs WHERE created_at < ?", [cutoff]);
+        let _ = conn.execute("DELETE FROM watched_prs WHERE created_at < ?", [cutoff]);
+        let _ = conn.execute(
+            "DELETE FROM tracked_pr_status WHERE pr_number NOT IN (
+                SELECT pr_number FROM tracked_prs UNION SELECT pr_number FROM watched_prs
+            )",
+            [],
+        );
+    });
+}
+
+/// Drops everything about a PR that no longer exists.
+fn forget_pr(pr_number: u64) {
+    tokio::task::block_in_place(|| {
+        let conn = DB.lock().unwrap();
+        for table in ["tracked_prs", "watched_prs", "tracked_pr_status"] {
+            let _ = conn.execute(
+                &format!("DELETE FROM {table} WHERE pr_number = ?"),
+                [pr_number.cast_signed()],
+            );
+        }
+    });
+}
+
+/// Lookups shared by every PR checked in one poll cycle.
+#[derive(Default)]
+struct Cycle {
+    heads: HashMap<String, String>,
+    /// Whether a commit is in a branch head, keyed by `(head, commit)`.
+    comparisons: HashMap<(String, String), bool>,
+}
 
-    for row in rows {
-        if now - row.created_at > TRACKING_TTL_SECS {
-            delete_tracked(row.pr_number, row.user_id);
-            continue;
+impl Cycle {
+    async fn head(&mut self, github: &mut GitHub, branch: &str) -> Result<String> {
+        if let Some(head) = self.heads.get(branch) {
+            return Ok(head.clone());
         }
+        let head = github.branch_head(branch).await?;
+        self.heads.insert(branch.to_string(), head.clone());
+        Ok(head)
+    }
 
-        let pr = fetch_nixpkgs_pull_request(
-            crate::types::W(client.clone()),
-            row.pr_number,
-            Some(&github_token),
-        )
-        .await;
+    async fn contains(&mut self, github: &GitHub, head: &str, commit: &str) -> Result<bool> {
+        let key = (head.to_string(), commit.to_string());
+        if let Some(contains) = self.comparisons.get(&key) {
+            return Ok(*contains);
+        }
+        let contains = github.contains_commit(head, commit).await?;
+        self.comparisons.insert(key, contains);
+        Ok(contains)
+    }
+}
 
-        match pr {
-            Ok(pr) => {
-                let Some(commit_sha) = pr.merge_commit_sha.clone() else {
-                    tokio::time::sleep(std::time::Duration::from_secs(PER_PR_DELAY_SECS)).await;
-                    continue;
-                };
-
-                let branches = tracked_branches_for(&pr.base.r#ref);
-                let statuses =
-                    match branch_statuses(client.clone(), &github_token, &branches, &commit_sha)
-                        .await
-                    {
-                        Ok(s) => s,
-                        Err(err) => {
-                            eprintln!(
-                                "nixpkgs-track: error checking branches for PR #{}: {err}",
-                                row.pr_number
-                            );
-                            tokio::time::sleep(std::time::Duration::from_secs(PER_PR_DELAY_SECS))
-                                .await;
-                            continue;
-                        }
-                    };
-
-                let reached_target = statuses
-                    .iter()
-                    .find(|(name, _)| *name == row.target_branch)
-                    .is_some_and(|(_, contains)| *contains);
-
-                if reached_target {
-                    notify_reached_target(serenity, &row, &pr, &statuses).await;
-                    delete_tracked(row.pr_number, row.user_id);
-                }
+/// Brings the status of a PR up to date, or returns `None` if the PR doesn't
+/// exist. Only merged PRs are compared against branches, and only branches
+/// that moved since the last check.
+async fn refresh_status(
+    github: &mut GitHub,
+    cycle: &mut Cycle,
+    pr_number: u64,
+) -> Result<Option<PrStatus>> {
+    let stored = load_status(pr_number)?;
+    let etag = stored.as_ref().and_then(|status| status.etag.clone());
+
+    let mut status = match (
+        github.pull_request(pr_number, etag.as_deref()).await?,
+        stored,
+    ) {
+        (Conditional::NotFound, _) => return Ok(None),
+        (Conditional::NotModified, Some(stored)) => stored,
+        (Conditional::Modified { value, etag }, stored) => {
+            // What was found out about the old commit doesn't carry over if
+            // the PR was retargeted or its merge commit changed.
+            let (reached, checked_heads) = stored
+                .filter(|stored| {
+                    stored.pull_request.merge_commit_sha == value.merge_commit_sha
+                        && stored.pull_request.base.name == value.base.name
+                })
+                .map(|stored| (stored.reached, stored.checked_heads))
+                .unwrap_or_default();
+            PrStatus {
+                pull_request: value,
+                etag,
+                reached,
+                checked_heads,
             }
-            Err(nixpkgs_track_lib::NixpkgsTrackError::PullRequestNotFound(_)) => {
-                delete_tracked(row.pr_number, row.user_id);
+        }
+        (Conditional::NotModified, None) => {
+            return Err(eyre!(
+                "GitHub said PR #{pr_number} is unchanged, but nothing is stored"
+            ));
+        }
+    };
+
+    if status.pull_request.merged
+        && let Some(commit) = status.pull_request.merge_commit_sha.clone()
+    {
+        for branch in tracked_branches_for(&status.pull_request.base.name) {
+            if status.reached.contains(&branch) {
+                continue;
             }
-            Err(nixpkgs_track_lib::NixpkgsTrackError::RateLimitExceeded) => {
-                eprintln!("nixpkgs-track: rate limited; aborting this poll cycle");
-                return;
+
+            let head = cycle.head(github, &branch).await?;
+            if status.checked_heads.get(&branch) == Some(&head) {
+                continue;
             }
-            Err(err) => {
-                eprintln!("nixpkgs-track: error fetching PR #{}: {err}", row.pr_number);
+
+            if cycle.contains(github, &head, &commit).await? {
+                status.checked_heads.remove(&branch);
+                status.reached.insert(branch);
+            } else {
+                status.checked_heads.insert(branch, head);
             }
         }
+    }
+
+    save_status(&status)?;
+    Ok(Some(status))
+}
+
+/// The backport of PR `number` to the newest stable release, if any.
+async fn latest_backport(github: &GitHub, number: u64) -> Option<PullRequest> {
+    let backports = match github.backports(number).await {
+        Ok(backports) => backports,
+        Err(err) => {
+            error!("nixpkgs-track: error looking for backports of #{number}: {err}");
+            return None;
+        }
+    };
+
+    backports
+        .into_iter()
+        .filter_map(|pr| stable_version(&pr.base.name).map(|version| (version, pr)))
+        .max_by(|(a, _), (b, _)| compare_versions(a, b))
+        .map(|(_, pr)| pr)
+}
+
+/// Moves a row following `original` to its backport, so it is tracked like
+/// any other PR from the next cycle on, and lets the user know.
+async fn follow_to_backport(
+    serenity: &SerenityContext,
+    row: &TrackedRow,
+    original: &PullRequest,
+    backport: &PullRequest,
+) {
+    let target_branch =
+        equivalent_branch(&row.target_branch, &original.base.name, &backport.base.name)
+            .unwrap_or_else(|| resolve_target_branch(&backport.base.name, None));
+
+    let moved = tokio::task::block_in_place(|| {
+        let mut conn = DB.lock().unwrap();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT OR REPLACE INTO tracked_prs (pr_number, user_id, channel_id, created_at, target_branch, follow_backport) VALUES (?, ?, ?, ?, ?, 0)",
+            rusqlite::params![
+                backport.number.cast_signed(),
+                row.user_id.cast_signed(),
+                row.channel_id.cast_signed(),
+                row.created_at,
+                target_branch,
+            ],
+        )?;
+        tx.execute(
+            "DELETE FROM tracked_prs WHERE pr_number = ? AND user_id = ?",
+            rusqlite::params![row.pr_number.cast_signed(), row.user_id.cast_signed()],
+        )?;
+        tx.commit()
+    });
+    if let Err(err) = moved {
+        error!(
+            "nixpkgs-track: error following #{} to backport #{}: {err}",
+            row.pr_number, backport.number
+        );
+        return;
+    }
+
+    let embed = CreateEmbed::new()
+        .title(format!("{} - #{}", backport.title, backport.number))
+        .url(&backport.html_url)
+        .description(format!(
+            "Found the backport of #{} to `{}`. I'll DM you when it reaches `{target_branch}`.",
+            original.number, backport.base.name
+        ));
+    notify_user(serenity, row.user_id, row.channel_id, embed).await;
+}
+
+/// Checks every tracked and watched PR once. A PR tracked by several users or
+/// channels is only looked up once, and each branch head once per cycle.
+pub async fn poll_once(serenity: &SerenityContext, github: &mut GitHub) {
+    prune(chrono::Utc::now().timestamp());
+
+    let tracked = load_tracked_rows();
+    let watched = load_watched_rows();
+    let pr_numbers: BTreeSet<u64> = tracked
+        .iter()
+        .map(|row| row.pr_number)
+        .chain(watched.iter().map(|row| row.pr_number))
+        .collect();
+
+    let mut cycle = Cycle::default();
+    for pr_number in pr_numbers {
+        let status = match refresh_status(github, &mut cycle, pr_number).await {
+            Ok(Some(status)) => status,
+            Ok(None) => {
+                forget_pr(pr_number);
+                continue;
+            }
+            Err(err) => {
+                error!("nixpkgs-track: error checking PR #{pr_number}: {err}");
+                continue;
+            }
+        };
+        let statuses = status.statuses();
 
-        tokio::time::sleep(std::time::Duration::from_secs(PER_PR_DELAY_SECS)).await;
+        // Only looked up if someone is following the PR to its backport.
+        let mut backport: Option<Option<PullRequest>> = None;
+        for row in tracked.iter().filter(|row| row.pr_number == pr_number) {
+            if row.follow_backport {
+                if !status.pull_request.merged {
+                    continue;
+                }
+                if backport.is_none() {
+                    backport = Some(latest_backport(github, pr_number).await);
+                }
+                if let Some(Some(backport)) = &backport {
+                    follow_to_backport(serenity, row, &status.pull_request, backport).await;
+                }
+            } else if status.reached.contains(&row.target_branch) {
+                notify_reached_target(serenity, row, &status.pull_request, &statuses).await;
+                delete_tracked(row.pr_number, row.user_id);
+            }
+        }
+        for row in watched.iter().filter(|row| row.pr_number == pr_number) {
+            update_watch_status(serenity, row, &status.pull_request, &statuses).await;
+        }
     }
 }
 
 pub fn spawn_poller(serenity: SerenityContext) {
     tokio::spawn(async move {
+        let Ok(client) = Client::builder().user_agent("isabelroses/blahaj").build() else {
+            return;
+        };
+        let mut github = GitHub::new(client, crate::config::get().github_token.clone());
+
         let mut interval =
             tokio::time::interval(std::time::Duration::from_secs(POLL_INTERVAL_SECS));
+        // A cycle can outlast the interval while waiting out a rate limit;
+        // start the next one a full interval later instead of straight away.
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
         loop {
             interval.tick().await;
-            poll_once(&serenity).await;
+            poll_once(&serenity, &mut github).await;
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checked_heads_round_trip() {
+        let heads = parse_heads("master=abc nixos-unstable=def bogus");
+        assert_eq!(heads.len(), 2);
+        assert_eq!(heads["master"], "abc");
+        assert_eq!(format_heads(&heads), "master=abc nixos-unstable=def");
+    }
+
+    fn statuses(reached: &[bool]) -> Vec<(String, bool)> {
+        ["master", "nixos-unstable", "nixos-25.05"]
+            .into_iter()
+            .zip(reached)
+            .map(|(branch, contains)| (branch.to_string(), *contains))
+            .collect()
+    }
+
+    #[test]
+    fn reaching_a_branch_edits_the_status_message_once() {
+        let update = plan_watch_update("master", &statuses(&[true, true, false])).unwrap();
+        assert_eq!(
+            update,
+            WatchUpdate {
+                reached: "master nixos-unstable".to_string(),
+                edit: true,
+                done: false,
+            }
+        );
+
+        // The next poll sees what the last one stored and leaves the message be.
+        assert_eq!(
+            plan_watch_update(&update.reached, &statuses(&[true, true, false])),
+            None
+        );
+    }
+
+    #[test]
+    fn reaching_every_branch_edits_and_ends_the_watch() {
+        let update =
+            plan_watch_update("master nixos-unstable", &statuses(&[true, true, true])).unwrap();
+        assert!(update.edit);
+        assert!(update.done);
+        assert_eq!(update.reached, "master nixos-unstable nixos-25.05");
+    }
+
+    #[test]
+    fn nothing_new_leaves_the_status_message_alone() {
+        assert_eq!(
+            plan_watch_update("", &statuses(&[false, false, false])),
+            None
+        );
+    }
+}
@@ -0,0 +1,396 @@
+use color_eyre::eyre::{Result, eyre};
+use poise::CreateReply;
+use poise::serenity_prelude::{Context as SerenityContext, CreateEmbed, CreateEmbedFooter};
+use rusqlite::OptionalExtension;
+use std::cmp::Ordering;
+use std::fmt::Write as _;
+use tracing::error;
+
+use crate::commands::nix::nixpkg::{autocomplete_channel, autocomplete_package, resolve_channel};
+use crate::commands::nix::track::notify_user;
+use crate::nixpkgs_db::{self, Channel, versions::compare_versions};
+use crate::types::Context;
+use crate::utils::DB;
+
+/// How many versions one user can be waiting for at once.
+const MAX_TRACKED_PER_USER: i64 = 25;
+
+#[derive(Debug)]
+struct TrackedVersion {
+    user_id: u64,
+    channel_id: u64,
+    package_name: String,
+    min_version: String,
+    /// The nixpkgs channel to wait for, or `None` for whichever configured
+    /// channel gets there first.
+    nixpkgs_channel: Option<String>,
+}
+
+/// Versions are often written with a leading `v`, which would otherwise sort
+/// below any number.
+fn normalize_version(version: &str) -> &str {
+    let version = version.trim();
+    match version.strip_prefix(['v', 'V']) {
+        Some(rest) if rest.starts_with(|c: char| c.is_ascii_digit()) => rest,
+        _ => version,
+    }
+}
+
+/// The version of `package` in `channel`, if it is there at all.
+fn current_version(channel: &Channel, package: &str) -> rusqlite::Result<Option<String>> {
+    let db = channel.db()?;
+    let version = db
+        .query_row(
+            "SELECT version FROM packages WHERE package_name = ?1",
+            [package],
+            |row| row.get::<_, Option<String>>(0),
+        )
+        .optional()?;
+    Ok(version.flatten())
+}
+
+/// The channels a tracker looks at.
+fn channels_for(nixpkgs_channel: Option<&str>) -> Vec<&'static Channel> {
+    match nixpkgs_channel {
+        Some(name) => nixpkgs_db::channel(name).into_iter().collect(),
+        None => nixpkgs_db::channels().iter().collect(),
+    }
+}
+
+fn satisfies(version: &str, min_version: &str) -> bool {
+    compare_versions(version, min_version) != Ordering::Less
+}
+
+/// Get notified when a package version reaches a nixpkgs channel
+#[allow(clippy::unused_async)]
+#[poise::command(
+    slash_command,
+    rename = "nixpkg-track",
+    install_context = "Guild|User",
+    interaction_context = "Guild|BotDm|PrivateChannel",
+    subcommands("add", "list", "remove")
+)]
+pub async fn nixpkg_track(_: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+/// Get a DM once a package is at least the given version in a channel
+#[poise::command(slash_command)]
+pub async fn add(
+    ctx: Context<'_>,
+    #[description = "package name"]
+    #[autocomplete = "autocomplete_package"]
+    package: String,
+    #[description = "lowest version to wait for, e.g. 140"] version: String,
+    #[description = "channel to wait for (defaults to any configured channel)"]
+    #[autocomplete = "autocomplete_channel"]
+    channel: Option<String>,
+) -> Result<()> {
+    let package = package.trim();
+    let min_version = normalize_version(&version);
+    if min_version.is_empty() {
+        return Err(eyre!("Give a version to wait for"));
+    }
+    let nixpkgs_channel = match channel.as_deref() {
+        Some(name) => Some(resolve_channel(Some(name))?.name.clone()),
+        None => None,
+    };
+
+    let current: Vec<(&Channel, Option<String>)> = channels_for(nixpkgs_channel.as_deref())
+        .into_iter()
+        .map(|channel| current_version(channel, package).map(|version| (channel, version)))
+        .collect::<rusqlite::Result<_>>()?;
+
+    if current.iter().all(|(_, version)| version.is_none()) {
+        ctx.send(
+            CreateReply::default()
+                .content(format!("`{package}` isn't in any of the channels I know."))
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    if let Some((channel, Some(version))) = current.iter().find(|(_, version)| {
+        version
+            .as_deref()
+            .is_some_and(|v| satisfies(v, min_version))
+    }) {
+        ctx.send(
+            CreateReply::default()
+                .content(format!(
+                    "`{package}` is already at `{version}` in `{}`.",
+                    channel.name
+                ))
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let user_id = ctx.author().id.get();
+    let channel_id = ctx.channel_id().get();
+    let now = chrono::Utc::now().timestamp();
+
+    let inserted = tokio::task::block_in_place(|| -> rusqlite::Result<bool> {
+        let conn = DB.lock().unwrap();
+        // Changing the version of a tracker already counted is always fine.
+        let others: i64 = conn.query_row(
+            "SELECT count(*) FROM tracked_versions
+             WHERE user_id = ?1 AND NOT (package_name = ?2 AND nixpkgs_channel = ?3)",
+            rusqlite::params![
+                user_id.cast_signed(),
+                package,
+                nixpkgs_channel.as_deref().unwrap_or_default(),
+            ],
+            |row| row.get(0),
+        )?;
+        if others >= MAX_TRACKED_PER_USER {
+            return Ok(false);
+        }
+
+        conn.execute(
+            "INSERT OR REPLACE INTO tracked_versions (user_id, channel_id, package_name, min_version, nixpkgs_channel, created_at) VALUES (?, ?, ?, ?, ?, ?)",
+            rusqlite::params![
+                user_id.cast_signed(),
+                channel_id.cast_signed(),
+                package,
+                min_version,
+                nixpkgs_channel.as_deref().unwrap_or_default(),
+                now,
+            ],
+        )?;
+        Ok(true)
+    })?;
+
+    if !inserted {
+        ctx.send(
+            CreateReply::default()
+                .content(format!(
+                    "You are already waiting for {MAX_TRACKED_PER_USER} versions, remove some with `/nixpkg-track remove` first."
+                ))
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let mut description = String::new();
+    for (channel, version) in &current {
+        let _ = writeln!(
+            description,
+            "`{}`: {}",
+            channel.name,
+            version.as_deref().unwrap_or("not packaged")
+        );
+    }
+    let target = nixpkgs_channel
+        .as_deref()
+        .map_or_else(|| "any channel".to_string(), |name| format!("`{name}`"));
+    let _ = write!(
+        description,
+        "\nI'll DM you when it reaches `{min_version}` in {target}."
+    );
+
+    ctx.send(
+        CreateReply::default().embed(
+            CreateEmbed::new()
+                .title(format!("Waiting for `{package}` {min_version}"))
+                .description(description)
+                .color(0x00DE_A586),
+        ),
+    )
+    .await?;
+    Ok(())
+}
+
+/// List the package versions you are waiting for
+#[poise::command(slash_command)]
+pub async fn list(ctx: Context<'_>) -> Result<()> {
+    let rows = load_tracked_versions_of(ctx.author().id.get());
+
+    let description = if rows.is_empty() {
+        "You aren't waiting for any package versions. Use `/nixpkg-track add` to start.".to_string()
+    } else {
+        let mut description = String::new();
+        for row in &rows {
+            let mut current = Vec::new();
+            for channel in channels_for(row.nixpkgs_channel.as_deref()) {
+                if let Some(version) = current_version(channel, &row.package_name)? {
+                    current.push(format!("{version} in {}", channel.name));
+                }
+            }
+            let _ = writeln!(
+                description,
+                "`{}` ≥ `{}` in {}, now {}",
+                row.package_name,
+                row.min_version,
+                row.nixpkgs_channel
+                    .as_deref()
+                    .map_or_else(|| "any channel".to_string(), |name| format!("`{name}`")),
+                if current.is_empty() {
+                    "not packaged".to_string()
+                } else {
+                    current.join(", ")
+                }
+            );
+        }
+        description
+    };
+
+    ctx.send(
+        CreateReply::default()
+            .embed(
+                CreateEmbed::new()
+                    .title("Tracked package versions")
+                    .description(description)
+                    .color(0x00DE_A586),
+            )
+            .ephemeral(true),
+    )
+    .await?;
+    Ok(())
+}
+
+#[allow(clippy::unused_async)]
+async fn autocomplete_tracked_package(ctx: Context<'_>, partial: &str) -> Vec<String> {
+    let mut packages: Vec<String> = load_tracked_versions_of(ctx.author().id.get())
+        .into_iter()
+        .map(|row| row.package_name)
+        .filter(|name| name.contains(partial.trim()))
+        .collect();
+    packages.dedup();
+    packages.truncate(25);
+    packages
+}
+
+/// Stop waiting for a package version
+#[poise::command(slash_command)]
+pub async fn remove(
+    ctx: Context<'_>,
+    #[description = "package name"]
+    #[autocomplete = "autocomplete_tracked_package"]
+    package: String,
+) -> Result<()> {
+    let package = package.trim();
+    let user_id = ctx.author().id.get();
+    let deleted = tokio::task::block_in_place(|| {
+        let conn = DB.lock().unwrap();
+        conn.execute(
+            "DELETE FROM tracked_versions WHERE user_id = ? AND package_name = ?",
+            rusqlite::params![user_id.cast_signed(), package],
+        )
+    })?;
+
+    let content = if deleted > 0 {
+        format!("✅ No longer waiting for `{package}`.")
+    } else {
+        format!("You aren't waiting for `{package}`.")
+    };
+    ctx.send(CreateReply::default().content(content).ephemeral(true))
+        .await?;
+    Ok(())
+}
+
+fn tracked_version(row: &rusqlite::Row) -> rusqlite::Result<TrackedVersion> {
+    Ok(TrackedVersion {
+        user_id: row.get::<_, i64>(0)?.cast_unsigned(),
+        channel_id: row.get::<_, i64>(1)?.cast_unsigned(),
+        package_name: row.get(2)?,
+        min_version: row.get(3)?,
+        nixpkgs_channel: Some(row.get::<_, String>(4)?).filter(|name| !name.is_empty()),
+    })
+}
+
+fn load_tracked_versions_of(user_id: u64) -> Vec<TrackedVersion> {
+    tokio::task::block_in_place(|| {
+        let conn = DB.lock().unwrap();
+        let Ok(mut stmt) = conn.prepare(
+            "SELECT user_id, channel_id, package_name, min_version, nixpkgs_channel
+             FROM tracked_versions WHERE user_id = ? ORDER BY package_name",
+        ) else {
+            return Vec::new();
+        };
+        stmt.query_map([user_id.cast_signed()], tracked_version)
+            .map(|iter| iter.filter_map(std::result::Result::ok).collect::<Vec<_>>())
+            .unwrap_or_default()
+    })
+}
+
+/// The trackers that look at `channel`.
+fn load_tracked_versions_for(channel: &str) -> Vec<TrackedVersion> {
+    tokio::task::block_in_place(|| {
+        let conn = DB.lock().unwrap();
+        let Ok(mut stmt) = conn.prepare(
+            "SELECT user_id, channel_id, package_name, min_version, nixpkgs_channel
+             FROM tracked_versions WHERE nixpkgs_channel IN ('', ?)",
+        ) else {
+            return Vec::new();
+        };
+        stmt.query_map([channel], tracked_version)
+            .map(|iter| iter.filter_map(std::result::Result::ok).collect::<Vec<_>>())
+            .unwrap_or_default()
+    })
+}
+
+fn delete_tracked_version(row: &TrackedVersion) {
+    tokio::task::block_in_place(|| {
+        let conn = DB.lock().unwrap();
+        let _ = conn.execute(
+            "DELETE FROM tracked_versions WHERE user_id = ? AND package_name = ? AND nixpkgs_channel = ?",
+            rusqlite::params![
+                row.user_id.cast_signed(),
+                row.package_name,
+                row.nixpkgs_channel.as_deref().unwrap_or_default(),
+            ],
+        );
+    });
+}
+
+/// Notifies everyone waiting for a package version that `channel` now has.
+/// Run after every refresh of the channel's database.
+pub async fn check_channel(serenity: &SerenityContext, channel: &Channel) {
+    for row in load_tracked_versions_for(&channel.name) {
+        let version = match current_version(channel, &row.package_name) {
+            Ok(Some(version)) => version,
+            Ok(None) => continue,
+            Err(err) => {
+                error!(
+                    channel = %channel.name,
+                    "failed to look up the version of {}: {err}", row.package_name
+                );
+                continue;
+            }
+        };
+        if !satisfies(&version, &row.min_version) {
+            continue;
+        }
+
+        let embed = CreateEmbed::new()
+            .title(format!(
+                "`{}` {version} is in `{}`",
+                row.package_name, channel.name
+            ))
+            .description(format!(
+                "You asked to be told when `{}` reached `{}`.",
+                row.package_name, row.min_version
+            ))
+            .footer(CreateEmbedFooter::new(&channel.name))
+            .color(0x00DE_A586);
+        notify_user(serenity, row.user_id, row.channel_id, embed).await;
+        delete_tracked_version(&row);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_leading_v() {
+        assert_eq!(normalize_version(" v140.0 "), "140.0");
+        assert_eq!(normalize_version("vim"), "vim");
+        assert!(satisfies("140.0.2", normalize_version("v140")));
+        assert!(!satisfies("139.0.4", "140"));
+    }
+}
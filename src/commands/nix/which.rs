@@ -0,0 +1,173 @@
+use crate::commands::nix::nixpkg::{autocomplete_channel, resolve_channel};
+use crate::nixpkgs_db::{
+    self, Channel,
+    programs::{self, Provider},
+};
+use crate::types::Context;
+use color_eyre::eyre::Result;
+use poise::{
+    CreateReply,
+    serenity_prelude::{CreateEmbed, CreateEmbedFooter, Message},
+};
+use regex::Regex;
+use std::fmt::Write as _;
+use std::sync::LazyLock;
+
+/// Most packages listed for a single program.
+const MAX_PROVIDERS: usize = 10;
+/// Programs looked up from a single message, so a pasted log full of errors
+/// doesn't turn into a wall of embeds.
+const MAX_PROGRAMS: usize = 3;
+
+/// "command not found" errors as printed by zsh, bash, fish, nushell and dash.
+static NOT_FOUND_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(concat!(
+        r"(?:\S+ )?command not found: ([\w.+-]+)",
+        r"|([\w.+-]+): command not found",
+        r"|Unknown command:? '?([\w.+-]+)",
+        r"|Command `([\w.+-]+)` not found",
+        r"|\d+: ([\w.+-]+): not found",
+    ))
+    .unwrap()
+});
+
+/// Find which packages provide a program
+#[poise::command(
+    slash_command,
+    rename = "nix-which",
+    install_context = "Guild|User",
+    interaction_context = "Guild|BotDm|PrivateChannel"
+)]
+pub async fn nix_which(
+    ctx: Context<'_>,
+    #[description = "name of the program, e.g. rg"] program: String,
+    #[description = "channel to look in (defaults to the first configured channel)"]
+    #[autocomplete = "autocomplete_channel"]
+    channel: Option<String>,
+) -> Result<()> {
+    ctx.defer().await?;
+
+    let channel = resolve_channel(channel.as_deref())?;
+    let embed = providers_embed(channel, program.trim())?;
+    ctx.send(CreateReply::default().embed(embed)).await?;
+
+    Ok(())
+}
+
+/// Find the packages providing the commands a "command not found" error
+/// complains about
+#[poise::command(
+    context_menu_command = "Find missing command",
+    install_context = "Guild|User",
+    interaction_context = "Guild|BotDm|PrivateChannel"
+)]
+pub async fn nix_which_message(ctx: Context<'_>, message: Message) -> Result<()> {
+    let missing = missing_programs(&message.content);
+    if missing.is_empty() {
+        ctx.send(
+            CreateReply::default()
+                .content("That message doesn't contain a \"command not found\" error.")
+                .ephemeral(true),
+        )
+        .await?;
+        return Ok(());
+    }
+
+    ctx.defer().await?;
+
+    let channel = nixpkgs_db::default_channel();
+    let mut reply = CreateReply::default();
+    for program in missing {
+        reply = reply.embed(providers_embed(channel, program)?);
+    }
+    ctx.send(reply).await?;
+
+    Ok(())
+}
+
+/// The distinct programs a message complains about, in order of appearance.
+fn missing_programs(content: &str) -> Vec<&str> {
+    let mut programs: Vec<&str> = Vec::new();
+    for captures in NOT_FOUND_RE.captures_iter(content) {
+        let Some(program) = captures.iter().skip(1).flatten().next() else {
+            continue;
+        };
+        if !programs.contains(&program.as_str()) {
+            programs.push(program.as_str());
+        }
+    }
+    programs.truncate(MAX_PROGRAMS);
+    programs
+}
+
+/// Lists the `nix shell` invocation for each package providing `program`.
+fn providers_embed(channel: &Channel, program: &str) -> Result<CreateEmbed> {
+    let (providers, total) = {
+        let db = channel.db()?;
+        (
+            programs::providers(&db, program, MAX_PROVIDERS)?,
+            programs::count_providers(&db, program)?,
+        )
+    };
+
+    let embed = CreateEmbed::new()
+        .footer(CreateEmbedFooter::new(&channel.name))
+        .color(0x00DE_A586);
+
+    if providers.is_empty() {
+        return Ok(embed.title(format!("No package provides `{program}`")));
+    }
+
+    let flake_ref = channel.flake_ref();
+    let mut description = String::new();
+    for Provider {
+        package_name,
+        version,
+        broken,
+        insecure,
+    } in &providers
+    {
+        let _ = write!(description, "- `nix shell {flake_ref}#{package_name}`");
+        if let Some(version) = version {
+            let _ = write!(description, " {version}");
+        }
+        if *broken {
+            description.push_str(" (broken)");
+        }
+        if *insecure {
+            description.push_str(" (insecure)");
+        }
+        description.push('\n');
+    }
+    if total > providers.len() {
+        let _ = writeln!(description, "- ...and {} more", total - providers.len());
+    }
+
+    Ok(embed
+        .title(format!("`{program}` is provided by"))
+        .description(description))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognises_shell_errors() {
+        assert_eq!(missing_programs("zsh: command not found: rg"), ["rg"]);
+        assert_eq!(missing_programs("zsh:1: command not found: rg"), ["rg"]);
+        assert_eq!(missing_programs("bash: htop: command not found"), ["htop"]);
+        assert_eq!(missing_programs("fish: Unknown command: fd"), ["fd"]);
+        assert_eq!(missing_programs("Error: Command `jq` not found"), ["jq"]);
+        assert_eq!(missing_programs("/bin/sh: 1: make: not found"), ["make"]);
+        assert!(missing_programs("the file was not found").is_empty());
+    }
+
+    #[test]
+    fn dedupes_and_caps_programs() {
+        let log = "zsh: command not found: a\nzsh: command not found: a\n\
+                   zsh: command not found: b\nzsh: command not found: c\n\
+                   zsh: command not found: d";
+        assert_eq!(missing_programs(log), ["a", "b", "c"]);
+    }
+}
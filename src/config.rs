@@ -1,7 +1,9 @@
 use color_eyre::eyre::{Result, eyre};
 use confique::Config;
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::OnceLock;
+use tracing::warn;
 
 #[derive(Config, Debug, Clone)]
 pub struct AppConfig {
@@ -14,13 +16,141 @@ pub struct AppConfig {
     #[config(env = "BLAHAJ_DATA_DIR", default = "/var/lib/blahaj")]
     pub data_dir: PathBuf,
 
+    /// Channel base URLs, each indexed into its own package database. The
+    /// first one is used when a command doesn't name a channel. Falls back to
+    /// `nixpkgs_channel`, then to [`DEFAULT_CHANNEL`].
     #[config(
-        env = "NIXPKGS_CHANNEL",
-        default = "https://channels.nixos.org/nixpkgs-unstable"
+        env = "NIXPKGS_CHANNELS",
+        parse_env = confique::env::parse::list_by_comma,
+        default = []
     )]
-    pub nixpkgs_channel: String,
+    pub nixpkgs_channels: Vec<String>,
+
+    /// The single channel blahaj used to index, kept so existing
+    /// configurations don't silently switch to the default channel.
+    #[config(env = "NIXPKGS_CHANNEL")]
+    pub nixpkgs_channel: Option<String>,
+
+    #[config(nested)]
+    pub grok: GrokConfig,
 }
 
+/// The OpenAI-compatible chat completions API behind `@grok`. The endpoint,
+/// key, model and its settings can be overridden per guild with
+/// `/grok-config`.
+#[derive(Config, Debug, Clone)]
+pub struct GrokConfig {
+    /// Base URL of the API, `/chat/completions` is appended to it.
+    #[config(env = "GROK_ENDPOINT", default = "https://opencode.ai/zen/v1")]
+    pub endpoint: String,
+
+    /// Sent as a bearer token when set.
+    #[config(env = "GROK_API_KEY")]
+    pub api_key: Option<String>,
+
+    /// Endpoints guilds may pick with `/grok-config set` even though they
+    /// aren't public, e.g. a server on the local network. Matched by scheme,
+    /// host and port.
+    #[config(
+        env = "GROK_ALLOWED_ENDPOINTS",
+        parse_env = confique::env::parse::list_by_comma,
+        default = []
+    )]
+    pub allowed_endpoints: Vec<String>,
+
+    #[config(env = "GROK_MODEL", default = "deepseek-v4-flash-free")]
+    pub model: String,
+
+    /// Models guilds may pick with `/grok-config set` while using the global
+    /// endpoint and key, besides `model`. Guilds with an endpoint or key of
+    /// their own may pick any.
+    #[config(
+        env = "GROK_ALLOWED_MODELS",
+        parse_env = confique::env::parse::list_by_comma,
+        default = []
+    )]
+    pub allowed_models: Vec<String>,
+
+    /// Whether the model understands images. When it does, images attached
+    /// to the conversation are sent along with it.
+    #[config(env = "GROK_VISION", default = false)]
+    pub vision: bool,
+
+    /// Reasoning effort hint, left out of the request when empty since not
+    /// every server understands it.
+    #[config(env = "GROK_REASONING", default = "low")]
+    pub reasoning: String,
+
+    #[config(env = "GROK_TEMPERATURE")]
+    pub temperature: Option<f64>,
+
+    #[config(env = "GROK_MAX_TOKENS")]
+    pub max_tokens: Option<u32>,
+
+    /// How many tokens fit in the model's context window, prompt and reply
+    /// together. Older messages and link contents are cut to stay within it.
+    /// Tokens are estimated from the text's length rather than counted with
+    /// the model's tokenizer, so a fifth of it is left unused to be safe.
+    #[config(env = "GROK_CONTEXT_TOKENS", default = 16384)]
+    pub context_tokens: u32,
+
+    /// `context_tokens` for particular models, by name. Also applies to the
+    /// models guilds pick.
+    #[config(default = {})]
+    pub model_context_tokens: HashMap<String, u32>,
+
+    /// Replaces the built-in system prompt.
+    #[config(env = "GROK_SYSTEM_PROMPT")]
+    pub system_prompt: Option<String>,
+
+    /// Whether the model is offered blahaj's lookups as tools. Turn this off
+    /// for servers that reject requests with tools in them.
+    #[config(env = "GROK_TOOLS", default = true)]
+    pub tools: bool,
+
+    /// Whether `@grok` answers in DMs. In servers it has to be turned on with
+    /// `/grok-config enable`.
+    #[config(env = "GROK_DMS", default = false)]
+    pub dms: bool,
+
+    /// How many bytes of text attachments (logs, `.nix` files and the like)
+    /// are inlined into the conversation, 0 to leave them out.
+    #[config(env = "GROK_ATTACHMENT_BYTES", default = 32768)]
+    pub attachment_bytes: usize,
+
+    #[config(nested)]
+    pub limits: GrokLimits,
+}
+
+/// How often `@grok` can be asked things. Every user, channel and guild gets
+/// a token bucket holding up to `*_burst` requests that refills at
+/// `*_per_hour`. Setting a burst to 0 turns that limit off.
+#[derive(Config, Debug, Clone)]
+pub struct GrokLimits {
+    #[config(env = "GROK_USER_BURST", default = 3)]
+    pub user_burst: u32,
+    #[config(env = "GROK_USER_PER_HOUR", default = 20)]
+    pub user_per_hour: u32,
+
+    #[config(env = "GROK_CHANNEL_BURST", default = 6)]
+    pub channel_burst: u32,
+    #[config(env = "GROK_CHANNEL_PER_HOUR", default = 60)]
+    pub channel_per_hour: u32,
+
+    #[config(env = "GROK_GUILD_BURST", default = 10)]
+    pub guild_burst: u32,
+    #[config(env = "GROK_GUILD_PER_HOUR", default = 120)]
+    pub guild_per_hour: u32,
+
+    /// Prompt and completion tokens one user may use per day (UTC), across
+    /// all servers.
+    #[config(env = "GROK_USER_DAILY_TOKENS")]
+    pub user_daily_tokens: Option<u64>,
+}
+
+/// The channel indexed when none is configured.
+pub const DEFAULT_CHANNEL: &str = "https://channels.nixos.org/nixpkgs-unstable";
+
 static CONFIG: OnceLock<AppConfig> = OnceLock::new();
 
 pub fn init() -> Result<&'static AppConfig> {
@@ -35,7 +165,20 @@ pub fn init() -> Result<&'static AppConfig> {
         builder = builder.file("blahaj.toml");
     }
 
-    let config = builder.load().map_err(|err| eyre!("{err}"))?;
+    let mut config = builder.load().map_err(|err| eyre!("{err}"))?;
+    if config.nixpkgs_channels.is_empty() {
+        let channel = match config.nixpkgs_channel.take() {
+            Some(channel) => {
+                warn!("nixpkgs_channel (NIXPKGS_CHANNEL) is deprecated, use nixpkgs_channels");
+                channel
+            }
+            None => DEFAULT_CHANNEL.to_string(),
+        };
+        config.nixpkgs_channels = vec![channel];
+    } else if config.nixpkgs_channel.is_some() {
+        warn!("nixpkgs_channel is ignored since nixpkgs_channels is set");
+    }
+
     CONFIG
         .set(config)
         .map_err(|_| eyre!("config already initialized"))?;
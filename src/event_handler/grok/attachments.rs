@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD;
+use color_eyre::eyre::{Result, eyre};
+use poise::serenity_prelude::{Attachment, Message, MessageId};
+use tracing::warn;
+
+use crate::types::Data;
+
+/// How many images are sent to the model, the newest ones win.
+const MAX_IMAGES: usize = 4;
+/// Images bigger than this are left out rather than downloaded.
+const MAX_IMAGE_BYTES: u32 = 8 * 1024 * 1024;
+/// Image formats vision models generally accept.
+const IMAGE_TYPES: &[&str] = &["image/png", "image/jpeg", "image/webp", "image/gif"];
+/// Files that are text even though Discord doesn't always say so.
+const TEXT_EXTENSIONS: &[&str] = &[
+    "nix", "log", "txt", "md", "rs", "toml", "lock", "json", "yaml", "yml", "ini", "conf", "cfg",
+    "sh", "py", "lua", "js", "ts", "go", "c", "h", "cpp", "hpp", "diff", "patch", "xml", "html",
+    "css", "csv",
+];
+
+/// What a message had attached, ready to be shown to the model.
+#[derive(Default)]
+pub(super) struct Attached {
+    /// `data:` URLs of the images.
+    pub(super) images: Vec<String>,
+    /// Name and contents of each text file.
+    pub(super) files: Vec<(String, String)>,
+}
+
+impl Attached {
+    fn is_empty(&self) -> bool {
+        self.images.is_empty() && self.files.is_empty()
+    }
+}
+
+fn is_image(attachment: &Attachment) -> bool {
+    attachment
+        .content_type
+        .as_deref()
+        .is_some_and(|content_type| IMAGE_TYPES.contains(&content_type))
+}
+
+fn is_text(attachment: &Attachment) -> bool {
+    let content_type = attachment.content_type.as_deref().unwrap_or_default();
+    if content_type.starts_with("text/") || content_type.starts_with("application/json") {
+        return true;
+    }
+    attachment
+        .filename
+        .rsplit_once('.')
+        .is_some_and(|(_, extension)| {
+            TEXT_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str())
+        })
+}
+
+async fn download(data: &Data, attachment: &Attachment) -> Result<Vec<u8>> {
+    let response = data.client.get(&attachment.url).send().await?;
+    if !response.status().is_success() {
+        return Err(eyre!("discord returned status {}", response.status()));
+    }
+    Ok(response.bytes().await?.to_vec())
+}
+
+/// Downloads the attachments of `messages` the model can make sense of:
+/// images when it has `vision`, and text files while they fit in `budget`
+/// bytes. `messages` should come newest first, so the message that triggered
+/// us gets first pick. Failed downloads are skipped (best-effort context).
+pub(super) async fn gather(
+    data: &Data,
+    messages: &[&Message],
+    vision: bool,
+    mut budget: usize,
+) -> HashMap<MessageId, Attached> {
+    let mut gathered = HashMap::new();
+    let mut images = 0;
+
+    for msg in messages {
+        let mut attached = Attached::default();
+        for attachment in &msg.attachments {
+            if vision && images < MAX_IMAGES && is_image(attachment) {
+                if attachment.size > MAX_IMAGE_BYTES {
+                    continue;
+                }
+                match download(data, attachment).await {
+                    Ok(bytes) => {
+                        let content_type = attachment.content_type.as_deref().unwrap_or_default();
+                        attached.images.push(format!(
+                            "data:{content_type};base64,{}",
+                            STANDARD.encode(bytes)
+                        ));
+                        images += 1;
+                    }
+                    Err(err) => warn!("grok failed to fetch {}: {err}", attachment.filename),
+                }
+            } else if is_text(attachment) && attachment.size as usize <= budget {
+                match download(data, attachment).await {
+                    Ok(bytes) => {
+                        // Binary files with a misleading name aren't worth
+                        // sending.
+                        if let Ok(text) = String::from_utf8(bytes) {
+                            budget = budget.saturating_sub(text.len());
+                            attached.files.push((attachment.filename.clone(), text));
+                        }
+                    }
+                    Err(err) => warn!("grok failed to fetch {}: {err}", attachment.filename),
+                }
+            }
+        }
+
+        if !attached.is_empty() {
+            gathered.insert(msg.id, attached);
+        }
+    }
+
+    gathered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attachment(filename: &str, content_type: Option<&str>) -> Attachment {
+        serde_json::from_value(serde_json::json!({
+            "id": "1",
+            "filename": filename,
+            "size": 100,
+            "url": "https://cdn.discordapp.com/attachments/1/1/file",
+            "proxy_url": "https://media.discordapp.net/attachments/1/1/file",
+            "content_type": content_type,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn tells_text_from_images() {
+        assert!(is_image(&attachment("screenshot.png", Some("image/png"))));
+        assert!(!is_image(&attachment("drawing.svg", Some("image/svg+xml"))));
+
+        assert!(is_text(&attachment(
+            "build.log",
+            Some("text/plain; charset=utf-8")
+        )));
+        assert!(is_text(&attachment("flake.nix", None)));
+        assert!(is_text(&attachment(
+            "Cargo.LOCK",
+            Some("application/octet-stream")
+        )));
+        assert!(!is_text(&attachment(
+            "nixos.iso",
+            Some("application/octet-stream")
+        )));
+        assert!(!is_text(&attachment("screenshot.png", Some("image/png"))));
+    }
+}
@@ -0,0 +1,326 @@
+use super::{ChatMessage, Content, ContentPart, LinkContext, tools};
+use crate::llm::Provider;
+
+/// What the framing of a message (role, separators) costs.
+const MESSAGE_TOKENS: usize = 4;
+/// What an image costs, roughly, across the vision models we have seen.
+const IMAGE_TOKENS: usize = 1000;
+/// Room kept for the reply when the provider doesn't cap it.
+const REPLY_TOKENS: u32 = 1024;
+/// Link contents that would have to be cut shorter than this are left out,
+/// as a few lines of a page are more confusing than helpful.
+const MIN_LINK_TOKENS: usize = 200;
+/// Tools are only offered while this much is left for what they return.
+const MIN_TOOL_TOKENS: usize = 500;
+/// Part of the context window left unused, as [`estimate_tokens`] only
+/// approximates what the model's tokenizer counts: one part in this many.
+const SAFETY_MARGIN: usize = 5;
+/// Added to whatever had to be cut short.
+const TRUNCATED: &str = "\n\n[truncated]";
+
+/// Roughly how many tokens `text` takes. BPE tokenizers average about four
+/// characters per token on English and code, while other scripts tend to
+/// take a token per character. This is an estimate, not what any model's
+/// tokenizer counts, so budgets keep a [`SAFETY_MARGIN`].
+pub(super) fn estimate_tokens(text: &str) -> usize {
+    let ascii = text.bytes().filter(u8::is_ascii).count();
+    let other = text.chars().filter(|c| !c.is_ascii()).count();
+    ascii.div_ceil(4) + other
+}
+
+fn message_tokens(message: &ChatMessage) -> usize {
+    let content = match &message.content {
+        Content::Text(text) => estimate_tokens(text),
+        Content::Parts(parts) => parts
+            .iter()
+            .map(|part| match part {
+                ContentPart::Text { text } => estimate_tokens(text),
+                ContentPart::ImageUrl { .. } => IMAGE_TOKENS,
+            })
+            .sum(),
+    };
+    let tool_calls: usize = message
+        .tool_calls
+        .iter()
+        .map(|call| {
+            estimate_tokens(&call.function.name) + estimate_tokens(&call.function.arguments)
+        })
+        .sum();
+    MESSAGE_TOKENS + content + tool_calls
+}
+
+/// Cuts `text` down to about `tokens` tokens, marking that it was cut.
+fn truncate(text: &str, tokens: usize) -> String {
+    if estimate_tokens(text) <= tokens {
+        return text.to_string();
+    }
+
+    let room = tokens.saturating_sub(estimate_tokens(TRUNCATED));
+    let (mut ascii, mut other) = (0_usize, 0_usize);
+    let mut end = 0;
+    for (index, c) in text.char_indices() {
+        if c.is_ascii() {
+            ascii += 1;
+        } else {
+            other += 1;
+        }
+        if ascii.div_ceil(4) + other > room {
+            break;
+        }
+        end = index + c.len_utf8();
+    }
+    format!("{}{TRUNCATED}", text[..end].trim_end())
+}
+
+/// Cuts the text of `message` so the whole of it fits in `tokens`, leaving
+/// its images alone.
+fn truncate_message(message: &mut ChatMessage, tokens: usize) {
+    let text_tokens = estimate_tokens(message.text());
+    let room = tokens.saturating_sub(message_tokens(message) - text_tokens);
+    match &mut message.content {
+        Content::Text(text) => *text = truncate(text, room),
+        Content::Parts(parts) => {
+            for part in parts {
+                if let ContentPart::Text { text } = part {
+                    *text = truncate(text, room);
+                }
+            }
+        }
+    }
+}
+
+/// Hands out `pool` tokens between things that would take `wants` tokens
+/// each. Those that want less than an even share take only what they want,
+/// leaving the rest to the bigger ones, so the pool is handed out from the
+/// smallest up. Anything that would get less than `min` gets nothing, and its
+/// share goes to the others.
+fn share_out(wants: &[usize], mut pool: usize, min: usize) -> Vec<usize> {
+    let mut by_size: Vec<usize> = (0..wants.len()).collect();
+    by_size.sort_by_key(|&index| wants[index]);
+    let mut rooms = vec![0; wants.len()];
+    for (handed_out, &index) in by_size.iter().enumerate() {
+        let room = wants[index].min(pool / (by_size.len() - handed_out));
+        if room >= min {
+            rooms[index] = room;
+            pool -= room;
+        }
+    }
+    rooms
+}
+
+/// What is left of `budget` once `messages` are sent.
+pub(super) fn room_left(messages: &[ChatMessage], budget: usize) -> usize {
+    budget.saturating_sub(messages.iter().map(message_tokens).sum())
+}
+
+/// Whether there is still room for a round of tool calls after `messages`.
+pub(super) fn has_room_for_tools(messages: &[ChatMessage], budget: usize) -> bool {
+    room_left(messages, budget) >= MIN_TOOL_TOKENS
+}
+
+/// Cuts the `results` of a round of tool calls so they fit in what is left
+/// of `budget` after the messages `sent` so far, sharing it between them.
+pub(super) fn fit_tool_results(sent: &[ChatMessage], results: &mut [ChatMessage], budget: usize) {
+    let wants: Vec<usize> = results.iter().map(message_tokens).collect();
+    let rooms = share_out(&wants, room_left(sent, budget), 0);
+    for (result, room) in results.iter_mut().zip(rooms) {
+        if message_tokens(result) > room {
+            truncate_message(result, room);
+        }
+    }
+}
+
+fn link_message(url: &str, content: &str) -> ChatMessage {
+    ChatMessage::new(
+        "system",
+        format!("Contents of the link {url} (converted to markdown):\n\n{content}"),
+    )
+}
+
+/// How many tokens the prompt may take with `provider`: its context window
+/// minus the [`SAFETY_MARGIN`], room for the reply and the tool definitions.
+pub(super) fn prompt_budget(provider: &Provider) -> usize {
+    let reply = provider.max_tokens.unwrap_or(REPLY_TOKENS) as usize;
+    let context = provider.context_tokens as usize;
+    let mut budget = (context - context / SAFETY_MARGIN).saturating_sub(reply);
+    if provider.tools {
+        budget = budget.saturating_sub(estimate_tokens(&tools::definitions().to_string()));
+    }
+    budget
+}
+
+/// Everything we could tell the model, before it is cut down to fit its
+/// context window.
+pub(super) struct Prompt {
+    /// The system prompts, always sent but cut short if they are too long.
+    pub(super) system: Vec<ChatMessage>,
+    pub(super) links: Vec<LinkContext>,
+    /// The reply chain or thread history, oldest first.
+    pub(super) context: Vec<ChatMessage>,
+    pub(super) trigger: ChatMessage,
+}
+
+impl Prompt {
+    /// Fits the prompt into `budget` tokens. The system prompts share up to a
+    /// quarter of it, so a long custom prompt or emote list can't crowd out
+    /// the conversation, and the trigger goes next, both cut short if they
+    /// have to be. The links then get up to half of what is left, and the
+    /// context fills the rest from the newest message back, dropping the
+    /// oldest ones.
+    pub(super) fn assemble(self, budget: usize) -> Vec<ChatMessage> {
+        let wants: Vec<usize> = self.system.iter().map(message_tokens).collect();
+        let rooms = share_out(&wants, budget / 4, 0);
+        let mut messages = Vec::new();
+        for (mut message, room) in self.system.into_iter().zip(rooms) {
+            if message_tokens(&message) > room {
+                truncate_message(&mut message, room);
+            }
+            messages.push(message);
+        }
+        let mut remaining = room_left(&messages, budget);
+
+        let mut trigger = self.trigger;
+        if message_tokens(&trigger) > remaining {
+            truncate_message(&mut trigger, remaining);
+        }
+        remaining = remaining.saturating_sub(message_tokens(&trigger));
+
+        let framing: Vec<usize> = self
+            .links
+            .iter()
+            .map(|link| message_tokens(&link_message(&link.url, "")))
+            .collect();
+        let wants: Vec<usize> = self
+            .links
+            .iter()
+            .zip(&framing)
+            .map(|(link, framing)| estimate_tokens(&link.content) + framing)
+            .collect();
+        // Links need room for their framing on top of the least worth
+        // sending.
+        let min = framing.iter().max().copied().unwrap_or_default() + MIN_LINK_TOKENS;
+        let rooms = share_out(&wants, remaining / 2, min);
+        for ((link, room), framing) in self.links.iter().zip(rooms).zip(framing) {
+            if room > 0 {
+                let message = link_message(&link.url, &truncate(&link.content, room - framing));
+                remaining = remaining.saturating_sub(message_tokens(&message));
+                messages.push(message);
+            }
+        }
+
+        let mut kept = Vec::new();
+        for message in self.context.into_iter().rev() {
+            let tokens = message_tokens(&message);
+            if tokens > remaining {
+                break;
+            }
+            remaining -= tokens;
+            kept.push(message);
+        }
+        messages.extend(kept.into_iter().rev());
+
+        messages.push(trigger);
+        messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(text: &str) -> ChatMessage {
+        ChatMessage::new("user", text.to_string())
+    }
+
+    #[test]
+    fn estimates_and_truncates() {
+        assert_eq!(estimate_tokens("abcdefgh"), 2);
+        assert_eq!(estimate_tokens("日本語"), 3);
+
+        let long = "word ".repeat(1000);
+        let cut = truncate(&long, 100);
+        assert!(cut.ends_with(TRUNCATED));
+        assert!(estimate_tokens(&cut) <= 100);
+        assert_eq!(truncate("short", 100), "short");
+    }
+
+    #[test]
+    fn drops_the_oldest_context_first() {
+        let prompt = Prompt {
+            system: vec![ChatMessage::new("system", "be nice".to_string())],
+            links: Vec::new(),
+            context: vec![user(&"a".repeat(400)), user(&"b".repeat(400))],
+            trigger: user("question"),
+        };
+        // Room for the system prompt, the trigger and one 100 token message.
+        let messages = prompt.assemble(130);
+
+        let texts: Vec<&str> = messages.iter().map(ChatMessage::text).collect();
+        assert_eq!(texts, ["be nice", "b".repeat(400).as_str(), "question"]);
+    }
+
+    #[test]
+    fn links_share_half_of_what_is_left() {
+        let prompt = Prompt {
+            system: Vec::new(),
+            links: vec![
+                LinkContext {
+                    url: "https://example.com/long".to_string(),
+                    content: "x".repeat(40_000),
+                },
+                LinkContext {
+                    url: "https://example.com/short".to_string(),
+                    content: "y".repeat(1200),
+                },
+            ],
+            context: Vec::new(),
+            trigger: user("what do these say"),
+        };
+        let messages = prompt.assemble(4000);
+
+        // The short link fits whole and the long one gets the rest of the
+        // half.
+        assert_eq!(messages.len(), 3);
+        assert!(messages[0].text().ends_with(TRUNCATED));
+        assert!(messages[1].text().ends_with(&"y".repeat(1200)));
+        let links: usize = messages[..2].iter().map(message_tokens).sum();
+        assert!(links <= 2000);
+        assert!(links > 1900);
+    }
+
+    #[test]
+    fn system_prompts_share_a_quarter() {
+        let prompt = Prompt {
+            system: vec![
+                ChatMessage::new("system", "be nice".to_string()),
+                ChatMessage::new("system", ":emote:\n".repeat(2000)),
+            ],
+            links: Vec::new(),
+            context: Vec::new(),
+            trigger: user("question"),
+        };
+        let messages = prompt.assemble(4000);
+
+        assert_eq!(messages[0].text(), "be nice");
+        assert!(messages[1].text().ends_with(TRUNCATED));
+        let system: usize = messages[..2].iter().map(message_tokens).sum();
+        assert!(system <= 1000);
+        assert_eq!(messages[2].text(), "question");
+    }
+
+    #[test]
+    fn tool_results_fit_what_is_left() {
+        let sent = vec![user(&"a".repeat(2000))];
+        let mut results = vec![
+            ChatMessage::new("tool", "[1] short".to_string()),
+            ChatMessage::new("tool", "x".repeat(40_000)),
+        ];
+        fit_tool_results(&sent, &mut results, 1000);
+
+        assert_eq!(results[0].text(), "[1] short");
+        assert!(results[1].text().ends_with(TRUNCATED));
+        assert!(room_left(&sent, 1000) >= results.iter().map(message_tokens).sum());
+        assert!(!has_room_for_tools(&sent, 800));
+        assert!(has_room_for_tools(&sent, 1100));
+    }
+}
@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use poise::serenity_prelude::Message;
+
+use crate::config::GrokLimits;
+
+/// Full buckets carry no information, so they are forgotten once there are
+/// this many.
+const MAX_BUCKETS: usize = 10_000;
+
+static LIMITER: LazyLock<Mutex<RateLimiter>> = LazyLock::new(Mutex::default);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Scope {
+    User,
+    Channel,
+    Guild,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Limit {
+    burst: u32,
+    per_hour: u32,
+}
+
+impl Limit {
+    /// Tokens added back per second.
+    fn rate(self) -> f64 {
+        f64::from(self.per_hour) / 3600.0
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    limit: Limit,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.rate()).min(f64::from(self.limit.burst));
+        self.updated = now;
+    }
+
+    /// How long until the bucket has a token to give.
+    fn wait(&self) -> Duration {
+        if self.tokens >= 1.0 {
+            Duration::ZERO
+        } else if self.limit.per_hour == 0 {
+            Duration::MAX
+        } else {
+            Duration::from_secs_f64((1.0 - self.tokens) / self.limit.rate())
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.tokens >= f64::from(self.limit.burst)
+    }
+}
+
+#[derive(Debug, Default)]
+struct RateLimiter {
+    buckets: HashMap<(Scope, u64), Bucket>,
+}
+
+impl RateLimiter {
+    /// Takes a token from each of the buckets, or from none of them if one is
+    /// empty, in which case this is how long until all of them have one.
+    fn acquire(&mut self, keys: &[(Scope, u64, Limit)], now: Instant) -> Result<(), Duration> {
+        if self.buckets.len() >= MAX_BUCKETS {
+            self.buckets.retain(|_, bucket| {
+                bucket.refill(now);
+                !bucket.is_full()
+            });
+        }
+
+        let mut wait = Duration::ZERO;
+        for &(scope, id, limit) in keys {
+            let bucket = self.buckets.entry((scope, id)).or_insert(Bucket {
+                tokens: f64::from(limit.burst),
+                updated: now,
+                limit,
+            });
+            // The config is read fresh every time, so keep up with it.
+            bucket.limit = limit;
+            bucket.refill(now);
+            wait = wait.max(bucket.wait());
+        }
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+
+        for &(scope, id, _) in keys {
+            if let Some(bucket) = self.buckets.get_mut(&(scope, id)) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Counts `msg` against the limits of its author, channel and guild.
+/// Returns how long to wait if one of them is used up.
+pub(super) fn check(msg: &Message, limits: &GrokLimits) -> Result<(), Duration> {
+    let mut keys = vec![
+        (
+            Scope::User,
+            msg.author.id.get(),
+            Limit {
+                burst: limits.user_burst,
+                per_hour: limits.user_per_hour,
+            },
+        ),
+        (
+            Scope::Channel,
+            msg.channel_id.get(),
+            Limit {
+                burst: limits.channel_burst,
+                per_hour: limits.channel_per_hour,
+            },
+        ),
+    ];
+    if let Some(guild_id) = msg.guild_id {
+        keys.push((
+            Scope::Guild,
+            guild_id.get(),
+            Limit {
+                burst: limits.guild_burst,
+                per_hour: limits.guild_per_hour,
+            },
+        ));
+    }
+    keys.retain(|(_, _, limit)| limit.burst > 0);
+
+    LIMITER.lock().unwrap().acquire(&keys, Instant::now())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_refill_over_time() {
+        let mut limiter = RateLimiter::default();
+        let limit = Limit {
+            burst: 2,
+            per_hour: 60,
+        };
+        let keys = [(Scope::User, 1, limit), (Scope::Channel, 2, limit)];
+        let start = Instant::now();
+
+        assert!(limiter.acquire(&keys, start).is_ok());
+        assert!(limiter.acquire(&keys, start).is_ok());
+        let wait = limiter.acquire(&keys, start).unwrap_err();
+        assert_eq!(wait.as_secs(), 60);
+
+        // A token per minute comes back.
+        assert!(
+            limiter
+                .acquire(&keys, start + Duration::from_secs(61))
+                .is_ok()
+        );
+        assert!(
+            limiter
+                .acquire(&keys, start + Duration::from_secs(62))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn a_full_bucket_does_not_spend_the_others() {
+        let mut limiter = RateLimiter::default();
+        let roomy = Limit {
+            burst: 5,
+            per_hour: 60,
+        };
+        let tight = Limit {
+            burst: 1,
+            per_hour: 60,
+        };
+        let start = Instant::now();
+
+        assert!(limiter.acquire(&[(Scope::User, 1, tight)], start).is_ok());
+        assert!(
+            limiter
+                .acquire(&[(Scope::User, 1, tight), (Scope::Guild, 3, roomy)], start)
+                .is_err()
+        );
+        assert!((limiter.buckets[&(Scope::Guild, 3)].tokens - 5.0).abs() < f64::EPSILON);
+    }
+}
@@ -0,0 +1,975 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::sync::LazyLock;
+
+use color_eyre::eyre::{Result, eyre};
+use poise::serenity_prelude::{
+    AutoArchiveDuration, ChannelId, ChannelType, Context, CreateThread, Emoji, FullEvent, GuildId,
+    Message, MessageId, RoleId,
+};
+use regex::Regex;
+use reqwest::header::CONTENT_TYPE;
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+
+use crate::llm::{self, Access, Provider, ThreadEntry, Usage};
+use crate::types::Data;
+use attachments::Attached;
+use budget::Prompt;
+use stream::{LiveReply, SseDecoder};
+use tools::ToolCall;
+
+mod attachments;
+mod budget;
+mod limits;
+mod stream;
+mod tools;
+
+/// Trigger tokens that invoke the bot. `@gork` and `@gock` are common typos of
+/// `@grok`.
+const TRIGGERS: &[&str] = &["@grok", "@gork", "@gock"];
+/// How far up a reply chain we walk when gathering context.
+const MAX_CHAIN_DEPTH: usize = 25;
+/// Base endpoint for defuddle, which returns a readable markdown rendering of a
+/// page. The target URL (without its scheme) is appended to this.
+const DEFUDDLE_URL: &str = "https://defuddle.md/";
+/// How many links from a single message we fetch contents for.
+const MAX_LINKS: usize = 3;
+/// Cap on how many characters of fetched link content we keep. It is cut
+/// further when it doesn't fit in the model's context window.
+const MAX_LINK_CHARS: usize = 20000;
+/// How many rounds of tool calls the model gets before it has to answer.
+const MAX_TOOL_ROUNDS: usize = 3;
+/// Discord's limit for thread names.
+const THREAD_NAME_LIMIT: usize = 100;
+
+/// Matches URLs in a message so we can fetch their readable contents as context.
+static URL_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"https?://[^\s<>()\[\]]+").unwrap());
+
+/// Matches bare `:emote_name:` tokens in the model's reply so we can swap them
+/// for the real Discord emote token. Discord emote names are limited to word
+/// characters, so anything else (e.g. `:)`) is left untouched.
+static EMOTE_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r":(\w+):").unwrap());
+
+/// Matches full Discord emote tokens (`<:name:id>` or `<a:name:id>`) so we can
+/// strip the numeric IDs back down to bare `:name:` form before feeding message
+/// content to the model. This keeps emote IDs out of the model's context, so it
+/// only ever sees the human-readable name.
+static EMOTE_ID_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"<a?:(\w+):\d+>").unwrap());
+
+/// Used unless the config or the guild sets a system prompt of its own.
+const SYSTEM_PROMPT: &str = r#"
+You are blahaj, a helpful and concise assistant living inside a Discord
+chat. You are given a message that triggered you, along with the reply chain or thread it is part
+of for context (oldest first). User messages are prefixed with the author's display name followed by a
+colon, so you can tell who said what; do not prefix your own reply with a name. Answer the latest
+message concisely, unless otherwise specified. You may use markdown in your response, but NEVER use LaTeX or tables.
+You may use the server's custom emotes when it fits naturally. To use an emote you MUST write ONLY
+its name wrapped in colons, like `:emote_name:`, and nothing else; never write an emote in any other
+form. Only use names from the provided list; do not invent emotes.
+You may be given the contents of external links the user shared, supplied as system context; use them
+when relevant. Keep replies under 4000 characters.
+"#;
+
+/// Tells the model how to use the tools it is offered.
+const TOOLS_PROMPT: &str = "You can call tools to look things up instead of answering from memory. \
+Tool results that start with a number like [1] are shown as footnotes under your reply; cite them \
+with that number.";
+
+#[derive(Serialize)]
+struct ChatRequest<'a> {
+    model: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reasoning: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    stream: bool,
+    stream_options: StreamOptions,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tools: Option<serde_json::Value>,
+    messages: &'a [ChatMessage],
+}
+
+#[derive(Serialize)]
+struct StreamOptions {
+    /// Asks for token usage in the last event, since there's no response
+    /// body to put it in.
+    include_usage: bool,
+}
+
+#[derive(Serialize, Clone)]
+struct ChatMessage {
+    role: String,
+    content: Content,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<ToolCall>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<String>,
+}
+
+impl ChatMessage {
+    fn new(role: &str, content: String) -> Self {
+        Self {
+            role: role.to_string(),
+            content: Content::Text(content),
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+        }
+    }
+
+    /// The text of the message, without any images.
+    fn text(&self) -> &str {
+        match &self.content {
+            Content::Text(text) => text,
+            Content::Parts(parts) => parts
+                .iter()
+                .find_map(|part| match part {
+                    ContentPart::Text { text } => Some(text.as_str()),
+                    ContentPart::ImageUrl { .. } => None,
+                })
+                .unwrap_or_default(),
+        }
+    }
+
+    /// A user message with images, which only vision models understand.
+    fn with_images(text: String, images: &[String]) -> Self {
+        if images.is_empty() {
+            return Self::new("user", text);
+        }
+
+        let mut parts = vec![ContentPart::Text { text }];
+        parts.extend(images.iter().map(|url| ContentPart::ImageUrl {
+            image_url: ImageUrl { url: url.clone() },
+        }));
+        Self {
+            content: Content::Parts(parts),
+            ..Self::new("user", String::new())
+        }
+    }
+}
+
+/// Plain text, or text and images as separate parts.
+#[derive(Serialize, Clone)]
+#[serde(untagged)]
+enum Content {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+#[derive(Serialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+}
+
+#[derive(Serialize, Clone)]
+struct ImageUrl {
+    url: String,
+}
+
+#[derive(Deserialize)]
+struct ChatResponse {
+    choices: Vec<Choice>,
+    usage: Option<Usage>,
+}
+
+#[derive(Deserialize)]
+struct Choice {
+    message: ResponseMessage,
+}
+
+#[derive(Deserialize)]
+struct ResponseMessage {
+    content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<ToolCall>,
+}
+
+/// The readable contents of a link shared in a message, fetched via defuddle.
+struct LinkContext {
+    url: String,
+    content: String,
+}
+
+pub async fn handle(ctx: &Context, event: &FullEvent, data: &Data) -> Result<()> {
+    let FullEvent::Message { new_message } = event else {
+        return Ok(());
+    };
+
+    // Ignore messages from bots (including ourselves) to avoid loops.
+    if new_message.author.bot {
+        return Ok(());
+    }
+
+    let bot_id = ctx.cache.current_user().id;
+    // We only open threads in guilds.
+    let in_thread = new_message.guild_id.is_some()
+        && llm::is_grok_thread(new_message.channel_id.get()).unwrap_or_else(|err| {
+            error!("grok failed to look up thread: {err}");
+            false
+        });
+
+    // Respond when the message mentions the trigger anywhere, when it is a
+    // reply to one of our own messages, or to anything said in our threads.
+    let (prompt, explicit) = match strip_trigger(&new_message.content) {
+        Some(prompt) => (prompt, true),
+        None if in_thread || is_reply_to_bot(new_message, bot_id) => {
+            (new_message.content.trim().to_string(), false)
+        }
+        None => return Ok(()),
+    };
+
+    if !is_allowed(ctx, new_message).await {
+        return Ok(());
+    }
+
+    // Attachments are enough of a question, e.g. a screenshot of an error.
+    if prompt.is_empty() && new_message.attachments.is_empty() {
+        if explicit {
+            let _ = new_message
+                .reply(&ctx.http, "ask me something after `@grok`")
+                .await;
+        }
+        return Ok(());
+    }
+
+    // The quota goes first, so someone over it doesn't also use up what the
+    // rest of the channel and server may ask.
+    let grok_limits = &crate::config::get().grok.limits;
+    if let Some(quota) = grok_limits.user_daily_tokens
+        && llm::tokens_used_today(new_message.author.id.get()).unwrap_or_else(|err| {
+            warn!("grok failed to look up today's usage: {err}");
+            0
+        }) >= quota
+    {
+        let _ = new_message
+            .reply(
+                &ctx.http,
+                "you've used up today's @grok quota, it resets at midnight UTC",
+            )
+            .await;
+        return Ok(());
+    }
+    if let Err(wait) = limits::check(new_message, grok_limits) {
+        let again = chrono::Duration::from_std(wait)
+            .ok()
+            .and_then(|wait| chrono::Utc::now().checked_add_signed(wait));
+        let content = match again {
+            Some(again) => format!("slow down, ask again <t:{}:R>", again.timestamp() + 1),
+            None => "@grok can't take more questions here for now".to_string(),
+        };
+        let _ = new_message.reply(&ctx.http, content).await;
+        return Ok(());
+    }
+
+    // The thread the conversation is held in, if there is one. Opening one
+    // can fail (missing permissions, say), in which case we reply in place.
+    let opened = if !in_thread && explicit && should_open_thread(ctx, new_message).await {
+        open_thread(ctx, new_message, &prompt).await
+    } else {
+        None
+    };
+    let thread = opened.or(in_thread.then_some(new_message.channel_id));
+
+    // Keeps the typing indicator alive (re-broadcast every few seconds) until
+    // the first part of the reply is sent, so it persists across slow model
+    // responses.
+    let typing = thread
+        .unwrap_or(new_message.channel_id)
+        .start_typing(&ctx.http);
+
+    let provider = Provider::for_guild(new_message.guild_id);
+    let emojis = fetch_emojis(ctx, new_message).await;
+    // Our threads keep their history, so there is no reply chain to walk.
+    let (chain, history) = if in_thread {
+        let history = llm::thread_history(new_message.channel_id.get(), llm::MAX_THREAD_HISTORY)
+            .unwrap_or_else(|err| {
+                error!("grok failed to load thread history: {err}");
+                Vec::new()
+            });
+        (Vec::new(), history)
+    } else {
+        (collect_chain(ctx, new_message).await, Vec::new())
+    };
+    let link_contexts = fetch_link_contexts(data, &new_message.content).await;
+    // Newest first, so the trigger's attachments get first pick.
+    let mut with_attachments = vec![new_message];
+    with_attachments.extend(chain.iter().rev().filter(|msg| msg.author.id != bot_id));
+    let attached = attachments::gather(
+        data,
+        &with_attachments,
+        provider.vision,
+        crate::config::get().grok.attachment_bytes,
+    )
+    .await;
+    let mut conversation = build_messages(
+        provider.system_prompt.as_deref().unwrap_or(SYSTEM_PROMPT),
+        &chain,
+        new_message,
+        &prompt,
+        bot_id,
+        &emojis,
+        link_contexts,
+        &attached,
+    );
+
+    if let Some(thread) = thread {
+        // Only one of them is ever there: the history of a thread we already
+        // had, or the chain a new one was opened from.
+        conversation.context.splice(
+            0..0,
+            history
+                .into_iter()
+                .map(|entry| ChatMessage::new(&entry.role, entry.content)),
+        );
+
+        // A new thread starts out with the reply chain it was opened from.
+        for msg in &chain {
+            if let Some(entry) = context_message(msg, bot_id, attached.get(&msg.id)) {
+                remember(thread, msg.id, &entry);
+            }
+        }
+        remember(thread, new_message.id, &conversation.trigger);
+    }
+
+    if provider.tools {
+        // Right after the system prompt.
+        conversation
+            .system
+            .insert(1, ChatMessage::new("system", TOOLS_PROMPT.to_string()));
+    }
+    let messages = conversation.assemble(budget::prompt_budget(&provider));
+
+    let mut live = match opened {
+        Some(thread) => LiveReply::in_thread(ctx, thread, typing),
+        None => LiveReply::new(ctx, new_message, typing),
+    };
+    let mut usage = Usage::default();
+    let result =
+        request_completion(data, &provider, messages, &mut live, &emojis, &mut usage).await;
+    if let Err(err) = llm::record_usage(
+        new_message.guild_id.map(GuildId::get),
+        new_message.author.id.get(),
+        usage,
+    ) {
+        error!("grok failed to record usage: {err}");
+    }
+
+    match result {
+        Ok(reply) => {
+            let sent = live.finish(&substitute_emotes(&reply, &emojis)).await;
+            if let (Some(thread), Some(sent)) = (thread, sent) {
+                let entry = ChatMessage::new("assistant", strip_footnotes(&reply));
+                remember(thread, sent, &entry);
+            }
+        }
+        Err(err) => {
+            live.stop_typing();
+            eprintln!("grok request failed: {err}");
+            let content = format!("something went wrong talking to the model: {err}");
+            let _ = match opened {
+                Some(thread) => thread.say(&ctx.http, content).await,
+                None => new_message.reply(&ctx.http, content).await,
+            };
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether an explicit `@grok` in `msg` should open a thread: the guild has
+/// to want threads, and the message can't be in a thread already.
+async fn should_open_thread(ctx: &Context, msg: &Message) -> bool {
+    let Some(guild_id) = msg.guild_id else {
+        return false;
+    };
+    if !Access::load(guild_id.get()).is_ok_and(|access| access.threads) {
+        return false;
+    }
+
+    msg.channel_id
+        .to_channel(ctx)
+        .await
+        .ok()
+        .and_then(|channel| channel.guild())
+        .is_some_and(|channel| matches!(channel.kind, ChannelType::Text | ChannelType::News))
+}
+
+/// Opens a thread on `trigger` for the conversation, named after `prompt`.
+async fn open_thread(ctx: &Context, trigger: &Message, prompt: &str) -> Option<ChannelId> {
+    let guild_id = trigger.guild_id?;
+    let first_line = prompt.lines().next().unwrap_or_default().trim();
+    let name: String = if first_line.is_empty() {
+        "@grok".to_string()
+    } else {
+        first_line.chars().take(THREAD_NAME_LIMIT).collect()
+    };
+
+    let builder = CreateThread::new(name).auto_archive_duration(AutoArchiveDuration::OneDay);
+    let thread = match trigger
+        .channel_id
+        .create_thread_from_message(&ctx.http, trigger.id, builder)
+        .await
+    {
+        Ok(thread) => thread,
+        Err(err) => {
+            warn!("grok failed to open a thread: {err}");
+            return None;
+        }
+    };
+
+    if let Err(err) = llm::start_thread(thread.id.get(), guild_id.get()) {
+        error!("grok failed to store thread {}: {err}", thread.id);
+        return None;
+    }
+    Some(thread.id)
+}
+
+/// Adds a message to the history of one of our threads. Only the text is
+/// kept, images are seen the one time they are sent.
+fn remember(thread: ChannelId, message_id: MessageId, message: &ChatMessage) {
+    let entry = ThreadEntry {
+        role: message.role.clone(),
+        content: message.text().to_string(),
+    };
+    if let Err(err) = llm::record_thread_message(thread.get(), message_id.get(), &entry) {
+        error!("grok failed to store thread message: {err}");
+    }
+}
+
+/// Whether `@grok` may answer `msg`: in DMs if the config says so, and in
+/// guilds that turned it on, in an allowed channel, for a member with an
+/// allowed role. Checked before anything is fetched or sent to the model.
+async fn is_allowed(ctx: &Context, msg: &Message) -> bool {
+    let Some(guild_id) = msg.guild_id else {
+        return crate::config::get().grok.dms;
+    };
+
+    let access = match Access::load(guild_id.get()) {
+        Ok(access) => access,
+        Err(err) => {
+            error!("grok failed to load access for {guild_id}: {err}");
+            return false;
+        }
+    };
+    if !access.enabled {
+        return false;
+    }
+
+    let roles: Vec<u64> = msg
+        .member
+        .as_ref()
+        .map(|member| member.roles.iter().copied().map(RoleId::get).collect())
+        .unwrap_or_default();
+    if !access.allows_roles(&roles) {
+        return false;
+    }
+
+    let channel = msg.channel_id.get();
+    if access.allows_channel(channel, None) {
+        return true;
+    }
+    // Threads are allowed along with the channel they belong to.
+    let parent = msg
+        .channel_id
+        .to_channel(ctx)
+        .await
+        .ok()
+        .and_then(|channel| channel.guild())
+        .and_then(|channel| channel.parent_id)
+        .map(ChannelId::get);
+    access.allows_channel(channel, parent)
+}
+
+/// Returns the message content with any trigger token removed, or `None` if the
+/// message does not contain a trigger anywhere.
+fn strip_trigger(content: &str) -> Option<String> {
+    if !TRIGGERS.iter().any(|trigger| content.contains(trigger)) {
+        return None;
+    }
+    let mut stripped = content.to_string();
+    for trigger in TRIGGERS {
+        stripped = stripped.replace(trigger, "");
+    }
+    Some(stripped.trim().to_string())
+}
+
+/// Whether `msg` is a reply to a message authored by the bot.
+fn is_reply_to_bot(msg: &Message, bot_id: poise::serenity_prelude::UserId) -> bool {
+    msg.referenced_message
+        .as_ref()
+        .is_some_and(|parent| parent.author.id == bot_id)
+}
+
+/// Fetches the custom emotes for the guild the message was sent in. Returns an
+/// empty list in DMs or if the request fails (emotes are best-effort context).
+async fn fetch_emojis(ctx: &Context, msg: &Message) -> Vec<Emoji> {
+    let Some(guild_id) = msg.guild_id else {
+        return Vec::new();
+    };
+
+    match guild_id.emojis(&ctx.http).await {
+        Ok(emojis) => emojis,
+        Err(err) => {
+            eprintln!("grok failed to fetch emojis: {err}");
+            Vec::new()
+        }
+    }
+}
+
+/// Extracts the URLs from `content` and fetches a readable markdown rendering
+/// of each via defuddle, so the model can reason about pages it cannot
+/// otherwise see. When a message has more than [`MAX_LINKS`] links we keep the
+/// most recent (last) ones, since those are usually what the user is asking
+/// about. Failed fetches are skipped (best-effort context).
+async fn fetch_link_contexts(data: &Data, content: &str) -> Vec<LinkContext> {
+    // Collect unique URLs in the order they appear, then keep the last
+    // MAX_LINKS so we favour the most recently shared links.
+    let mut urls: Vec<&str> = Vec::new();
+    for m in URL_RE.find_iter(content) {
+        // The regex greedily grabs trailing sentence punctuation (e.g. the `.`
+        // in "see https://example.com."), which would corrupt the fetch URL.
+        let url = m.as_str().trim_end_matches(['.', ',', '!', '?', ';', ':']);
+        if !url.is_empty() && !urls.contains(&url) {
+            urls.push(url);
+        }
+    }
+    let start = urls.len().saturating_sub(MAX_LINKS);
+
+    let mut contexts = Vec::new();
+    for url in &urls[start..] {
+        match fetch_link(data, url).await {
+            Ok(content) => contexts.push(LinkContext {
+                url: (*url).to_string(),
+                content,
+            }),
+            Err(err) => eprintln!("grok failed to fetch link {url}: {err}"),
+        }
+    }
+
+    contexts
+}
+
+/// Fetches a single URL through defuddle and returns its markdown contents,
+/// truncated to [`MAX_LINK_CHARS`] characters.
+async fn fetch_link(data: &Data, url: &str) -> Result<String> {
+    // defuddle takes the target URL with its scheme stripped, appended to the base.
+    let stripped = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+        .unwrap_or(url);
+    let request_url = format!("{DEFUDDLE_URL}{stripped}");
+
+    let response = data.client.get(&request_url).send().await?;
+    if !response.status().is_success() {
+        return Err(eyre!("defuddle returned status {}", response.status()));
+    }
+
+    let text = response.text().await?;
+    let trimmed = text.trim();
+    if trimmed.is_empty() {
+        return Err(eyre!("defuddle returned empty content"));
+    }
+
+    Ok(trimmed.chars().take(MAX_LINK_CHARS).collect())
+}
+
+/// Replaces bare `:name:` emote tokens in `reply` with the real Discord token
+/// (`<:name:id>`, or `<a:name:id>` for animated emotes, both produced by
+/// [`Emoji`]'s `Display`) for every emote that exists in the guild. Tokens that
+/// don't match a known emote are left untouched, so ordinary text and unknown
+/// shortcodes pass through unchanged.
+fn substitute_emotes(reply: &str, emojis: &[Emoji]) -> String {
+    if emojis.is_empty() {
+        return reply.to_string();
+    }
+
+    let by_name: HashMap<&str, &Emoji> = emojis
+        .iter()
+        .map(|emoji| (emoji.name.as_str(), emoji))
+        .collect();
+
+    EMOTE_RE
+        .replace_all(reply, |caps: &regex::Captures<'_>| {
+            match by_name.get(&caps[1]) {
+                Some(emoji) => emoji.to_string(),
+                None => caps[0].to_string(),
+            }
+        })
+        .into_owned()
+}
+
+/// Strips Discord emote IDs out of message content, rewriting full
+/// `<:name:id>` / `<a:name:id>` tokens back to bare `:name:` form. We do this
+/// before feeding any message content to the model so it only ever sees the
+/// emote's name, never its numeric ID.
+fn strip_emote_ids(content: &str) -> String {
+    EMOTE_ID_RE
+        .replace_all(content, |caps: &regex::Captures<'_>| format!(":{}:", &caps[1]))
+        .into_owned()
+}
+
+/// Renders the available custom emotes as a system message listing each emote's
+/// name, instructing the model to reference them with bare `:name:` tokens
+/// ([`substitute_emotes`] swaps them for the real `<:name:id>` token before
+/// sending). Returns `None` if there are no emotes.
+fn emote_list(emojis: &[Emoji]) -> Option<String> {
+    if emojis.is_empty() {
+        return None;
+    }
+
+    let mut list = String::from(
+        "The server has these custom emotes available. To use one, write its name wrapped in colons (for example :name:):\n",
+    );
+    for emoji in emojis {
+        list.push_str(&format!("- :{}:\n", emoji.name));
+    }
+    Some(list)
+}
+
+/// Walks up the reply chain starting from (but not including) `start`,
+/// returning the referenced messages ordered oldest first.
+async fn collect_chain(ctx: &Context, start: &Message) -> Vec<Message> {
+    let mut chain = Vec::new();
+    let mut current = start.clone();
+
+    while chain.len() < MAX_CHAIN_DEPTH {
+        let Some(reference) = &current.message_reference else {
+            break;
+        };
+        let Some(message_id) = reference.message_id else {
+            break;
+        };
+
+        match current.channel_id.message(&ctx.http, message_id).await {
+            Ok(parent) => {
+                current = parent.clone();
+                chain.push(parent);
+            }
+            Err(_) => break,
+        }
+    }
+
+    chain.reverse();
+    chain
+}
+
+/// Gathers what the model is told: a system prompt, the reply chain as
+/// context, and the triggering message. User messages are prefixed with the
+/// author's username so the model knows who said what, and carry what was
+/// `attached` to them. [`Prompt::assemble`] fits it all into the model's
+/// context window.
+#[allow(clippy::too_many_arguments)]
+fn build_messages(
+    system_prompt: &str,
+    chain: &[Message],
+    trigger: &Message,
+    prompt: &str,
+    bot_id: poise::serenity_prelude::UserId,
+    emojis: &[Emoji],
+    link_contexts: Vec<LinkContext>,
+    attached: &HashMap<MessageId, Attached>,
+) -> Prompt {
+    let mut system = vec![ChatMessage::new("system", system_prompt.to_string())];
+    if let Some(list) = emote_list(emojis) {
+        system.push(ChatMessage::new("system", list));
+    }
+
+    Prompt {
+        system,
+        links: link_contexts,
+        context: chain
+            .iter()
+            .filter_map(|msg| context_message(msg, bot_id, attached.get(&msg.id)))
+            .collect(),
+        trigger: user_message(trigger, &strip_emote_ids(prompt), attached.get(&trigger.id)),
+    }
+}
+
+/// A message from the reply chain as the model sees it, `None` if there is
+/// nothing in it.
+fn context_message(
+    msg: &Message,
+    bot_id: poise::serenity_prelude::UserId,
+    attached: Option<&Attached>,
+) -> Option<ChatMessage> {
+    let raw = message_text(msg);
+    let content = strip_emote_ids(&strip_trigger(&raw).unwrap_or(raw));
+    if content.trim().is_empty() && attached.is_none() {
+        return None;
+    }
+
+    if msg.author.id == bot_id {
+        Some(ChatMessage::new("assistant", strip_footnotes(&content)))
+    } else {
+        Some(user_message(msg, &content, attached))
+    }
+}
+
+/// Someone's message, prefixed with their name, with its text files inlined
+/// and its images as parts of their own.
+fn user_message(msg: &Message, content: &str, attached: Option<&Attached>) -> ChatMessage {
+    let mut text = format!("{}: {content}", display_name(msg));
+    let Some(attached) = attached else {
+        return ChatMessage::new("user", text);
+    };
+
+    for (name, contents) in &attached.files {
+        let _ = write!(
+            text,
+            "\n\nAttached file `{name}`:\n```\n{}\n```",
+            contents.trim_end()
+        );
+    }
+    ChatMessage::with_images(text, &attached.images)
+}
+
+/// Drops the tool footnotes from one of our own replies, they are there for
+/// the people reading it rather than the model.
+fn strip_footnotes(content: &str) -> String {
+    content
+        .lines()
+        .filter(|line| !line.starts_with("-# ["))
+        .collect::<Vec<&str>>()
+        .join("\n")
+        .trim_end()
+        .to_string()
+}
+
+/// The textual content of a message for context purposes. Falls back to the
+/// embed descriptions when the plain content is empty, since blahaj's own long
+/// replies live in an embed description rather than the message body.
+fn message_text(msg: &Message) -> String {
+    if !msg.content.trim().is_empty() {
+        return msg.content.clone();
+    }
+
+    msg.embeds
+        .iter()
+        .filter_map(|embed| embed.description.as_deref())
+        .collect::<Vec<&str>>()
+        .join("\n\n")
+}
+
+/// The name to attribute a message to: the per-guild nickname if present,
+/// otherwise the global display name, otherwise the username.
+fn display_name(msg: &Message) -> &str {
+    msg.member
+        .as_ref()
+        .and_then(|member| member.nick.as_deref())
+        .or(msg.author.global_name.as_deref())
+        .unwrap_or(&msg.author.name)
+}
+
+/// Asks the model for a reply, running the tools it calls along the way.
+/// Footnotes for the tool results it used are added to the end of the reply.
+/// The tokens used along the way are added to `usage`, even if it fails.
+async fn request_completion(
+    data: &Data,
+    provider: &Provider,
+    mut messages: Vec<ChatMessage>,
+    live: &mut LiveReply<'_>,
+    emojis: &[Emoji],
+    usage: &mut Usage,
+) -> Result<String> {
+    let mut footnotes: Vec<String> = Vec::new();
+    let budget = budget::prompt_budget(provider);
+
+    for round in 0..=MAX_TOOL_ROUNDS {
+        // The last round doesn't offer tools, so the model has to answer, and
+        // neither does one without room left for what they return.
+        let offer_tools = provider.tools
+            && round < MAX_TOOL_ROUNDS
+            && budget::has_room_for_tools(&messages, budget);
+        let (content, mut tool_calls) =
+            complete(data, provider, &messages, offer_tools, live, emojis, usage).await?;
+        // Servers that don't stream hand over every call at once.
+        tool_calls.truncate(tools::MAX_TOOL_CALLS);
+
+        if tool_calls.is_empty() {
+            let mut reply = content.trim().to_string();
+            if reply.is_empty() {
+                return Err(eyre!("model returned empty content"));
+            }
+            if !footnotes.is_empty() {
+                reply.push('\n');
+                for (index, footnote) in footnotes.iter().enumerate() {
+                    let _ = write!(reply, "\n-# [{}] {footnote}", index + 1);
+                }
+            }
+            return Ok(reply);
+        }
+
+        let mut assistant = ChatMessage::new("assistant", content);
+        assistant.tool_calls.clone_from(&tool_calls);
+        messages.push(assistant);
+
+        let mut results = Vec::new();
+        for call in tool_calls {
+            let output = tools::run(data, &call).await;
+            let mut content = output.content;
+            if let Some(footnote) = output.footnote {
+                footnotes.push(footnote);
+                content = format!("[{}] {content}", footnotes.len());
+            }
+            if let Some(attachment) = output.attachment {
+                live.attach(attachment);
+            }
+
+            let mut result = ChatMessage::new("tool", content);
+            result.tool_call_id = Some(call.id);
+            results.push(result);
+        }
+        budget::fit_tool_results(&messages, &mut results, budget);
+        messages.extend(results);
+    }
+
+    Err(eyre!("model kept calling tools instead of answering"))
+}
+
+/// Makes one request to the model, showing its reply in `live` as it
+/// streams in. Servers that don't stream get the whole reply shown at once by
+/// the caller. Returns the reply along with the tools the model wants called.
+async fn complete(
+    data: &Data,
+    provider: &Provider,
+    messages: &[ChatMessage],
+    offer_tools: bool,
+    live: &mut LiveReply<'_>,
+    emojis: &[Emoji],
+    usage: &mut Usage,
+) -> Result<(String, Vec<ToolCall>)> {
+    let body = ChatRequest {
+        model: &provider.model,
+        reasoning: provider.reasoning.as_deref(),
+        temperature: provider.temperature,
+        max_tokens: provider.max_tokens,
+        stream: true,
+        stream_options: StreamOptions {
+            include_usage: true,
+        },
+        tools: offer_tools.then(tools::definitions),
+        messages,
+    };
+
+    // A guild's endpoint is checked again as what its name resolves to may
+    // have changed since it was set.
+    let guild_client;
+    let client = if provider.guild_endpoint {
+        guild_client = llm::check_guild_endpoint(&crate::config::get().grok, &provider.endpoint)
+            .await?
+            .client()?;
+        &guild_client
+    } else {
+        &data.client
+    };
+    let mut request = client.post(provider.completions_url()).json(&body);
+    if let Some(key) = &provider.api_key {
+        request = request.bearer_auth(key);
+    }
+    let mut response = request.send().await?;
+
+    if !response.status().is_success() {
+        return Err(eyre!("model returned status {}", response.status()));
+    }
+
+    let streaming = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/event-stream"));
+
+    if !streaming {
+        let parsed: ChatResponse = response.json().await?;
+        usage.add(parsed.usage.unwrap_or_default());
+        let message = parsed
+            .choices
+            .into_iter()
+            .next()
+            .map(|choice| choice.message)
+            .ok_or_else(|| eyre!("model returned no choices"))?;
+        return Ok((message.content.unwrap_or_default(), message.tool_calls));
+    }
+
+    let mut decoder = SseDecoder::default();
+    let mut content = String::new();
+    let mut tool_calls = Vec::new();
+    'stream: while let Some(chunk) = response.chunk().await? {
+        for event in decoder.push(&chunk) {
+            if event == "[DONE]" {
+                break 'stream;
+            }
+            let (delta, event_usage) = stream::parse_event(&event)?;
+            usage.add(event_usage.unwrap_or_default());
+            if let Some(text) = delta.content {
+                content.push_str(&text);
+            }
+            tools::merge_deltas(&mut tool_calls, delta.tool_calls);
+        }
+        live.update(&substitute_emotes(&content, emojis)).await;
+    }
+
+    Ok((content, tool_calls))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn emoji(name: &str, id: u64, animated: bool) -> Emoji {
+        serde_json::from_value(serde_json::json!({
+            "name": name,
+            "id": id.to_string(),
+            "animated": animated,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn substitutes_known_emotes_and_leaves_others_alone() {
+        let emojis = [emoji("blahaj", 123, false), emoji("dance", 456, true)];
+
+        // Known static and animated emotes become real Discord tokens; an
+        // unknown shortcode and a plain colon emoticon pass through untouched.
+        let reply = "hi :blahaj: time to :dance: :unknown: :)";
+        let out = substitute_emotes(reply, &emojis);
+
+        assert_eq!(out, "hi <:blahaj:123> time to <a:dance:456> :unknown: :)");
+    }
+
+    #[test]
+    fn no_emotes_returns_reply_unchanged() {
+        assert_eq!(substitute_emotes(":blahaj: hi", &[]), ":blahaj: hi");
+    }
+
+    #[test]
+    fn strips_emote_ids_to_bare_names() {
+        // Both static and animated full tokens collapse to bare :name:, while
+        // existing bare shortcodes and plain text are left untouched.
+        let input = "hi <:blahaj:123> and <a:dance:456> plus :already: text";
+        assert_eq!(
+            strip_emote_ids(input),
+            "hi :blahaj: and :dance: plus :already: text"
+        );
+    }
+
+    #[test]
+    fn images_become_content_parts() {
+        let plain = serde_json::to_value(ChatMessage::with_images("hi".to_string(), &[])).unwrap();
+        assert_eq!(plain["content"], "hi");
+
+        let images = ["data:image/png;base64,AAAA".to_string()];
+        let message = serde_json::to_value(ChatMessage::with_images("look".to_string(), &images));
+        assert_eq!(
+            message.unwrap()["content"],
+            serde_json::json!([
+                { "type": "text", "text": "look" },
+                { "type": "image_url", "image_url": { "url": "data:image/png;base64,AAAA" } },
+            ])
+        );
+    }
+}
@@ -0,0 +1,323 @@
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre::{Result, eyre};
+use poise::serenity_prelude::{
+    ChannelId, Context, CreateAttachment, CreateMessage, EditMessage, Message, MessageId, Typing,
+};
+use serde::Deserialize;
+use tracing::warn;
+
+use super::tools::ToolCallDelta;
+use crate::llm::Usage;
+
+/// Discord allows about five edits of a message every five seconds, and a
+/// long reply also sends follow-up messages in between.
+const EDIT_INTERVAL: Duration = Duration::from_millis(1500);
+/// Discord's limit for the content of a regular message.
+const MESSAGE_LIMIT: usize = 2000;
+/// How many messages a reply may take, so a model that doesn't stop doesn't
+/// flood the channel.
+const MAX_PAGES: usize = 3;
+/// Ends the last page of a reply that didn't fit.
+const CUT_SHORT: &str = "\n\n*(reply cut short)*";
+
+#[derive(Deserialize)]
+struct StreamChunk {
+    #[serde(default)]
+    choices: Vec<StreamChoice>,
+    error: Option<serde_json::Value>,
+    usage: Option<Usage>,
+}
+
+#[derive(Deserialize)]
+struct StreamChoice {
+    #[serde(default)]
+    delta: Delta,
+}
+
+/// What one streamed completion event adds to the reply.
+#[derive(Deserialize, Default)]
+pub(super) struct Delta {
+    pub(super) content: Option<String>,
+    #[serde(default)]
+    pub(super) tool_calls: Vec<ToolCallDelta>,
+}
+
+/// Splits a `text/event-stream` body into the `data` of each event, however
+/// the body happens to be chunked.
+#[derive(Default)]
+pub(super) struct SseDecoder {
+    buffer: Vec<u8>,
+    data: String,
+}
+
+impl SseDecoder {
+    /// Feeds in the next chunk of the body, returning the events it completed.
+    pub(super) fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\r', '\n']);
+
+            if line.is_empty() {
+                if !self.data.is_empty() {
+                    events.push(std::mem::take(&mut self.data));
+                }
+            } else if let Some(value) = line.strip_prefix("data:") {
+                if !self.data.is_empty() {
+                    self.data.push('\n');
+                }
+                self.data.push_str(value.strip_prefix(' ').unwrap_or(value));
+            }
+            // Comments like `: keep-alive` and the other fields are of no use
+            // to us.
+        }
+        events
+    }
+}
+
+/// Reads a streamed completion event. Usage only comes with the last one.
+pub(super) fn parse_event(event: &str) -> Result<(Delta, Option<Usage>)> {
+    let chunk: StreamChunk = serde_json::from_str(event)?;
+    if let Some(error) = chunk.error {
+        return Err(eyre!("model stream failed: {error}"));
+    }
+
+    let delta = chunk
+        .choices
+        .into_iter()
+        .next()
+        .map(|choice| choice.delta)
+        .unwrap_or_default();
+    Ok((delta, chunk.usage))
+}
+
+/// Splits `text` into at most `max_pages` pages that each fit in a message,
+/// breaking at a line end when there is one in the second half of the page.
+/// Whatever doesn't fit is cut off the last page.
+fn paginate(text: &str, limit: usize, max_pages: usize) -> Vec<String> {
+    let mut pages = Vec::new();
+    let mut rest = text;
+
+    while rest.chars().count() > limit {
+        if pages.len() + 1 == max_pages {
+            let room = limit - CUT_SHORT.chars().count();
+            let kept: String = rest.chars().take(room).collect();
+            pages.push(format!("{}{CUT_SHORT}", kept.trim_end()));
+            return pages;
+        }
+
+        let cut = rest
+            .char_indices()
+            .nth(limit)
+            .map_or(rest.len(), |(index, _)| index);
+        let cut = match rest[..cut].rfind('\n') {
+            Some(newline) if rest[..newline].chars().count() >= limit / 2 => newline + 1,
+            _ => cut,
+        };
+        pages.push(rest[..cut].trim_end().to_string());
+        rest = &rest[cut..];
+    }
+    if !rest.trim().is_empty() {
+        pages.push(rest.to_string());
+    }
+    pages
+}
+
+/// A reply that is edited in place as the model streams it in, rolling over
+/// into follow-up messages once it outgrows one.
+pub(super) struct LiveReply<'a> {
+    ctx: &'a Context,
+    channel: ChannelId,
+    /// What the first message replies to, if anything.
+    reply_to: Option<&'a Message>,
+    typing: Option<Typing>,
+    /// The messages sent so far, along with what they currently say.
+    sent: Vec<(Message, String)>,
+    last_flush: Option<Instant>,
+    /// Files sent along once the reply is done.
+    attachments: Vec<CreateAttachment>,
+}
+
+impl<'a> LiveReply<'a> {
+    /// A reply to `trigger`. `typing` is stopped once the first message is
+    /// out.
+    pub(super) fn new(ctx: &'a Context, trigger: &'a Message, typing: Typing) -> Self {
+        Self {
+            ctx,
+            channel: trigger.channel_id,
+            reply_to: Some(trigger),
+            typing: Some(typing),
+            sent: Vec::new(),
+            last_flush: None,
+            attachments: Vec::new(),
+        }
+    }
+
+    /// A reply posted as the first message of a thread just opened for it.
+    pub(super) fn in_thread(ctx: &'a Context, thread: ChannelId, typing: Typing) -> Self {
+        Self {
+            ctx,
+            channel: thread,
+            reply_to: None,
+            typing: Some(typing),
+            sent: Vec::new(),
+            last_flush: None,
+            attachments: Vec::new(),
+        }
+    }
+
+    /// Shows `text` so far, unless the last update was too recent.
+    pub(super) async fn update(&mut self, text: &str) {
+        if text.trim().is_empty()
+            || self
+                .last_flush
+                .is_some_and(|flushed| flushed.elapsed() < EDIT_INTERVAL)
+        {
+            return;
+        }
+        self.flush(text).await;
+    }
+
+    pub(super) fn attach(&mut self, attachment: CreateAttachment) {
+        self.attachments.push(attachment);
+    }
+
+    /// Shows the final `text`, followed by the attachments. Returns the ID of
+    /// the first message of the reply, if any made it out.
+    pub(super) async fn finish(mut self, text: &str) -> Option<MessageId> {
+        let pages = self.flush(text).await;
+        self.stop_typing();
+
+        // Text streamed before a tool call can take more pages than the
+        // final answer does, which would leave them showing stale text.
+        if pages > 0 {
+            for (message, _) in self.sent.split_off(pages.min(self.sent.len())) {
+                if let Err(err) = message.delete(&self.ctx.http).await {
+                    warn!("grok failed to delete a leftover page: {err}");
+                }
+            }
+        }
+
+        if !self.attachments.is_empty() {
+            let mut builder = CreateMessage::new().add_files(std::mem::take(&mut self.attachments));
+            if let Some(reference) = self.reference() {
+                builder = builder.reference_message(reference);
+            }
+            if let Err(err) = self.channel.send_message(&self.ctx.http, builder).await {
+                warn!("grok failed to send attachments: {err}");
+            }
+        }
+
+        self.sent.first().map(|(message, _)| message.id)
+    }
+
+    /// What the next message should reply to.
+    fn reference(&self) -> Option<&Message> {
+        self.sent
+            .last()
+            .map(|(message, _)| message)
+            .or(self.reply_to)
+    }
+
+    pub(super) fn stop_typing(&mut self) {
+        if let Some(typing) = self.typing.take() {
+            typing.stop();
+        }
+    }
+
+    /// Edits and sends messages until they show `text`, returning how many
+    /// pages that takes.
+    async fn flush(&mut self, text: &str) -> usize {
+        self.last_flush = Some(Instant::now());
+
+        let pages = paginate(text.trim(), MESSAGE_LIMIT, MAX_PAGES);
+        let count = pages.len();
+        for (index, page) in pages.into_iter().enumerate() {
+            if let Some((message, shown)) = self.sent.get_mut(index) {
+                if *shown == page {
+                    continue;
+                }
+                match message
+                    .edit(&self.ctx.http, EditMessage::new().content(&page))
+                    .await
+                {
+                    Ok(()) => *shown = page,
+                    Err(err) => warn!("grok failed to edit reply: {err}"),
+                }
+                continue;
+            }
+
+            // Each follow-up replies to the page before it, so the whole
+            // answer is part of the reply chain when someone answers it.
+            let mut builder = CreateMessage::new().content(&page);
+            if let Some(reference) = self.reference() {
+                builder = builder.reference_message(reference);
+            }
+            match self.channel.send_message(&self.ctx.http, builder).await {
+                Ok(message) => {
+                    self.stop_typing();
+                    self.sent.push((message, page));
+                }
+                Err(err) => {
+                    warn!("grok failed to send reply: {err}");
+                    break;
+                }
+            }
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_events_split_across_chunks() {
+        let mut decoder = SseDecoder::default();
+        assert!(decoder.push(b": keep-alive\n\ndata: {\"a\"").is_empty());
+        assert_eq!(decoder.push(b":1}\r\n\r\ndata: [DONE]\n"), ["{\"a\":1}"]);
+        assert_eq!(decoder.push(b"\n"), ["[DONE]"]);
+    }
+
+    #[test]
+    fn reads_events() {
+        let event = r#"{"choices":[{"index":0,"delta":{"content":"hi"}}]}"#;
+        assert_eq!(parse_event(event).unwrap().0.content.as_deref(), Some("hi"));
+        assert!(parse_event(r#"{"error":{"message":"overloaded"}}"#).is_err());
+
+        let event = r#"{"choices":[{"delta":{"tool_calls":[{"index":0,"id":"call_1"}]}}]}"#;
+        assert_eq!(parse_event(event).unwrap().0.tool_calls.len(), 1);
+
+        let event = r#"{"choices":[],"usage":{"prompt_tokens":12,"completion_tokens":3}}"#;
+        let (delta, usage) = parse_event(event).unwrap();
+        assert_eq!(delta.content, None);
+        assert_eq!(usage.unwrap().total(), 15);
+    }
+
+    #[test]
+    fn paginates_at_line_ends() {
+        let text = format!("{}\n{}", "a".repeat(1500), "b".repeat(1000));
+        let pages = paginate(&text, 2000, 3);
+        assert_eq!(pages, ["a".repeat(1500), "b".repeat(1000)]);
+
+        let pages = paginate(&"c".repeat(4500), 2000, 3);
+        assert_eq!(
+            pages.iter().map(String::len).collect::<Vec<_>>(),
+            [2000, 2000, 500]
+        );
+        assert_eq!(paginate("short", 2000, 3), ["short"]);
+    }
+
+    #[test]
+    fn cuts_off_what_does_not_fit() {
+        let pages = paginate(&"d".repeat(7000), 2000, 3);
+        assert_eq!(pages.len(), 3);
+        assert!(pages[2].ends_with(CUT_SHORT));
+        assert!(pages[2].chars().count() <= 2000);
+    }
+}
@@ -0,0 +1,393 @@
+use color_eyre::eyre::{Result, eyre};
+use poise::serenity_prelude::CreateAttachment;
+use rusqlite::OptionalExtension;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::fmt::Write as _;
+
+use crate::commands::misc::{crates, typst};
+use crate::commands::nix::nixpkgs::{
+    branch_statuses, format_branch_statuses, tracked_branches_for,
+};
+use crate::github::{Conditional, GitHub};
+use crate::nixpkgs_db::{self, Channel, search};
+use crate::types::Data;
+
+/// Cap on how many characters of a tool's output we feed the model.
+const MAX_OUTPUT_CHARS: usize = 4000;
+/// How many tools the model may call at once. Anything past it is dropped,
+/// including streamed pieces with a higher index, which would otherwise make
+/// us allocate as many calls as the index says.
+pub(super) const MAX_TOOL_CALLS: usize = 5;
+
+/// A function call the model asked for, in the shape the API uses both ways.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(super) struct ToolCall {
+    pub(super) id: String,
+    #[serde(rename = "type")]
+    kind: String,
+    pub(super) function: FunctionCall,
+}
+
+impl Default for ToolCall {
+    fn default() -> Self {
+        Self {
+            id: String::new(),
+            kind: "function".to_string(),
+            function: FunctionCall::default(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub(super) struct FunctionCall {
+    pub(super) name: String,
+    /// JSON encoded, and not necessarily valid JSON at that.
+    pub(super) arguments: String,
+}
+
+/// A piece of a tool call as it is streamed in.
+#[derive(Deserialize)]
+pub(super) struct ToolCallDelta {
+    #[serde(default)]
+    index: usize,
+    id: Option<String>,
+    function: Option<FunctionDelta>,
+}
+
+#[derive(Deserialize)]
+struct FunctionDelta {
+    name: Option<String>,
+    arguments: Option<String>,
+}
+
+/// Adds streamed pieces of tool calls to the calls they belong to, leaving
+/// out calls past [`MAX_TOOL_CALLS`].
+pub(super) fn merge_deltas(calls: &mut Vec<ToolCall>, deltas: Vec<ToolCallDelta>) {
+    for delta in deltas {
+        if delta.index >= MAX_TOOL_CALLS {
+            continue;
+        }
+        if calls.len() <= delta.index {
+            calls.resize_with(delta.index + 1, ToolCall::default);
+        }
+        let call = &mut calls[delta.index];
+        if let Some(id) = delta.id {
+            call.id = id;
+        }
+        if let Some(function) = delta.function {
+            if let Some(name) = function.name {
+                call.function.name.push_str(&name);
+            }
+            if let Some(arguments) = function.arguments {
+                call.function.arguments.push_str(&arguments);
+            }
+        }
+    }
+}
+
+/// What running a tool gave us.
+pub(super) struct ToolOutput {
+    /// Fed back to the model.
+    pub(super) content: String,
+    /// Shown under the reply, so people can tell where an answer came from.
+    pub(super) footnote: Option<String>,
+    pub(super) attachment: Option<CreateAttachment>,
+}
+
+impl ToolOutput {
+    fn text(content: String, footnote: Option<String>) -> Self {
+        Self {
+            content,
+            footnote,
+            attachment: None,
+        }
+    }
+}
+
+/// The tools offered to the model, in the function calling format.
+pub(super) fn definitions() -> Value {
+    let channels: Vec<&str> = nixpkgs_db::channels()
+        .iter()
+        .map(|channel| channel.name.as_str())
+        .collect();
+
+    json!([
+        {
+            "type": "function",
+            "function": {
+                "name": "nixpkgs_package",
+                "description": "Look up a nixpkgs package by attribute path: its version, description and whether it is broken, insecure or unfree, in each channel.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "name": {
+                            "type": "string",
+                            "description": "attribute path, e.g. ripgrep or python3Packages.requests"
+                        },
+                        "channel": {
+                            "type": "string",
+                            "enum": channels,
+                            "description": "only look in this channel"
+                        }
+                    },
+                    "required": ["name"]
+                }
+            }
+        },
+        {
+            "type": "function",
+            "function": {
+                "name": "crates_io",
+                "description": "Look up a Rust crate on crates.io: its latest version, description, license and repository.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "name": { "type": "string", "description": "crate name" }
+                    },
+                    "required": ["name"]
+                }
+            }
+        },
+        {
+            "type": "function",
+            "function": {
+                "name": "nixpkgs_pr",
+                "description": "Check a nixpkgs pull request: whether it is merged and which branches and channels it has reached.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "number": { "type": "integer", "description": "PR number" }
+                    },
+                    "required": ["number"]
+                }
+            }
+        },
+        {
+            "type": "function",
+            "function": {
+                "name": "typst_math",
+                "description": "Render a typst math expression to an image that is attached to your reply. Use this to show formulas.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "expression": {
+                            "type": "string",
+                            "description": "typst math, without the surrounding $, e.g. sum_(i=1)^n i = (n(n+1))/2"
+                        }
+                    },
+                    "required": ["expression"]
+                }
+            }
+        }
+    ])
+}
+
+/// Runs a tool call. Failures are reported back to the model as the result,
+/// so it can tell the user or try something else.
+pub(super) async fn run(data: &Data, call: &ToolCall) -> ToolOutput {
+    let arguments: Value = serde_json::from_str(&call.function.arguments).unwrap_or(Value::Null);
+
+    let result = match call.function.name.as_str() {
+        // Its queries, and the fuzzy search when the name is wrong, block.
+        "nixpkgs_package" => tokio::task::block_in_place(|| nixpkgs_package(&arguments)),
+        "crates_io" => crates_io(data, &arguments).await,
+        "nixpkgs_pr" => nixpkgs_pr(data, &arguments).await,
+        "typst_math" => typst_math(&arguments).await,
+        name => Err(eyre!("there is no tool called {name}")),
+    };
+
+    match result {
+        Ok(mut output) => {
+            if output.content.chars().count() > MAX_OUTPUT_CHARS {
+                output.content = output.content.chars().take(MAX_OUTPUT_CHARS).collect();
+            }
+            output
+        }
+        Err(err) => ToolOutput::text(format!("error: {err}"), None),
+    }
+}
+
+fn string_arg<'a>(arguments: &'a Value, name: &str) -> Result<&'a str> {
+    arguments
+        .get(name)
+        .and_then(Value::as_str)
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .ok_or_else(|| eyre!("missing `{name}` argument"))
+}
+
+fn nixpkgs_package(arguments: &Value) -> Result<ToolOutput> {
+    let name = string_arg(arguments, "name")?;
+    let channels: Vec<&Channel> = match arguments.get("channel").and_then(Value::as_str) {
+        Some(channel) => {
+            vec![nixpkgs_db::channel(channel).ok_or_else(|| eyre!("unknown channel {channel}"))?]
+        }
+        None => nixpkgs_db::channels().iter().collect(),
+    };
+
+    let mut content = String::new();
+    for channel in &channels {
+        let db = channel.db()?;
+        let found = db
+            .query_row(
+                "SELECT version, description, homepage, broken, insecure, unfree
+                 FROM packages WHERE package_name = ?1",
+                [name],
+                |row| {
+                    let mut line = format!(
+                        "{name} {}",
+                        row.get::<_, Option<String>>(0)?.unwrap_or_default()
+                    );
+                    for (index, flag) in [(3, "broken"), (4, "insecure"), (5, "unfree")] {
+                        if row.get::<_, bool>(index)? {
+                            let _ = write!(line, " ({flag})");
+                        }
+                    }
+                    if let Some(description) = row.get::<_, Option<String>>(1)? {
+                        let _ = write!(line, ": {description}");
+                    }
+                    if let Some(homepage) = row.get::<_, Option<String>>(2)? {
+                        let _ = write!(line, " <{homepage}>");
+                    }
+                    Ok(line)
+                },
+            )
+            .optional()?;
+
+        let line = match found {
+            Some(line) => line,
+            None => {
+                let suggestions = search::suggest(&db, name, 5)?;
+                if suggestions.is_empty() {
+                    "not found".to_string()
+                } else {
+                    format!("not found, similar: {}", suggestions.join(", "))
+                }
+            }
+        };
+        let _ = writeln!(content, "{}: {line}", channel.name);
+    }
+
+    let names: Vec<&str> = channels
+        .iter()
+        .map(|channel| channel.name.as_str())
+        .collect();
+    Ok(ToolOutput::text(
+        content,
+        Some(format!("nixpkgs `{name}` in {}", names.join(", "))),
+    ))
+}
+
+async fn crates_io(data: &Data, arguments: &Value) -> Result<ToolOutput> {
+    let name = string_arg(arguments, "name")?;
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(eyre!("`{name}` isn't a valid crate name"));
+    }
+
+    let content = match crates::crate_summary(&data.client, name).await? {
+        Some(summary) => format!(
+            "{name} {}{}: {}\nlicense: {}\nrepository: {}",
+            summary.version,
+            if summary.yanked { " (yanked)" } else { "" },
+            summary.description.trim(),
+            summary.license.as_deref().unwrap_or("unknown"),
+            summary.repository.as_deref().unwrap_or("unknown"),
+        ),
+        None => format!("there is no crate called {name} on crates.io"),
+    };
+
+    Ok(ToolOutput::text(
+        content,
+        Some(format!(
+            "[crates.io `{name}`](<https://crates.io/crates/{name}>)"
+        )),
+    ))
+}
+
+async fn nixpkgs_pr(data: &Data, arguments: &Value) -> Result<ToolOutput> {
+    let number = arguments
+        .get("number")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| eyre!("missing `number` argument"))?;
+
+    // Waiting out a rate limit would hold up the whole reply, so the model
+    // is told about it instead.
+    let github = GitHub::interactive(data.client.clone(), data.github_token.clone());
+    let Conditional::Modified { value: pr, .. } = github.pull_request(number, None).await? else {
+        return Ok(ToolOutput::text(
+            format!("nixpkgs has no PR #{number}"),
+            None,
+        ));
+    };
+
+    let mut content = format!(
+        "#{} {}\nbase branch: {}\n",
+        pr.number, pr.title, pr.base.name
+    );
+    match (&pr.merge_commit_sha, pr.merged) {
+        (Some(commit_sha), true) => {
+            let branches = tracked_branches_for(&pr.base.name);
+            let statuses = branch_statuses(&github, &branches, commit_sha).await?;
+            content.push_str("merged, branches that have it:\n");
+            content.push_str(&format_branch_statuses(&statuses));
+        }
+        _ => content.push_str("not merged\n"),
+    }
+
+    Ok(ToolOutput::text(
+        content,
+        Some(format!("[nixpkgs#{number}](<{}>)", pr.html_url)),
+    ))
+}
+
+async fn typst_math(arguments: &Value) -> Result<ToolOutput> {
+    let expression = string_arg(arguments, "expression")?.to_string();
+    let png = tokio::task::spawn_blocking(move || typst::render_math(&expression)).await??;
+
+    Ok(ToolOutput {
+        content: "Rendered, the image is attached to your reply.".to_string(),
+        footnote: None,
+        attachment: Some(CreateAttachment::bytes(png, "math.png")),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merges_streamed_tool_calls() {
+        let deltas: Vec<ToolCallDelta> = serde_json::from_value(json!([
+            { "index": 0, "id": "call_1", "function": { "name": "crates_io", "arguments": "" } },
+            { "index": 1, "id": "call_2", "function": { "name": "nixpkgs_pr", "arguments": "{\"number\"" } },
+        ]))
+        .unwrap();
+        let more: Vec<ToolCallDelta> = serde_json::from_value(json!([
+            { "index": 0, "function": { "arguments": "{\"name\":\"serde\"}" } },
+            { "index": 1, "function": { "arguments": ":1}" } },
+        ]))
+        .unwrap();
+
+        let mut calls = Vec::new();
+        merge_deltas(&mut calls, deltas);
+        merge_deltas(&mut calls, more);
+
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].id, "call_1");
+        assert_eq!(calls[0].function.name, "crates_io");
+        assert_eq!(calls[0].function.arguments, r#"{"name":"serde"}"#);
+        assert_eq!(calls[1].function.arguments, r#"{"number":1}"#);
+        assert_eq!(serde_json::to_value(&calls[1]).unwrap()["type"], "function");
+
+        let runaway: Vec<ToolCallDelta> = serde_json::from_value(json!([
+            { "index": usize::MAX, "id": "call_3" },
+        ]))
+        .unwrap();
+        merge_deltas(&mut calls, runaway);
+        assert_eq!(calls.len(), 2);
+    }
+}
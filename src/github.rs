@@ -0,0 +1,540 @@
+use color_eyre::eyre::{Result, eyre};
+use reqwest::header::{ACCEPT, ETAG, HeaderMap, IF_NONE_MATCH, RETRY_AFTER};
+use reqwest::{Client, Response, StatusCode};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+use tracing::warn;
+
+const NIXPKGS_API: &str = "https://api.github.com/repos/NixOS/nixpkgs";
+/// How many times a rate limited request is retried before giving up on it.
+const MAX_ATTEMPTS: u32 = 5;
+/// The longest we are willing to wait for a rate limit to reset in one go.
+const MAX_WAIT_SECS: u64 = 60 * 60;
+/// Once this few requests are left, we wait for the reset rather than
+/// running into the limit.
+const LOW_REMAINING: u64 = 5;
+/// How many of a PR's commits are searched for cherry-picks of.
+const SEARCHED_COMMITS: usize = 3;
+/// How many candidate backports of one PR are looked at.
+const MAX_BACKPORTS: usize = 5;
+/// How long the backports found for a PR are remembered before searching
+/// again. Searching costs a request per term out of the 30 a minute the
+/// search API allows, and the PR poller and `/nixpkgs` would otherwise ask
+/// every time.
+const BACKPORTS_SECS: i64 = 60 * 60;
+/// How many searches we make a minute, leaving some of the search API's 30
+/// for when they are needed again.
+const SEARCHES_PER_MINUTE: usize = 20;
+
+/// When each PR was last searched for backports, and what was found.
+static BACKPORTS: LazyLock<Mutex<HashMap<u64, (i64, Vec<PullRequest>)>>> =
+    LazyLock::new(Mutex::default);
+/// When each search of the last minute was made.
+static RECENT_SEARCHES: LazyLock<Mutex<Vec<i64>>> = LazyLock::new(Mutex::default);
+
+/// The parts of a nixpkgs pull request the PR poller cares about.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PullRequest {
+    pub number: u64,
+    pub title: String,
+    pub html_url: String,
+    #[serde(default)]
+    pub merged: bool,
+    pub merge_commit_sha: Option<String>,
+    pub base: BaseRef,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BaseRef {
+    #[serde(rename = "ref")]
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct BranchJson {
+    commit: CommitJson,
+}
+
+#[derive(Debug, Deserialize)]
+struct CommitJson {
+    sha: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CompareJson {
+    status: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchJson {
+    items: Vec<SearchItemJson>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchItemJson {
+    number: u64,
+    title: String,
+    #[serde(default)]
+    body: Option<String>,
+    #[serde(default)]
+    labels: Vec<LabelJson>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LabelJson {
+    name: String,
+}
+
+impl SearchItemJson {
+    /// Whether the PR is labelled or calls itself a backport or cherry-pick,
+    /// rather than just mentioning the original in passing. The original
+    /// carries `backport release-*` labels, so only the bare label counts.
+    fn looks_like_backport(&self) -> bool {
+        if self.labels.iter().any(|label| label.name == "backport") {
+            return true;
+        }
+
+        let text = format!(
+            "{} {}",
+            self.title,
+            self.body.as_deref().unwrap_or_default()
+        )
+        .to_lowercase();
+        ["backport", "cherry picked from", "cherry-picked from"]
+            .iter()
+            .any(|marker| text.contains(marker))
+    }
+}
+
+/// The outcome of a conditional request.
+#[derive(Debug)]
+pub enum Conditional<T> {
+    Modified { value: T, etag: Option<String> },
+    NotModified,
+    NotFound,
+}
+
+/// A GitHub REST client for the nixpkgs repository that makes conditional
+/// requests where it can: a `304 Not Modified` answer doesn't count against
+/// the rate limit.
+pub struct GitHub {
+    client: Client,
+    token: String,
+    /// Whether rate limits are waited out rather than failed on.
+    waits: bool,
+    /// The last seen head of each branch, along with its ETag.
+    branch_heads: HashMap<String, (String, Option<String>)>,
+}
+
+impl GitHub {
+    /// A client that waits out rate limits instead of failing, for background
+    /// work like the PR poller.
+    pub fn new(client: Client, token: String) -> Self {
+        Self {
+            client,
+            token,
+            waits: true,
+            branch_heads: HashMap::new(),
+        }
+    }
+
+    /// A client that fails right away when rate limited, saying when to try
+    /// again. Commands use it, as their interaction expires long before a
+    /// rate limit resets.
+    pub fn interactive(client: Client, token: String) -> Self {
+        Self {
+            waits: false,
+            ..Self::new(client, token)
+        }
+    }
+
+    /// Sends a GET request, sleeping through rate limits as GitHub tells us to
+    /// if this client waits.
+    async fn get(&self, url: &str, etag: Option<&str>) -> Result<Response> {
+        let mut attempt = 0;
+        loop {
+            let mut request = self
+                .client
+                .get(url)
+                .header(ACCEPT, "application/vnd.github+json")
+                .header("X-GitHub-Api-Version", "2022-11-28");
+            if !self.token.is_empty() {
+                request = request.bearer_auth(&self.token);
+            }
+            if let Some(etag) = etag {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+
+            let response = request.send().await?;
+            let now = chrono::Utc::now().timestamp();
+
+            if let Some(wait) = rate_limit_wait(response.status(), response.headers(), now, attempt)
+            {
+                if !self.waits {
+                    return Err(eyre!(
+                        "GitHub is rate limiting us, try again in {}s",
+                        wait.as_secs()
+                    ));
+                }
+                attempt += 1;
+                if attempt >= MAX_ATTEMPTS {
+                    return Err(eyre!(
+                        "GitHub rate limit still exceeded after {attempt} attempts"
+                    ));
+                }
+                warn!("rate limited by GitHub, retrying in {}s", wait.as_secs());
+                tokio::time::sleep(wait).await;
+                continue;
+            }
+
+            if self.waits
+                && let Some(wait) = low_remaining_wait(response.headers(), now)
+            {
+                warn!(
+                    "GitHub rate limit almost used up, pausing for {}s",
+                    wait.as_secs()
+                );
+                tokio::time::sleep(wait).await;
+            }
+
+            return Ok(response);
+        }
+    }
+
+    /// Fetches a nixpkgs pull request, unless it hasn't changed since `etag`.
+    pub async fn pull_request(
+        &self,
+        number: u64,
+        etag: Option<&str>,
+    ) -> Result<Conditional<PullRequest>> {
+        let response = self
+            .get(&format!("{NIXPKGS_API}/pulls/{number}"), etag)
+            .await?;
+
+        match response.status() {
+            StatusCode::NOT_MODIFIED => Ok(Conditional::NotModified),
+            StatusCode::NOT_FOUND => Ok(Conditional::NotFound),
+            status if status.is_success() => {
+                let etag = response_etag(response.headers());
+                Ok(Conditional::Modified {
+                    value: response.json().await?,
+                    etag,
+                })
+            }
+            status => Err(eyre!("GitHub returned {status} for PR #{number}")),
+        }
+    }
+
+    /// Fetches a nixpkgs pull request, for commands that can't do anything
+    /// without it.
+    pub async fn require_pull_request(&self, number: u64) -> Result<PullRequest> {
+        match self.pull_request(number, None).await? {
+            Conditional::Modified { value, .. } => Ok(value),
+            Conditional::NotModified | Conditional::NotFound => {
+                Err(eyre!("nixpkgs has no pull request #{number}"))
+            }
+        }
+    }
+
+    /// The commit `branch` currently points at. Heads are remembered between
+    /// calls, so asking again about an unchanged branch is free.
+    pub async fn branch_head(&mut self, branch: &str) -> Result<String> {
+        let cached = self.branch_heads.get(branch).cloned();
+        let response = self
+            .get(
+                &format!("{NIXPKGS_API}/branches/{branch}"),
+                cached.as_ref().and_then(|(_, etag)| etag.as_deref()),
+            )
+            .await?;
+
+        match (response.status(), cached) {
+            (StatusCode::NOT_MODIFIED, Some((sha, _))) => Ok(sha),
+            (status, _) if status.is_success() => {
+                let etag = response_etag(response.headers());
+                let branch_json: BranchJson = response.json().await?;
+                let sha = branch_json.commit.sha;
+                self.branch_heads
+                    .insert(branch.to_string(), (sha.clone(), etag));
+                Ok(sha)
+            }
+            (status, _) => Err(eyre!("GitHub returned {status} for branch {branch}")),
+        }
+    }
+
+    /// Whether `commit` is an ancestor of (or is) `head`.
+    pub async fn contains_commit(&self, head: &str, commit: &str) -> Result<bool> {
+        let response = self
+            .get(
+                &format!("{NIXPKGS_API}/compare/{commit}...{head}?per_page=1"),
+                None,
+            )
+            .await?;
+
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(false),
+            status if status.is_success() => {
+                let compare: CompareJson = response.json().await?;
+                Ok(matches!(compare.status.as_str(), "ahead" | "identical"))
+            }
+            status => Err(eyre!(
+                "GitHub returned {status} comparing {commit}...{head}"
+            )),
+        }
+    }
+
+    /// Merged PRs whose title or body contains `term`, newest first.
+    async fn search_merged_pull_requests(&self, term: &str) -> Result<Vec<SearchItemJson>> {
+        let url = reqwest::Url::parse_with_params(
+            "https://api.github.com/search/issues",
+            [
+                (
+                    "q",
+                    format!("repo:NixOS/nixpkgs is:pr is:merged {term}").as_str(),
+                ),
+                ("sort", "created"),
+                ("per_page", "20"),
+            ],
+        )?;
+        let search: SearchJson = self
+            .get(url.as_str(), None)
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(search.items)
+    }
+
+    /// Merged PRs that look like backports of PR `number`: ones referencing
+    /// the PR or one of its commits, and labelled or calling themselves a
+    /// backport or cherry-pick. Which branches they target is up to the caller
+    /// to check. What was found is remembered for [`BACKPORTS_SECS`].
+    pub async fn backports(&self, number: u64) -> Result<Vec<PullRequest>> {
+        let now = chrono::Utc::now().timestamp();
+        if let Some((_, backports)) = BACKPORTS
+            .lock()
+            .unwrap()
+            .get(&number)
+            .filter(|(checked, _)| now - checked < BACKPORTS_SECS)
+        {
+            return Ok(backports.clone());
+        }
+
+        let commits: Vec<CommitJson> = self
+            .get(
+                &format!("{NIXPKGS_API}/pulls/{number}/commits?per_page={SEARCHED_COMMITS}"),
+                None,
+            )
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let mut terms = vec![format!("\"#{number}\"")];
+        terms.extend(commits.into_iter().map(|commit| commit.sha));
+
+        // One search failing, e.g. on the search API's own rate limit, still
+        // leaves the others worth looking at.
+        let mut candidates: Vec<u64> = Vec::new();
+        let mut complete = true;
+        for term in terms {
+            if !take_search(now) {
+                warn!("out of searches for this minute, skipping backports of #{number} by {term}");
+                complete = false;
+                continue;
+            }
+            let items = match self.search_merged_pull_requests(&term).await {
+                Ok(items) => items,
+                Err(err) => {
+                    warn!("failed to search for backports of #{number} by {term}: {err}");
+                    complete = false;
+                    continue;
+                }
+            };
+            for item in items {
+                if item.number != number
+                    && item.looks_like_backport()
+                    && !candidates.contains(&item.number)
+                {
+                    candidates.push(item.number);
+                }
+            }
+        }
+
+        let mut backports = Vec::new();
+        for candidate in candidates.into_iter().take(MAX_BACKPORTS) {
+            if let Conditional::Modified { value, .. } = self.pull_request(candidate, None).await? {
+                backports.push(value);
+            }
+        }
+
+        // Whatever was found is worth keeping even if some searches failed,
+        // as searching again would most likely find the same.
+        if complete || !backports.is_empty() {
+            let mut cache = BACKPORTS.lock().unwrap();
+            cache.retain(|_, (checked, _)| now - *checked < BACKPORTS_SECS);
+            cache.insert(number, (now, backports.clone()));
+        }
+        Ok(backports)
+    }
+}
+
+/// Takes one of the [`SEARCHES_PER_MINUTE`], returning false once they are
+/// used up.
+fn take_search(now: i64) -> bool {
+    let mut searches = RECENT_SEARCHES.lock().unwrap();
+    searches.retain(|&at| now - at < 60);
+    if searches.len() >= SEARCHES_PER_MINUTE {
+        return false;
+    }
+    searches.push(now);
+    true
+}
+
+fn response_etag(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(ETAG)
+        .and_then(|etag| etag.to_str().ok())
+        .map(str::to_string)
+}
+
+fn header_u64(headers: &HeaderMap, name: &str) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok()
+}
+
+/// Seconds until the rate limit window resets, according to the response.
+fn until_reset(headers: &HeaderMap, now: i64) -> Option<u64> {
+    let reset = header_u64(headers, "x-ratelimit-reset")?;
+    Some(reset.saturating_sub(now.max(0).cast_unsigned()) + 1)
+}
+
+/// How long to wait before retrying a response that was rate limited, or
+/// `None` if it wasn't. GitHub's `retry-after` and `x-ratelimit-reset`
+/// headers are followed when present; otherwise secondary limits are backed
+/// off from exponentially.
+fn rate_limit_wait(
+    status: StatusCode,
+    headers: &HeaderMap,
+    now: i64,
+    attempt: u32,
+) -> Option<Duration> {
+    if status != StatusCode::FORBIDDEN && status != StatusCode::TOO_MANY_REQUESTS {
+        return None;
+    }
+
+    let secs = if let Some(retry_after) = header_u64(headers, RETRY_AFTER.as_str()) {
+        retry_after
+    } else if header_u64(headers, "x-ratelimit-remaining") == Some(0) {
+        until_reset(headers, now)?
+    } else if status == StatusCode::TOO_MANY_REQUESTS {
+        60 << attempt.min(6)
+    } else {
+        // A plain 403 is a permissions problem, not a rate limit.
+        return None;
+    };
+
+    Some(Duration::from_secs(secs.min(MAX_WAIT_SECS)))
+}
+
+/// How long to pause after a successful response that left only a handful of
+/// requests in the current window.
+fn low_remaining_wait(headers: &HeaderMap, now: i64) -> Option<Duration> {
+    if header_u64(headers, "x-ratelimit-remaining")? > LOW_REMAINING {
+        return None;
+    }
+    let secs = until_reset(headers, now)?;
+    Some(Duration::from_secs(secs.min(MAX_WAIT_SECS)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn waits_for_rate_limit_reset() {
+        let exhausted = headers(&[
+            ("x-ratelimit-remaining", "0"),
+            ("x-ratelimit-reset", "1100"),
+        ]);
+        assert_eq!(
+            rate_limit_wait(StatusCode::FORBIDDEN, &exhausted, 1000, 0),
+            Some(Duration::from_secs(101))
+        );
+        assert_eq!(rate_limit_wait(StatusCode::OK, &exhausted, 1000, 0), None);
+
+        let retry_after = headers(&[("retry-after", "30")]);
+        assert_eq!(
+            rate_limit_wait(StatusCode::TOO_MANY_REQUESTS, &retry_after, 1000, 3),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            rate_limit_wait(StatusCode::TOO_MANY_REQUESTS, &HeaderMap::new(), 1000, 2),
+            Some(Duration::from_secs(240))
+        );
+        assert_eq!(
+            rate_limit_wait(StatusCode::FORBIDDEN, &HeaderMap::new(), 1000, 0),
+            None
+        );
+    }
+
+    #[test]
+    fn pauses_when_nearly_exhausted() {
+        let low = headers(&[
+            ("x-ratelimit-remaining", "3"),
+            ("x-ratelimit-reset", "1010"),
+        ]);
+        assert_eq!(
+            low_remaining_wait(&low, 1000),
+            Some(Duration::from_secs(11))
+        );
+
+        let plenty = headers(&[
+            ("x-ratelimit-remaining", "4000"),
+            ("x-ratelimit-reset", "1010"),
+        ]);
+        assert_eq!(low_remaining_wait(&plenty, 1000), None);
+    }
+
+    #[test]
+    fn budgets_searches_per_minute() {
+        let now = 2_000_000_000;
+        for _ in 0..SEARCHES_PER_MINUTE {
+            assert!(take_search(now));
+        }
+        assert!(!take_search(now + 59));
+        assert!(take_search(now + 60));
+    }
+
+    #[test]
+    fn recognizes_backports() {
+        let item = |title: &str, labels: &[&str]| -> SearchItemJson {
+            serde_json::from_value(serde_json::json!({
+                "number": 2,
+                "title": title,
+                "labels": labels
+                    .iter()
+                    .map(|name| serde_json::json!({ "name": name }))
+                    .collect::<Vec<_>>(),
+            }))
+            .unwrap()
+        };
+
+        assert!(
+            item("[Backport release-25.05] hello: 2.12.1 -> 2.12.2", &[]).looks_like_backport()
+        );
+        assert!(item("hello: 2.12.1 -> 2.12.2", &["backport"]).looks_like_backport());
+        assert!(
+            !item("hello: 2.12.1 -> 2.12.2", &["backport release-25.05"]).looks_like_backport()
+        );
+    }
+}
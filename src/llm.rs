@@ -0,0 +1,867 @@
+use crate::config::GrokConfig;
+use crate::utils::DB;
+use color_eyre::eyre::{Result, eyre};
+use poise::serenity_prelude::GuildId;
+use reqwest::Url;
+use rusqlite::{OptionalExtension, params};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{LazyLock, Mutex};
+
+/// The settings a guild has overridden for `@grok`. Anything left `None`
+/// falls back to the global config.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct GuildOverrides {
+    pub endpoint: Option<String>,
+    pub api_key: Option<String>,
+    pub model: Option<String>,
+    pub vision: Option<bool>,
+    pub temperature: Option<f64>,
+    pub max_tokens: Option<u32>,
+    pub system_prompt: Option<String>,
+}
+
+impl GuildOverrides {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Whether the guild picked a model the operator would pay for without
+    /// having allowed it: one sent to the global endpoint with the global key
+    /// that isn't the global model or one of `allowed_models`.
+    pub fn uses_disallowed_model(&self, global: &GrokConfig) -> bool {
+        self.model.as_ref().is_some_and(|model| {
+            self.endpoint.is_none()
+                && self.api_key.is_none()
+                && *model != global.model
+                && !global.allowed_models.contains(model)
+        })
+    }
+
+    pub fn load(guild_id: u64) -> rusqlite::Result<Option<Self>> {
+        tokio::task::block_in_place(|| {
+            let conn = DB.lock().unwrap();
+            conn.query_row(
+                "SELECT endpoint, api_key, model, vision, temperature, max_tokens, system_prompt
+                 FROM grok_config WHERE guild_id = ?",
+                [guild_id.cast_signed()],
+                |row| {
+                    Ok(Self {
+                        endpoint: row.get(0)?,
+                        api_key: row.get(1)?,
+                        model: row.get(2)?,
+                        vision: row.get(3)?,
+                        temperature: row.get(4)?,
+                        max_tokens: row.get(5)?,
+                        system_prompt: row.get(6)?,
+                    })
+                },
+            )
+            .optional()
+        })
+    }
+
+    /// Stores the overrides for `guild_id`, dropping the row once nothing is
+    /// overridden anymore.
+    pub fn save(&self, guild_id: u64) -> rusqlite::Result<()> {
+        tokio::task::block_in_place(|| {
+            let conn = DB.lock().unwrap();
+            if self.is_empty() {
+                conn.execute(
+                    "DELETE FROM grok_config WHERE guild_id = ?",
+                    [guild_id.cast_signed()],
+                )?;
+                return Ok(());
+            }
+
+            conn.execute(
+                "INSERT OR REPLACE INTO grok_config
+                 (guild_id, endpoint, api_key, model, vision, temperature, max_tokens, system_prompt)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+                params![
+                    guild_id.cast_signed(),
+                    self.endpoint,
+                    self.api_key,
+                    self.model,
+                    self.vision,
+                    self.temperature,
+                    self.max_tokens,
+                    self.system_prompt,
+                ],
+            )?;
+            Ok(())
+        })
+    }
+}
+
+/// What an entry in a guild's `@grok` allow-list refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllowKind {
+    Channel,
+    Role,
+}
+
+impl AllowKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            AllowKind::Channel => "channel",
+            AllowKind::Role => "role",
+        }
+    }
+}
+
+/// Where `@grok` may be used in a guild. Guilds have to opt in, and can then
+/// restrict it to some channels and to members with some roles.
+#[derive(Debug, Default)]
+pub struct Access {
+    pub enabled: bool,
+    /// Whether `@grok` opens a thread for each conversation.
+    pub threads: bool,
+    /// Empty means every channel.
+    pub channels: Vec<u64>,
+    /// Empty means everyone.
+    pub roles: Vec<u64>,
+}
+
+impl Access {
+    pub fn load(guild_id: u64) -> rusqlite::Result<Self> {
+        tokio::task::block_in_place(|| {
+            let conn = DB.lock().unwrap();
+            let (enabled, threads) = conn
+                .query_row(
+                    "SELECT enabled, threads FROM grok_guilds WHERE guild_id = ?",
+                    [guild_id.cast_signed()],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()?
+                .unwrap_or_default();
+
+            let mut access = Self {
+                enabled,
+                threads,
+                ..Self::default()
+            };
+            let mut stmt =
+                conn.prepare("SELECT kind, id FROM grok_allowed WHERE guild_id = ? ORDER BY id")?;
+            let rows = stmt.query_map([guild_id.cast_signed()], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, i64>(1)?.cast_unsigned(),
+                ))
+            })?;
+            for (kind, id) in rows.filter_map(Result::ok) {
+                if kind == AllowKind::Role.as_str() {
+                    access.roles.push(id);
+                } else {
+                    access.channels.push(id);
+                }
+            }
+            Ok(access)
+        })
+    }
+
+    /// Whether the channel is allowed. Threads pass `parent` so they are
+    /// allowed along with the channel they were started in.
+    pub fn allows_channel(&self, channel: u64, parent: Option<u64>) -> bool {
+        self.channels.is_empty()
+            || self.channels.contains(&channel)
+            || parent.is_some_and(|parent| self.channels.contains(&parent))
+    }
+
+    pub fn allows_roles(&self, roles: &[u64]) -> bool {
+        self.roles.is_empty() || roles.iter().any(|role| self.roles.contains(role))
+    }
+
+    /// Turns `@grok` on or off. The guild's settings are kept while it is
+    /// off, so turning it back on picks up where it left off.
+    pub fn set_enabled(guild_id: u64, enabled: bool) -> rusqlite::Result<()> {
+        tokio::task::block_in_place(|| {
+            let conn = DB.lock().unwrap();
+            if enabled {
+                conn.execute(
+                    "INSERT INTO grok_guilds (guild_id, enabled_at) VALUES (?, ?)
+                     ON CONFLICT(guild_id) DO UPDATE
+                     SET enabled = 1, enabled_at = excluded.enabled_at
+                     WHERE enabled = 0",
+                    [guild_id.cast_signed(), chrono::Utc::now().timestamp()],
+                )?;
+            } else {
+                conn.execute(
+                    "UPDATE grok_guilds SET enabled = 0 WHERE guild_id = ?",
+                    [guild_id.cast_signed()],
+                )?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Returns false if the guild hasn't enabled `@grok`, in which case there
+    /// is nothing to change.
+    pub fn set_threads(guild_id: u64, threads: bool) -> rusqlite::Result<bool> {
+        tokio::task::block_in_place(|| {
+            let conn = DB.lock().unwrap();
+            let changed = conn.execute(
+                "UPDATE grok_guilds SET threads = ? WHERE guild_id = ? AND enabled = 1",
+                params![threads, guild_id.cast_signed()],
+            )?;
+            Ok(changed > 0)
+        })
+    }
+
+    /// Adds or removes an allow-list entry, returning whether anything
+    /// changed.
+    pub fn set_allowed(
+        guild_id: u64,
+        kind: AllowKind,
+        id: u64,
+        allowed: bool,
+    ) -> rusqlite::Result<bool> {
+        tokio::task::block_in_place(|| {
+            let conn = DB.lock().unwrap();
+            let sql = if allowed {
+                "INSERT OR IGNORE INTO grok_allowed (guild_id, kind, id) VALUES (?, ?, ?)"
+            } else {
+                "DELETE FROM grok_allowed WHERE guild_id = ? AND kind = ? AND id = ?"
+            };
+            let changed = conn.execute(
+                sql,
+                params![guild_id.cast_signed(), kind.as_str(), id.cast_signed()],
+            )?;
+            Ok(changed > 0)
+        })
+    }
+}
+
+/// The chat completions API a `@grok` request is sent to, with the guild's
+/// overrides applied over the global config.
+#[derive(Debug, Clone)]
+pub struct Provider {
+    pub endpoint: String,
+    pub api_key: Option<String>,
+    pub model: String,
+    pub vision: bool,
+    pub reasoning: Option<String>,
+    pub temperature: Option<f64>,
+    pub max_tokens: Option<u32>,
+    pub context_tokens: u32,
+    /// `None` means the built-in prompt.
+    pub system_prompt: Option<String>,
+    pub tools: bool,
+    /// Whether a guild picked `endpoint` rather than the operator, so it has
+    /// to pass [`check_guild_endpoint`] before every request, which is then
+    /// sent through [`CheckedEndpoint::client`].
+    pub guild_endpoint: bool,
+}
+
+impl Provider {
+    pub fn resolve(global: &GrokConfig, guild: GuildOverrides) -> Self {
+        // The global key must never be sent to an endpoint a guild picked.
+        let api_key = if guild.endpoint.is_some() {
+            guild.api_key
+        } else {
+            guild.api_key.or_else(|| global.api_key.clone())
+        };
+        // A model the operator didn't allow, saved before they changed their
+        // mind, falls back to the global one.
+        let disallowed = guild.uses_disallowed_model(global);
+        let guild_model = guild.model.filter(|_| !disallowed);
+        // Whether the global model sees images says nothing about another one.
+        let vision = guild
+            .vision
+            .unwrap_or(global.vision && guild.endpoint.is_none() && guild_model.is_none());
+        let model = guild_model.unwrap_or_else(|| global.model.clone());
+        let guild_endpoint = guild.endpoint.is_some();
+
+        Self {
+            endpoint: guild.endpoint.unwrap_or_else(|| global.endpoint.clone()),
+            api_key: api_key.filter(|key| !key.is_empty()),
+            context_tokens: global
+                .model_context_tokens
+                .get(&model)
+                .copied()
+                .unwrap_or(global.context_tokens),
+            model,
+            vision,
+            reasoning: Some(global.reasoning.clone()).filter(|r| !r.is_empty()),
+            temperature: guild.temperature.or(global.temperature),
+            max_tokens: guild.max_tokens.or(global.max_tokens),
+            system_prompt: guild.system_prompt.or_else(|| global.system_prompt.clone()),
+            tools: global.tools,
+            guild_endpoint,
+        }
+    }
+
+    /// The provider for a message sent in `guild_id`, or in a DM when `None`.
+    pub fn for_guild(guild_id: Option<GuildId>) -> Self {
+        let overrides = guild_id
+            .and_then(|id| {
+                GuildOverrides::load(id.get())
+                    .inspect_err(|err| tracing::warn!("failed to load grok config: {err}"))
+                    .ok()
+                    .flatten()
+            })
+            .unwrap_or_default();
+        Self::resolve(&crate::config::get().grok, overrides)
+    }
+
+    pub fn completions_url(&self) -> String {
+        format!("{}/chat/completions", self.endpoint.trim_end_matches('/'))
+    }
+}
+
+/// A guild's endpoint that passed [`check_guild_endpoint`], along with the
+/// addresses it was checked at.
+#[derive(Debug)]
+pub struct CheckedEndpoint {
+    host: String,
+    /// Empty for the operator's `allowed_endpoints`, which aren't pinned.
+    addrs: Vec<SocketAddr>,
+}
+
+impl CheckedEndpoint {
+    /// A client for requests to the endpoint. It only connects to the checked
+    /// addresses, so a name that resolves to a public address for the check
+    /// can't point somewhere else for the request, and it doesn't follow
+    /// redirects, which could lead anywhere the check wouldn't let a guild go.
+    pub fn client(&self) -> Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder()
+            .user_agent("isabelroses/blahaj")
+            .redirect(reqwest::redirect::Policy::none());
+        if !self.addrs.is_empty() {
+            builder = builder.resolve_to_addrs(&self.host, &self.addrs);
+        }
+        Ok(builder.build()?)
+    }
+}
+
+/// Checks that a guild may point `@grok` at `endpoint`: an http(s) URL that
+/// is either one of the operator's `allowed_endpoints` or only resolves to
+/// public addresses. Guild admins otherwise could have the bot send requests
+/// to the machine it runs on or the network behind it.
+pub async fn check_guild_endpoint(global: &GrokConfig, endpoint: &str) -> Result<CheckedEndpoint> {
+    let url = Url::parse(endpoint.trim())
+        .map_err(|err| eyre!("`{endpoint}` isn't a valid URL: {err}"))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(eyre!("The endpoint must be an http or https URL"));
+    }
+
+    let host = url
+        .host_str()
+        .ok_or_else(|| eyre!("`{endpoint}` has no host"))?
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_string();
+    let allowed = global
+        .allowed_endpoints
+        .iter()
+        .filter_map(|allowed| Url::parse(allowed).ok())
+        .any(|allowed| allowed.origin() == url.origin());
+    if allowed {
+        return Ok(CheckedEndpoint {
+            host,
+            addrs: Vec::new(),
+        });
+    }
+
+    let port = url.port_or_known_default().unwrap_or(443);
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), port))
+        .await
+        .map_err(|err| eyre!("Couldn't resolve `{host}`: {err}"))?
+        .collect();
+    if addrs.is_empty() || !addrs.iter().all(|addr| is_public(addr.ip())) {
+        return Err(eyre!(
+            "The endpoint must be a public server. Ask the bot's operator to allow it otherwise."
+        ));
+    }
+    Ok(CheckedEndpoint { host, addrs })
+}
+
+/// Whether `ip` is reachable over the internet, rather than being this
+/// machine, a private or link-local network, or reserved.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            // 0.0.0.0/8 is "this network" and 100.64.0.0/10 carrier-grade NAT.
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || first == 0
+                || (first == 100 && second & 0xc0 == 64))
+        }
+        IpAddr::V6(ip) => match embedded_ipv4(ip) {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let [first, second, ..] = ip.segments();
+                // 64:ff9b:1::/48 is NAT64 for local use.
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local()
+                    || ip.is_multicast()
+                    || (first == 0x64 && second == 0xff9b))
+            }
+        },
+    }
+}
+
+/// The IPv4 address an IPv6 one ends up at: IPv4-mapped, NAT64
+/// (64:ff9b::/96) and 6to4 (2002::/16) addresses carry one.
+fn embedded_ipv4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let octets = ip.octets();
+    if ip.segments()[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        let [.., a, b, c, d] = octets;
+        Some(Ipv4Addr::new(a, b, c, d))
+    } else if ip.segments()[0] == 0x2002 {
+        Some(Ipv4Addr::new(octets[2], octets[3], octets[4], octets[5]))
+    } else {
+        ip.to_ipv4_mapped()
+    }
+}
+
+/// How many of the latest messages in one of our threads are kept and sent
+/// along.
+pub const MAX_THREAD_HISTORY: usize = 100;
+/// Threads nobody has written in for this long are forgotten, and answering
+/// in them takes a fresh `@grok` again.
+const THREAD_IDLE_DAYS: i64 = 30;
+/// 2015-01-01, where Discord snowflakes start counting.
+const DISCORD_EPOCH_MS: i64 = 1_420_070_400_000;
+
+/// A message in one of the threads `@grok` opened, as the model sees it.
+#[derive(Debug)]
+pub struct ThreadEntry {
+    /// `user` or `assistant`.
+    pub role: String,
+    pub content: String,
+}
+
+/// Threads `@grok` opened, loaded from the database the first time they are
+/// needed. `is_grok_thread` is asked about every message the bot sees, so it
+/// shouldn't have to wait on the database.
+static THREADS: LazyLock<Mutex<Option<HashSet<u64>>>> = LazyLock::new(|| Mutex::new(None));
+
+/// Whether `channel_id` is a thread `@grok` opened for a conversation.
+pub fn is_grok_thread(channel_id: u64) -> rusqlite::Result<bool> {
+    let mut threads = THREADS.lock().unwrap();
+    if threads.is_none() {
+        let loaded = tokio::task::block_in_place(|| {
+            let conn = DB.lock().unwrap();
+            let mut stmt = conn.prepare("SELECT thread_id FROM grok_threads")?;
+            let ids = stmt
+                .query_map([], |row| row.get::<_, i64>(0))?
+                .filter_map(Result::ok)
+                .map(i64::cast_unsigned)
+                .collect();
+            Ok::<_, rusqlite::Error>(ids)
+        })?;
+        *threads = Some(loaded);
+    }
+    Ok(threads
+        .as_ref()
+        .is_some_and(|threads| threads.contains(&channel_id)))
+}
+
+/// Records a thread `@grok` opened, and forgets the ones nobody has written
+/// in for `THREAD_IDLE_DAYS`.
+pub fn start_thread(thread_id: u64, guild_id: u64) -> rusqlite::Result<()> {
+    let now = chrono::Utc::now();
+    let cutoff = now - chrono::Duration::days(THREAD_IDLE_DAYS);
+    let forgotten: Vec<u64> = tokio::task::block_in_place(|| {
+        let mut conn = DB.lock().unwrap();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT OR IGNORE INTO grok_threads (thread_id, guild_id, created_at) VALUES (?, ?, ?)",
+            params![
+                thread_id.cast_signed(),
+                guild_id.cast_signed(),
+                now.timestamp()
+            ],
+        )?;
+
+        // A thread was last used when its newest message was sent, which
+        // its snowflake tells. Threads without messages go by when they were
+        // opened.
+        let forgotten = {
+            let mut stmt = tx.prepare(
+                "DELETE FROM grok_threads
+                 WHERE COALESCE(
+                     (SELECT MAX(message_id) FROM grok_thread_messages
+                      WHERE grok_thread_messages.thread_id = grok_threads.thread_id) < ?1,
+                     created_at < ?2
+                 )
+                 RETURNING thread_id",
+            )?;
+            stmt.query_map(
+                params![snowflake_at(cutoff).cast_signed(), cutoff.timestamp()],
+                |row| row.get::<_, i64>(0),
+            )?
+            .filter_map(Result::ok)
+            .map(i64::cast_unsigned)
+            .collect()
+        };
+        tx.execute(
+            "DELETE FROM grok_thread_messages
+             WHERE thread_id NOT IN (SELECT thread_id FROM grok_threads)",
+            [],
+        )?;
+        tx.commit()?;
+        Ok::<_, rusqlite::Error>(forgotten)
+    })?;
+
+    if let Some(threads) = THREADS.lock().unwrap().as_mut() {
+        threads.insert(thread_id);
+        for id in forgotten {
+            threads.remove(&id);
+        }
+    }
+    Ok(())
+}
+
+/// The smallest Discord snowflake that could have been made at `time`.
+fn snowflake_at(time: chrono::DateTime<chrono::Utc>) -> u64 {
+    let since_epoch = time.timestamp_millis() - DISCORD_EPOCH_MS;
+    since_epoch.max(0).cast_unsigned() << 22
+}
+
+/// Adds a message to a thread's history, keeping only the newest
+/// `MAX_THREAD_HISTORY`. Messages are ordered by their ID, so context gathered
+/// from before the thread existed can be added late.
+pub fn record_thread_message(
+    thread_id: u64,
+    message_id: u64,
+    entry: &ThreadEntry,
+) -> rusqlite::Result<()> {
+    tokio::task::block_in_place(|| {
+        let conn = DB.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO grok_thread_messages (thread_id, message_id, role, content)
+             VALUES (?, ?, ?, ?)",
+            params![
+                thread_id.cast_signed(),
+                message_id.cast_signed(),
+                entry.role,
+                entry.content
+            ],
+        )?;
+        conn.execute(
+            "DELETE FROM grok_thread_messages
+             WHERE thread_id = ?1 AND message_id NOT IN (
+                 SELECT message_id FROM grok_thread_messages
+                 WHERE thread_id = ?1 ORDER BY message_id DESC LIMIT ?2
+             )",
+            params![
+                thread_id.cast_signed(),
+                i64::try_from(MAX_THREAD_HISTORY).unwrap_or(i64::MAX)
+            ],
+        )?;
+        Ok(())
+    })
+}
+
+/// The last `limit` messages of a thread, oldest first.
+pub fn thread_history(thread_id: u64, limit: usize) -> rusqlite::Result<Vec<ThreadEntry>> {
+    tokio::task::block_in_place(|| {
+        let conn = DB.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT role, content FROM (
+                SELECT message_id, role, content FROM grok_thread_messages
+                WHERE thread_id = ? ORDER BY message_id DESC LIMIT ?
+             ) ORDER BY message_id",
+        )?;
+        let rows = stmt
+            .query_map(
+                params![
+                    thread_id.cast_signed(),
+                    i64::try_from(limit).unwrap_or(i64::MAX)
+                ],
+                |row| {
+                    Ok(ThreadEntry {
+                        role: row.get(0)?,
+                        content: row.get(1)?,
+                    })
+                },
+            )?
+            .filter_map(Result::ok)
+            .collect();
+        Ok(rows)
+    })
+}
+
+/// Tokens used by model requests, as reported in the API's `usage` field.
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+pub struct Usage {
+    #[serde(default)]
+    pub prompt_tokens: u64,
+    #[serde(default)]
+    pub completion_tokens: u64,
+}
+
+impl Usage {
+    pub fn add(&mut self, other: Usage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+    }
+
+    pub fn total(self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+/// One user's use of `@grok` over some days.
+#[derive(Debug)]
+pub struct UserUsage {
+    pub user_id: u64,
+    pub requests: u64,
+    pub usage: Usage,
+}
+
+/// The UTC day usage is counted against, e.g. `2025-05-23`.
+fn usage_day(timestamp: chrono::DateTime<chrono::Utc>) -> String {
+    timestamp.format("%Y-%m-%d").to_string()
+}
+
+/// Counts a request by `user_id` in `guild_id` (`None` for DMs) towards
+/// today's usage.
+pub fn record_usage(guild_id: Option<u64>, user_id: u64, usage: Usage) -> rusqlite::Result<()> {
+    let day = usage_day(chrono::Utc::now());
+    tokio::task::block_in_place(|| {
+        let conn = DB.lock().unwrap();
+        conn.execute(
+            "INSERT INTO grok_usage
+             (day, guild_id, user_id, requests, prompt_tokens, completion_tokens)
+             VALUES (?1, ?2, ?3, 1, ?4, ?5)
+             ON CONFLICT (day, guild_id, user_id) DO UPDATE SET
+                requests = requests + 1,
+                prompt_tokens = prompt_tokens + ?4,
+                completion_tokens = completion_tokens + ?5",
+            params![
+                day,
+                guild_id.unwrap_or(0).cast_signed(),
+                user_id.cast_signed(),
+                usage.prompt_tokens.cast_signed(),
+                usage.completion_tokens.cast_signed(),
+            ],
+        )?;
+        Ok(())
+    })
+}
+
+/// The tokens `user_id` has used today, across every guild and DMs.
+pub fn tokens_used_today(user_id: u64) -> rusqlite::Result<u64> {
+    let day = usage_day(chrono::Utc::now());
+    tokio::task::block_in_place(|| {
+        let conn = DB.lock().unwrap();
+        conn.query_row(
+            "SELECT COALESCE(SUM(prompt_tokens + completion_tokens), 0) FROM grok_usage
+             WHERE day = ? AND user_id = ?",
+            params![day, user_id.cast_signed()],
+            |row| row.get::<_, i64>(0),
+        )
+        .map(i64::cast_unsigned)
+    })
+}
+
+/// Usage in `guild_id` per user over the last `days` days including today,
+/// heaviest users first.
+pub fn usage_by_user(guild_id: u64, days: u32) -> rusqlite::Result<Vec<UserUsage>> {
+    let since =
+        usage_day(chrono::Utc::now() - chrono::Duration::days(i64::from(days.saturating_sub(1))));
+    tokio::task::block_in_place(|| {
+        let conn = DB.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT user_id, SUM(requests), SUM(prompt_tokens), SUM(completion_tokens)
+             FROM grok_usage WHERE guild_id = ? AND day >= ?
+             GROUP BY user_id
+             ORDER BY SUM(prompt_tokens + completion_tokens) DESC, SUM(requests) DESC",
+        )?;
+        let rows = stmt
+            .query_map(params![guild_id.cast_signed(), since], |row| {
+                Ok(UserUsage {
+                    user_id: row.get::<_, i64>(0)?.cast_unsigned(),
+                    requests: row.get::<_, i64>(1)?.cast_unsigned(),
+                    usage: Usage {
+                        prompt_tokens: row.get::<_, i64>(2)?.cast_unsigned(),
+                        completion_tokens: row.get::<_, i64>(3)?.cast_unsigned(),
+                    },
+                })
+            })?
+            .filter_map(Result::ok)
+            .collect();
+        Ok(rows)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use confique::Config as _;
+
+    #[test]
+    fn empty_allow_lists_allow_everything() {
+        let access = Access {
+            enabled: true,
+            ..Access::default()
+        };
+        assert!(access.allows_channel(1, None));
+        assert!(access.allows_roles(&[]));
+
+        let access = Access {
+            enabled: true,
+            threads: false,
+            channels: vec![10],
+            roles: vec![20, 21],
+        };
+        assert!(access.allows_channel(10, None));
+        assert!(access.allows_channel(11, Some(10)));
+        assert!(!access.allows_channel(11, None));
+        assert!(access.allows_roles(&[5, 21]));
+        assert!(!access.allows_roles(&[5]));
+    }
+
+    fn global() -> GrokConfig {
+        // Everything not set here is left at its default.
+        let mut config = GrokConfig::builder().load().unwrap();
+        config.endpoint = "https://example.com/v1".to_string();
+        config.api_key = Some("global-key".to_string());
+        config.model = "default-model".to_string();
+        config.vision = true;
+        config.reasoning = String::new();
+        config.temperature = Some(0.7);
+        config.context_tokens = 8192;
+        config
+            .model_context_tokens
+            .insert("local".to_string(), 4096);
+        config.allowed_models = vec!["local".to_string()];
+        config
+    }
+
+    #[test]
+    fn guild_overrides_win() {
+        let provider = Provider::resolve(
+            &global(),
+            GuildOverrides {
+                model: Some("local".to_string()),
+                max_tokens: Some(512),
+                ..GuildOverrides::default()
+            },
+        );
+        assert_eq!(
+            provider.completions_url(),
+            "https://example.com/v1/chat/completions"
+        );
+        assert_eq!(provider.api_key.as_deref(), Some("global-key"));
+        assert_eq!(provider.model, "local");
+        assert!(!provider.vision);
+        assert_eq!(provider.reasoning, None);
+        assert_eq!(provider.temperature, Some(0.7));
+        assert_eq!(provider.max_tokens, Some(512));
+        assert_eq!(provider.context_tokens, 4096);
+    }
+
+    #[test]
+    fn guilds_pay_for_models_the_operator_did_not_allow() {
+        let expensive = GuildOverrides {
+            model: Some("expensive".to_string()),
+            ..GuildOverrides::default()
+        };
+        assert!(expensive.uses_disallowed_model(&global()));
+        let provider = Provider::resolve(&global(), expensive.clone());
+        assert_eq!(provider.model, "default-model");
+        assert!(provider.vision);
+
+        let own_key = GuildOverrides {
+            api_key: Some("guild-key".to_string()),
+            ..expensive
+        };
+        assert!(!own_key.uses_disallowed_model(&global()));
+        assert_eq!(Provider::resolve(&global(), own_key).model, "expensive");
+    }
+
+    #[test]
+    fn global_key_stays_with_global_endpoint() {
+        let provider = Provider::resolve(
+            &global(),
+            GuildOverrides {
+                endpoint: Some("http://localhost:8080/v1/".to_string()),
+                ..GuildOverrides::default()
+            },
+        );
+        assert_eq!(
+            provider.completions_url(),
+            "http://localhost:8080/v1/chat/completions"
+        );
+        assert_eq!(provider.api_key, None);
+        assert_eq!(provider.context_tokens, 8192);
+    }
+
+    #[test]
+    fn snowflakes_follow_the_clock() {
+        // A message Discord's docs use as an example, sent on 2016-04-30 at
+        // 11:18:25.796 UTC.
+        let id: u64 = 175_928_847_299_117_063;
+        let sent = chrono::DateTime::from_timestamp_millis(1_462_015_105_796).unwrap();
+        assert_eq!(snowflake_at(sent), id >> 22 << 22);
+        assert!(snowflake_at(sent + chrono::Duration::milliseconds(1)) > id);
+    }
+
+    #[test]
+    fn tells_public_addresses_apart() {
+        for ip in ["1.1.1.1", "2606:4700:4700::1111", "64:ff9b::101:101"] {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in [
+            "127.0.0.1",
+            "10.0.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "64:ff9b::7f00:1",
+            "64:ff9b:1::a00:1",
+            "2002:a00:1::1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[tokio::test]
+    async fn guilds_only_pick_public_or_allowed_endpoints() {
+        let mut config = global();
+        assert!(
+            check_guild_endpoint(&config, "http://127.0.0.1:8080/v1")
+                .await
+                .is_err()
+        );
+        assert!(
+            check_guild_endpoint(&config, "ftp://1.1.1.1")
+                .await
+                .is_err()
+        );
+        let checked = check_guild_endpoint(&config, "https://1.1.1.1/v1")
+            .await
+            .unwrap();
+        assert_eq!(checked.addrs, [SocketAddr::from(([1, 1, 1, 1], 443))]);
+
+        config.allowed_endpoints = vec!["http://127.0.0.1:8080".to_string()];
+        assert!(
+            check_guild_endpoint(&config, "http://127.0.0.1:8080/v1")
+                .await
+                .is_ok()
+        );
+        assert!(
+            check_guild_endpoint(&config, "http://127.0.0.1:9090/v1")
+                .await
+                .is_err()
+        );
+    }
+}
@@ -1,11 +1,14 @@
 mod commands;
 mod config;
 mod event_handler;
+mod github;
+mod llm;
 mod nixpkgs_db;
 mod types;
 mod utils;
 
 use dotenv::dotenv;
+use tracing_subscriber::EnvFilter;
 
 use color_eyre::eyre::Result;
 use poise::serenity_prelude::{
@@ -20,8 +23,19 @@ async fn main() -> Result<()> {
 
     // Enable color_eyre beacuse error handling ig
     color_eyre::install()?;
+
+    // Log our own info messages and everyone else's warnings, unless RUST_LOG
+    // says otherwise
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| EnvFilter::new("warn,blahaj=info")),
+        )
+        .init();
+
     let config = config::init()?;
-    nixpkgs_db::ensure_nixpkgs_database().await?;
+    nixpkgs_db::migrate_legacy_database()?;
+    nixpkgs_db::build_missing_databases().await?;
 
     // Configure the client with your Discord bot token in the environment.
     let token = config.discord_token.clone();
@@ -43,6 +57,8 @@ async fn main() -> Result<()> {
             // misc commands
             commands::misc::avatarsync::avatarsync(),
             commands::misc::crates::crates(),
+            commands::misc::grok::grok_config(),
+            commands::misc::grok::grok_usage(),
             commands::misc::starboard::starboard_enable(),
             commands::misc::starboard::starboard_disable(),
             commands::misc::starboard::starboard_config(),
@@ -56,7 +72,18 @@ async fn main() -> Result<()> {
             commands::nix::nixpkgs::nixpkgs(),
             commands::nix::nix::nix(),
             commands::nix::nixpkg::nixpkg(),
+            commands::nix::history::nixpkg_history(),
+            commands::nix::search::nixpkgs_search(),
+            commands::nix::which::nix_which(),
+            commands::nix::which::nix_which_message(),
+            commands::nix::maintainer::nixpkgs_maintainer(),
+            commands::nix::maintainer::nixpkgs_orphans(),
+            commands::nix::option::nixos_option(),
+            commands::nix::changes::nixpkgs_changes(),
+            commands::nix::changes::nixpkgs_announce_enable(),
+            commands::nix::changes::nixpkgs_announce_disable(),
             commands::nix::track::nixpkgs_track(),
+            commands::nix::version::nixpkg_track(),
             // fun commands
             commands::fun::chance::roll(),
             commands::fun::kittysay::kittysay(),
@@ -79,17 +106,7 @@ async fn main() -> Result<()> {
 
                 poise::builtins::register_globally(ctx, &framework.options().commands).await?;
 
-                tokio::spawn(async {
-                    let mut interval =
-                        tokio::time::interval(std::time::Duration::from_secs(43_200));
-                    loop {
-                        interval.tick().await;
-                        if let Err(e) = nixpkgs_db::ensure_nixpkgs_database().await {
-                            eprintln!("Failed to update nixpkgs database: {e}");
-                        }
-                    }
-                });
-
+                commands::nix::changes::spawn_updater(ctx.clone());
                 commands::nix::track::spawn_poller(ctx.clone());
 
                 let ctx_clone = ctx.clone();
@@ -0,0 +1,319 @@
+use crate::utils::DB;
+use rusqlite::{Connection, OptionalExtension, params};
+use std::path::Path;
+
+/// How many past updates are kept per channel before the oldest are pruned.
+const KEPT_UPDATES: i64 = 10;
+
+/// What happened to a package between two releases of a channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, poise::ChoiceParameter)]
+pub enum ChangeKind {
+    #[name = "added"]
+    Added,
+    #[name = "removed"]
+    Removed,
+    #[name = "updated"]
+    Updated,
+    #[name = "newly broken"]
+    Broken,
+    #[name = "newly insecure"]
+    Insecure,
+}
+
+impl ChangeKind {
+    pub const ALL: [ChangeKind; 5] = [
+        ChangeKind::Added,
+        ChangeKind::Removed,
+        ChangeKind::Updated,
+        ChangeKind::Broken,
+        ChangeKind::Insecure,
+    ];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            ChangeKind::Added => "added",
+            ChangeKind::Removed => "removed",
+            ChangeKind::Updated => "updated",
+            ChangeKind::Broken => "broken",
+            ChangeKind::Insecure => "insecure",
+        }
+    }
+
+    fn parse(kind: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|k| k.as_str() == kind)
+    }
+
+    /// Heading used when listing changes of this kind.
+    pub fn label(self) -> &'static str {
+        match self {
+            ChangeKind::Added => "Added",
+            ChangeKind::Removed => "Removed",
+            ChangeKind::Updated => "Updated",
+            ChangeKind::Broken => "Newly broken",
+            ChangeKind::Insecure => "Newly insecure",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PackageChange {
+    pub package_name: String,
+    pub kind: ChangeKind,
+    pub old_version: Option<String>,
+    pub new_version: Option<String>,
+}
+
+/// A recorded bump of a channel from one release to the next.
+#[derive(Debug, Clone)]
+pub struct ChannelUpdate {
+    pub channel: String,
+    pub from_hash: Option<String>,
+    pub to_hash: String,
+    pub created_at: i64,
+    pub changes: Vec<PackageChange>,
+}
+
+impl ChannelUpdate {
+    pub fn changes_of(&self, kind: ChangeKind) -> impl Iterator<Item = &PackageChange> {
+        self.changes
+            .iter()
+            .filter(move |change| change.kind == kind)
+    }
+}
+
+/// Compares the freshly built database behind `conn` with the previous
+/// release's database at `previous`, returning every added, removed and
+/// re-versioned package along with packages that became broken or insecure.
+pub fn diff_against(conn: &Connection, previous: &Path) -> rusqlite::Result<Vec<PackageChange>> {
+    conn.execute(
+        "ATTACH DATABASE ? AS prev",
+        [previous.to_string_lossy().as_ref()],
+    )?;
+
+    let queries = [
+        (
+            ChangeKind::Added,
+            "SELECT n.package_name, NULL, n.version FROM packages n
+             WHERE NOT EXISTS (SELECT 1 FROM prev.packages o WHERE o.package_name = n.package_name)",
+        ),
+        (
+            ChangeKind::Removed,
+            "SELECT o.package_name, o.version, NULL FROM prev.packages o
+             WHERE NOT EXISTS (SELECT 1 FROM packages n WHERE n.package_name = o.package_name)",
+        ),
+        (
+            ChangeKind::Updated,
+            "SELECT n.package_name, o.version, n.version FROM packages n
+             JOIN prev.packages o ON o.package_name = n.package_name
+             WHERE n.version IS NOT o.version",
+        ),
+        (
+            ChangeKind::Broken,
+            "SELECT n.package_name, o.version, n.version FROM packages n
+             JOIN prev.packages o ON o.package_name = n.package_name
+             WHERE n.broken = 1 AND o.broken = 0",
+        ),
+        (
+            ChangeKind::Insecure,
+            "SELECT n.package_name, o.version, n.version FROM packages n
+             JOIN prev.packages o ON o.package_name = n.package_name
+             WHERE n.insecure = 1 AND o.insecure = 0",
+        ),
+    ];
+
+    let mut changes = Vec::new();
+    let result = queries
+        .into_iter()
+        .try_for_each(|(kind, query)| -> rusqlite::Result<()> {
+            let mut stmt = conn.prepare(query)?;
+            let rows = stmt.query_map([], |row| {
+                Ok(PackageChange {
+                    package_name: row.get(0)?,
+                    kind,
+                    old_version: row.get(1)?,
+                    new_version: row.get(2)?,
+                })
+            })?;
+            changes.extend(rows.filter_map(Result::ok));
+            Ok(())
+        });
+
+    conn.execute("DETACH DATABASE prev", [])?;
+    result?;
+
+    changes.sort_by(|a, b| a.package_name.cmp(&b.package_name));
+    Ok(changes)
+}
+
+/// Stores a channel bump and its changes in `blahaj.db`, pruning all but the
+/// most recent [`KEPT_UPDATES`] updates of that channel.
+pub fn record(
+    channel: &str,
+    from_hash: Option<&str>,
+    to_hash: &str,
+    changes: Vec<PackageChange>,
+) -> rusqlite::Result<ChannelUpdate> {
+    let created_at = chrono::Utc::now().timestamp();
+
+    tokio::task::block_in_place(|| {
+        let mut conn = DB.lock().unwrap();
+        let tx = conn.transaction()?;
+
+        tx.execute(
+            "INSERT INTO nixpkgs_updates (channel, from_hash, to_hash, created_at) VALUES (?, ?, ?, ?)",
+            params![channel, from_hash, to_hash, created_at],
+        )?;
+        let id = tx.last_insert_rowid();
+
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO nixpkgs_changes (update_id, package_name, kind, old_version, new_version) VALUES (?, ?, ?, ?, ?)",
+            )?;
+            for change in &changes {
+                stmt.execute(params![
+                    id,
+                    change.package_name,
+                    change.kind.as_str(),
+                    change.old_version,
+                    change.new_version,
+                ])?;
+            }
+        }
+        super::history::record(&tx, channel, to_hash, created_at, &changes)?;
+
+        let stale = "SELECT id FROM nixpkgs_updates WHERE channel = ?1
+                     ORDER BY created_at DESC, id DESC LIMIT -1 OFFSET ?2";
+        tx.execute(
+            &format!("DELETE FROM nixpkgs_changes WHERE update_id IN ({stale})"),
+            params![channel, KEPT_UPDATES],
+        )?;
+        tx.execute(
+            &format!("DELETE FROM nixpkgs_updates WHERE id IN ({stale})"),
+            params![channel, KEPT_UPDATES],
+        )?;
+
+        tx.commit()
+    })?;
+
+    Ok(ChannelUpdate {
+        channel: channel.to_string(),
+        from_hash: from_hash.map(str::to_string),
+        to_hash: to_hash.to_string(),
+        created_at,
+        changes,
+    })
+}
+
+/// The most recently recorded update of `channel`, if any.
+pub fn latest(channel: &str) -> rusqlite::Result<Option<ChannelUpdate>> {
+    tokio::task::block_in_place(|| {
+        let conn = DB.lock().unwrap();
+
+        let Some((id, from_hash, to_hash, created_at)) = conn
+            .query_row(
+                "SELECT id, from_hash, to_hash, created_at FROM nixpkgs_updates
+                 WHERE channel = ? ORDER BY created_at DESC, id DESC LIMIT 1",
+                [channel],
+                |row| Ok((row.get::<_, i64>(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .optional()?
+        else {
+            return Ok(None);
+        };
+
+        let mut stmt = conn.prepare(
+            "SELECT package_name, kind, old_version, new_version FROM nixpkgs_changes
+             WHERE update_id = ? ORDER BY package_name",
+        )?;
+        let changes = stmt
+            .query_map([id], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                ))
+            })?
+            .filter_map(Result::ok)
+            .filter_map(|(package_name, kind, old_version, new_version)| {
+                Some(PackageChange {
+                    package_name,
+                    kind: ChangeKind::parse(&kind)?,
+                    old_version,
+                    new_version,
+                })
+            })
+            .collect();
+
+        Ok(Some(ChannelUpdate {
+            channel: channel.to_string(),
+            from_hash,
+            to_hash,
+            created_at,
+            changes,
+        }))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_every_kind_of_change() {
+        // The previous release has to be a file ATTACH can find, which a
+        // shared in-memory database is as well.
+        let previous = Path::new("file:diff-previous?mode=memory&cache=shared");
+        let columns = "package_name, version, broken, insecure";
+        // Shared in-memory databases only live while a connection is open.
+        let _old = crate::nixpkgs_db::test_db_at(
+            previous,
+            columns,
+            &[
+                params!["hello", "2.12.1", false, false],
+                params!["gone", "1.0", false, false],
+                params!["flaky", "0.3", false, false],
+                params!["oldssl", "1.1", false, false],
+                params!["same", "3.0", false, false],
+            ],
+        );
+        let new = crate::nixpkgs_db::test_db(
+            columns,
+            &[
+                params!["hello", "2.12.2", false, false],
+                params!["fresh", "0.1", false, false],
+                params!["flaky", "0.3", true, false],
+                params!["oldssl", "1.1", false, true],
+                params!["same", "3.0", false, false],
+            ],
+        );
+
+        let changes: Vec<String> = diff_against(&new, previous)
+            .unwrap()
+            .into_iter()
+            .map(|change| {
+                format!(
+                    "{} {} {} -> {}",
+                    change.package_name,
+                    change.kind.as_str(),
+                    change.old_version.as_deref().unwrap_or("-"),
+                    change.new_version.as_deref().unwrap_or("-"),
+                )
+            })
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                "flaky broken 0.3 -> 0.3",
+                "fresh added - -> 0.1",
+                "gone removed 1.0 -> -",
+                "hello updated 2.12.1 -> 2.12.2",
+                "oldssl insecure 1.1 -> 1.1",
+            ]
+        );
+
+        // The previous release is detached again, so the next diff can attach
+        // one under the same name.
+        assert!(diff_against(&new, previous).is_ok());
+    }
+}
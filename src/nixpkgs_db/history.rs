@@ -0,0 +1,173 @@
+use super::diff::{ChangeKind, PackageChange};
+use crate::utils::DB;
+use rusqlite::{Transaction, params};
+use std::path::Path;
+
+/// When a version of a package first showed up in a channel.
+#[derive(Debug)]
+pub struct VersionRecord {
+    pub version: String,
+    /// The channel release the version was first seen in, if known.
+    pub first_hash: Option<String>,
+    pub first_seen: i64,
+    /// Whether the version was already there when history started being kept
+    /// for the channel, so `first_seen` is only an upper bound.
+    pub baseline: bool,
+}
+
+/// Starts the history of `channel` off with every package version in the
+/// database at `db`, unless it already has one. These are recorded as
+/// baseline versions, since there's no telling when they actually landed.
+pub(super) fn seed(channel: &str, db: &Path, hash: Option<&str>) -> rusqlite::Result<()> {
+    let now = chrono::Utc::now().timestamp();
+
+    tokio::task::block_in_place(|| {
+        let mut conn = DB.lock().unwrap();
+
+        let seeded: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM nixpkgs_history WHERE channel = ?)",
+            [channel],
+            |row| row.get(0),
+        )?;
+        if seeded {
+            return Ok(());
+        }
+
+        conn.execute("ATTACH DATABASE ? AS seed", [db.to_string_lossy().as_ref()])?;
+        let result = conn.transaction().and_then(|tx| {
+            tx.execute(
+                "INSERT OR IGNORE INTO nixpkgs_history
+                 (channel, package_name, version, first_hash, first_seen, baseline)
+                 SELECT ?, package_name, version, ?, ?, 1 FROM seed.packages
+                 WHERE version IS NOT NULL AND version != ''",
+                params![channel, hash, now],
+            )?;
+            tx.commit()
+        });
+        conn.execute("DETACH DATABASE seed", [])?;
+        result
+    })
+}
+
+/// Records the versions a channel bump brought in. Called as part of
+/// recording the bump itself, see [`super::diff::record`].
+pub(super) fn record(
+    tx: &Transaction,
+    channel: &str,
+    to_hash: &str,
+    created_at: i64,
+    changes: &[PackageChange],
+) -> rusqlite::Result<()> {
+    let mut stmt = tx.prepare(
+        "INSERT OR IGNORE INTO nixpkgs_history
+         (channel, package_name, version, first_hash, first_seen, baseline)
+         VALUES (?, ?, ?, ?, ?, 0)",
+    )?;
+
+    for change in changes {
+        if !matches!(change.kind, ChangeKind::Added | ChangeKind::Updated) {
+            continue;
+        }
+        let Some(version) = change.new_version.as_deref().filter(|v| !v.is_empty()) else {
+            continue;
+        };
+        stmt.execute(params![
+            channel,
+            change.package_name,
+            version,
+            to_hash,
+            created_at
+        ])?;
+    }
+
+    Ok(())
+}
+
+/// Every recorded version of `package` in `channel`, newest first.
+pub fn versions(channel: &str, package: &str) -> rusqlite::Result<Vec<VersionRecord>> {
+    tokio::task::block_in_place(|| {
+        let conn = DB.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT version, first_hash, first_seen, baseline FROM nixpkgs_history
+             WHERE channel = ? AND package_name = ?
+             ORDER BY first_seen DESC, rowid DESC",
+        )?;
+
+        let records = stmt
+            .query_map([channel, package], |row| {
+                Ok(VersionRecord {
+                    version: row.get(0)?,
+                    first_hash: row.get(1)?,
+                    first_seen: row.get(2)?,
+                    baseline: row.get(3)?,
+                })
+            })?
+            .filter_map(Result::ok)
+            .collect();
+
+        Ok(records)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::Connection;
+
+    fn change(name: &str, kind: ChangeKind, new_version: Option<&str>) -> PackageChange {
+        PackageChange {
+            package_name: name.to_string(),
+            kind,
+            old_version: None,
+            new_version: new_version.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn records_new_versions_only() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        crate::utils::init_nixpkgs_history(&conn).unwrap();
+
+        let tx = conn.transaction().unwrap();
+        record(
+            &tx,
+            "nixos-unstable",
+            "abc",
+            100,
+            &[
+                change("hello", ChangeKind::Updated, Some("2.12.2")),
+                change("foo", ChangeKind::Added, Some("1.0")),
+                change("bar", ChangeKind::Removed, None),
+                change("baz", ChangeKind::Broken, Some("0.1")),
+            ],
+        )
+        .unwrap();
+        // A version coming back keeps the date it first landed.
+        record(
+            &tx,
+            "nixos-unstable",
+            "def",
+            200,
+            &[change("hello", ChangeKind::Updated, Some("2.12.2"))],
+        )
+        .unwrap();
+
+        let rows: Vec<(String, String, i64)> = tx
+            .prepare(
+                "SELECT package_name, first_hash, first_seen FROM nixpkgs_history
+                 ORDER BY package_name",
+            )
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                ("foo".to_string(), "abc".to_string(), 100),
+                ("hello".to_string(), "abc".to_string(), 100)
+            ]
+        );
+    }
+}
@@ -0,0 +1,72 @@
+use rusqlite::{Connection, params};
+
+/// A license a package is distributed under, as given by its `meta.license`.
+#[derive(Debug)]
+pub struct License {
+    pub spdx_id: Option<String>,
+    pub short_name: Option<String>,
+    pub full_name: Option<String>,
+    pub free: Option<bool>,
+    pub redistributable: Option<bool>,
+    pub url: Option<String>,
+}
+
+impl License {
+    /// The most descriptive name available for the license.
+    pub fn name(&self) -> &str {
+        self.full_name
+            .as_deref()
+            .or(self.spdx_id.as_deref())
+            .or(self.short_name.as_deref())
+            .unwrap_or("Unknown")
+    }
+}
+
+/// Every license of `package`, in the order nixpkgs lists them.
+pub fn licenses_of(conn: &Connection, package: &str) -> rusqlite::Result<Vec<License>> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT spdx_id, short_name, full_name, free, redistributable, url
+         FROM licenses WHERE package_name = ?1 ORDER BY rowid",
+    )?;
+
+    let licenses = stmt
+        .query_map([package], |row| {
+            Ok(License {
+                spdx_id: row.get(0)?,
+                short_name: row.get(1)?,
+                full_name: row.get(2)?,
+                free: row.get(3)?,
+                redistributable: row.get(4)?,
+                url: row.get(5)?,
+            })
+        })?
+        .filter_map(Result::ok)
+        .collect();
+
+    Ok(licenses)
+}
+
+/// SPDX ids starting with `partial`, most widely used first, for
+/// autocompletion.
+pub fn complete_spdx_ids(
+    conn: &Connection,
+    partial: &str,
+    limit: usize,
+) -> rusqlite::Result<Vec<String>> {
+    let partial = partial.trim();
+
+    let mut stmt = conn.prepare_cached(
+        "SELECT spdx_id FROM licenses
+         WHERE spdx_id IS NOT NULL AND instr(lower(spdx_id), lower(?1)) = 1
+         GROUP BY spdx_id
+         ORDER BY count(*) DESC, spdx_id
+         LIMIT ?2",
+    )?;
+
+    let ids = stmt
+        .query_map(params![partial, limit], |row| row.get(0))?
+        .filter_map(Result::ok)
+        .collect();
+
+    Ok(ids)
+}
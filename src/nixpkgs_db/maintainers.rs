@@ -0,0 +1,169 @@
+use rusqlite::{Connection, params};
+
+/// Upper bound on how many orphaned packages a single listing returns.
+pub const MAX_ORPHANS: usize = 500;
+
+/// A package as shown in per-maintainer and orphan listings.
+#[derive(Debug)]
+pub struct ListedPackage {
+    pub package_name: String,
+    pub version: Option<String>,
+    pub broken: bool,
+    pub insecure: bool,
+}
+
+impl ListedPackage {
+    fn from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Self> {
+        Ok(Self {
+            package_name: row.get(0)?,
+            version: row.get(1)?,
+            broken: row.get::<_, Option<i32>>(2)?.unwrap_or(0) != 0,
+            insecure: row.get::<_, Option<i32>>(3)?.unwrap_or(0) != 0,
+        })
+    }
+}
+
+/// Every package maintained by the GitHub user `github`, matched
+/// case-insensitively. Broken and insecure packages come first, since those
+/// are the ones a maintainer will want to look at.
+pub fn packages_of(conn: &Connection, github: &str) -> rusqlite::Result<Vec<ListedPackage>> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT p.package_name, p.version, p.broken, p.insecure FROM packages p
+         WHERE p.package_name IN (
+             SELECT package_name FROM maintainers WHERE github = ?1 COLLATE NOCASE
+         )
+         ORDER BY (p.broken = 1 OR p.insecure = 1) DESC, p.package_name",
+    )?;
+
+    let packages = stmt
+        .query_map(
+            [github.trim().trim_start_matches('@')],
+            ListedPackage::from_row,
+        )?
+        .filter_map(Result::ok)
+        .collect();
+
+    Ok(packages)
+}
+
+/// Packages without a single maintainer whose attribute path starts with
+/// `prefix`, alphabetically, along with how many there are in total.
+pub fn orphans(conn: &Connection, prefix: &str) -> rusqlite::Result<(Vec<ListedPackage>, usize)> {
+    let prefix = prefix.trim();
+    let filter = "FROM packages p
+                  WHERE p.package_name >= ?1 AND p.package_name < ?1 || char(1114111)
+                  AND NOT EXISTS (SELECT 1 FROM maintainers m WHERE m.package_name = p.package_name)";
+
+    let total = conn.query_row(&format!("SELECT count(*) {filter}"), [prefix], |row| {
+        row.get(0)
+    })?;
+
+    let mut stmt = conn.prepare_cached(&format!(
+        "SELECT p.package_name, p.version, p.broken, p.insecure {filter}
+         ORDER BY p.package_name LIMIT ?2"
+    ))?;
+    let packages = stmt
+        .query_map(params![prefix, MAX_ORPHANS], ListedPackage::from_row)?
+        .filter_map(Result::ok)
+        .collect();
+
+    Ok((packages, total))
+}
+
+/// GitHub handles of maintainers starting with `partial`, for autocompletion.
+/// Handles maintaining the most packages come first.
+pub fn complete_handles(
+    conn: &Connection,
+    partial: &str,
+    limit: usize,
+) -> rusqlite::Result<Vec<String>> {
+    let partial = partial.trim().trim_start_matches('@');
+    if partial.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut stmt = conn.prepare_cached(
+        "SELECT github FROM maintainers
+         WHERE github LIKE ?1 || '%' ESCAPE '\\'
+         GROUP BY github COLLATE NOCASE
+         ORDER BY count(*) DESC, github
+         LIMIT ?2",
+    )?;
+
+    let escaped = partial
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    let handles = stmt
+        .query_map(params![escaped, limit], |row| row.get(0))?
+        .filter_map(Result::ok)
+        .collect();
+
+    Ok(handles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_db() -> Connection {
+        let conn = crate::nixpkgs_db::test_db(
+            "package_name, broken, insecure",
+            &[
+                params!["ripgrep", 0, 0],
+                params!["fd", 0, 0],
+                params!["oldssl", 0, 1],
+                params!["python3Packages.orphan", 1, 0],
+                params!["python3Packages.adopted", 0, 0],
+                params!["python3Packages.another", 0, 0],
+            ],
+        );
+        for (name, github) in [
+            ("ripgrep", "Alice"),
+            ("oldssl", "alice"),
+            ("fd", "bob"),
+            ("python3Packages.adopted", "bob"),
+        ] {
+            conn.execute(
+                "INSERT INTO maintainers (package_name, github) VALUES (?1, ?2)",
+                params![name, github],
+            )
+            .unwrap();
+        }
+        conn
+    }
+
+    #[test]
+    fn lists_packages_of_a_maintainer_flagged_first() {
+        let conn = test_db();
+        let names: Vec<String> = packages_of(&conn, "@ALICE")
+            .unwrap()
+            .into_iter()
+            .map(|package| package.package_name)
+            .collect();
+        assert_eq!(names, ["oldssl", "ripgrep"]);
+    }
+
+    #[test]
+    fn lists_orphans_by_prefix() {
+        let conn = test_db();
+        let (packages, total) = orphans(&conn, "python3Packages.").unwrap();
+        let names: Vec<&str> = packages
+            .iter()
+            .map(|package| package.package_name.as_str())
+            .collect();
+        assert_eq!(names, ["python3Packages.another", "python3Packages.orphan"]);
+        assert_eq!(total, 2);
+        assert!(packages[1].broken);
+    }
+
+    #[test]
+    fn completes_handles_by_package_count() {
+        let conn = test_db();
+        assert_eq!(complete_handles(&conn, "b", 25).unwrap(), ["bob"]);
+        assert_eq!(
+            complete_handles(&conn, "%", 25).unwrap(),
+            Vec::<String>::new()
+        );
+    }
+}
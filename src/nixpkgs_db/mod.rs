@@ -0,0 +1,1074 @@
+pub mod diff;
+pub mod history;
+pub mod licenses;
+pub mod maintainers;
+pub mod options;
+pub mod platforms;
+pub mod programs;
+pub mod search;
+pub mod versions;
+
+use color_eyre::eyre::Result;
+use rusqlite::{Connection, OpenFlags};
+use serde::Deserialize;
+use serde::de::{
+    DeserializeOwned, DeserializeSeed, Deserializer, Error as _, IgnoredAny, MapAccess, Visitor,
+};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::io::{BufReader, Read, Write as IoWrite};
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex, MutexGuard};
+use tracing::{error, info};
+
+/// A configured nixpkgs channel. Every channel is indexed into its own
+/// `packages-<name>.db`, next to a `nixpkgs-<name>.hash` recording which
+/// release that database was built from.
+#[derive(Debug)]
+pub struct Channel {
+    /// Short name, e.g. `nixos-25.05`, taken from the last segment of `url`.
+    pub name: String,
+    pub url: String,
+    db: Mutex<Option<OpenDb>>,
+    /// Bumped every time a rebuilt database is swapped into place, so readers
+    /// know to reopen their connection.
+    generation: AtomicU64,
+}
+
+/// A read-only connection to a channel's database, tagged with the
+/// generation of the file it was opened on.
+#[derive(Debug)]
+struct OpenDb {
+    generation: u64,
+    conn: Connection,
+    /// Whether the database has NixOS options, once someone asked.
+    has_options: Option<bool>,
+}
+
+/// Exclusive access to a channel's package database, see [`Channel::db`].
+pub struct PackagesDb<'a>(MutexGuard<'a, Option<OpenDb>>);
+
+impl Deref for PackagesDb<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        &self
+            .0
+            .as_ref()
+            .expect("PackagesDb is only handed out once opened")
+            .conn
+    }
+}
+
+impl PackagesDb<'_> {
+    /// Whether this database has NixOS options. Only checked once per
+    /// generation, as option autocompletion asks every channel on every
+    /// keystroke.
+    pub fn has_options(&mut self) -> bool {
+        let db = self
+            .0
+            .as_mut()
+            .expect("PackagesDb is only handed out once opened");
+        *db.has_options
+            .get_or_insert_with(|| options::has_options(&db.conn))
+    }
+}
+
+impl Channel {
+    fn new(url: &str) -> Self {
+        let url = url.trim_end_matches('/').to_string();
+        let name = url.rsplit('/').next().unwrap_or(&url).to_string();
+        Self {
+            name,
+            url,
+            db: Mutex::new(None),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    /// The flake reference `nix shell` and friends should use for this
+    /// channel. Unstable is what the `nixpkgs` registry entry points at; every
+    /// other channel has a branch of the same name in the nixpkgs repository.
+    pub fn flake_ref(&self) -> String {
+        if self.name == "nixpkgs-unstable" {
+            "nixpkgs".to_string()
+        } else {
+            format!("github:NixOS/nixpkgs/{}", self.name)
+        }
+    }
+
+    fn db_path(&self) -> PathBuf {
+        crate::utils::get_data_dir().join(format!("packages-{}.db", self.name))
+    }
+
+    /// Where a new release is built before being renamed over
+    /// [`Channel::db_path`], so readers never see a partial database.
+    fn build_db_path(&self) -> PathBuf {
+        crate::utils::get_data_dir().join(format!("packages-{}.db.tmp", self.name))
+    }
+
+    fn hash_path(&self) -> PathBuf {
+        crate::utils::get_data_dir().join(format!("nixpkgs-{}.hash", self.name))
+    }
+
+    /// The connection to this channel's package database, shared by every
+    /// command that reads from it. The connection is reopened whenever a
+    /// rebuilt database has been swapped in since it was opened; until then it
+    /// keeps reading the file it was opened on, which stays intact after the
+    /// swap.
+    pub fn db(&self) -> rusqlite::Result<PackagesDb<'_>> {
+        let mut guard = self.db.lock().unwrap();
+        let generation = self.generation.load(Ordering::Acquire);
+
+        if guard.as_ref().is_none_or(|db| db.generation != generation) {
+            let conn = Connection::open_with_flags(
+                self.db_path(),
+                OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+            )?;
+            *guard = Some(OpenDb {
+                generation,
+                conn,
+                has_options: None,
+            });
+        }
+
+        Ok(PackagesDb(guard))
+    }
+}
+
+/// Stored as the `user_version` of every channel database. Bump it whenever
+/// [`create_schema`] or what gets imported changes, so existing databases are
+/// rebuilt on the next update check instead of waiting for a new release.
+const SCHEMA_VERSION: i32 = 3;
+
+static CHANNELS: LazyLock<Vec<Channel>> = LazyLock::new(|| {
+    crate::config::get()
+        .nixpkgs_channels
+        .iter()
+        .map(|url| Channel::new(url))
+        .collect()
+});
+
+/// Every configured channel, in configuration order.
+pub fn channels() -> &'static [Channel] {
+    &CHANNELS
+}
+
+/// The channel commands use when none is given: the first configured one.
+pub fn default_channel() -> &'static Channel {
+    &CHANNELS[0]
+}
+
+/// Looks up a configured channel by its short name.
+pub fn channel(name: &str) -> Option<&'static Channel> {
+    CHANNELS.iter().find(|channel| channel.name == name)
+}
+
+#[derive(Debug)]
+pub struct NixpkgsRelease {
+    pub url: String,
+    pub hash: String,
+    /// Where the release's `options.json.br` is, and its hash. Only NixOS
+    /// channels publish one.
+    pub options: Option<(String, String)>,
+}
+
+#[derive(Debug, Clone)]
+struct Package {
+    name: String,
+    pname: Option<String>,
+    version: Option<String>,
+    display_name: Option<String>,
+    system: Option<String>,
+    output_name: Option<String>,
+    available: i32,
+    broken: i32,
+    description: Option<String>,
+    homepage: Option<String>,
+    insecure: i32,
+    unfree: i32,
+    unsupported: i32,
+    position: Option<String>,
+    long_description: Option<String>,
+    main_program: Option<String>,
+    platforms: Option<i64>,
+    bad_platforms: Option<i64>,
+}
+
+#[derive(Debug, Clone)]
+struct License {
+    package_name: String,
+    spdx_id: Option<String>,
+    short_name: Option<String>,
+    full_name: Option<String>,
+    free: Option<bool>,
+    redistributable: Option<bool>,
+    url: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct Maintainer {
+    package_name: String,
+    name: Option<String>,
+    email: Option<String>,
+    github: Option<String>,
+    github_id: Option<i64>,
+    matrix: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PackageJson {
+    #[serde(default)]
+    pname: Option<String>,
+    #[serde(default)]
+    version: Option<String>,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    system: Option<String>,
+    #[serde(default, rename = "outputName")]
+    output_name: Option<String>,
+    #[serde(default)]
+    meta: Option<MetaJson>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct MetaJson {
+    #[serde(default)]
+    available: Option<bool>,
+    #[serde(default)]
+    broken: Option<bool>,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    homepage: Option<HomepageJson>,
+    #[serde(default)]
+    insecure: Option<bool>,
+    #[serde(default)]
+    unfree: Option<bool>,
+    #[serde(default)]
+    unsupported: Option<bool>,
+    #[serde(default)]
+    position: Option<String>,
+    #[serde(default, rename = "longDescription")]
+    long_description: Option<String>,
+    #[serde(default, rename = "mainProgram")]
+    main_program: Option<String>,
+    #[serde(default)]
+    license: Option<LicenseJson>,
+    #[serde(default)]
+    maintainers: Option<Vec<MaintainerJson>>,
+    #[serde(default)]
+    platforms: Option<Vec<serde_json::Value>>,
+    #[serde(default, rename = "badPlatforms")]
+    bad_platforms: Option<Vec<serde_json::Value>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum HomepageJson {
+    Single(String),
+    Multi(Vec<String>),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum LicenseJson {
+    Object(LicenseObj),
+    Array(Vec<LicenseObj>),
+    String(String),
+}
+
+#[derive(Debug, Deserialize)]
+struct LicenseObj {
+    #[serde(default, rename = "spdxId")]
+    spdx_id: Option<String>,
+    #[serde(default, rename = "shortName")]
+    short_name: Option<String>,
+    #[serde(default, rename = "fullName")]
+    full_name: Option<String>,
+    #[serde(default)]
+    free: Option<bool>,
+    #[serde(default)]
+    redistributable: Option<bool>,
+    #[serde(default)]
+    url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MaintainerJson {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    email: Option<String>,
+    #[serde(default)]
+    github: Option<String>,
+    #[serde(default, rename = "githubId")]
+    github_id: Option<i64>,
+    #[serde(default)]
+    matrix: Option<String>,
+}
+
+pub async fn get_latest_nixpkgs_release(channel: &Channel) -> Result<NixpkgsRelease> {
+    let response = reqwest::get(&channel.url).await?;
+    let html = response.text().await?;
+
+    let (url, hash) = find_release_file(&html, "packages.json.br")?
+        .ok_or_else(|| color_eyre::eyre::eyre!("Could not find packages.json.br"))?;
+    let options = find_release_file(&html, "options.json.br")?;
+
+    Ok(NixpkgsRelease { url, hash, options })
+}
+
+/// The URL and SHA-256 of `file_name` in a channel's release listing.
+fn find_release_file(html: &str, file_name: &str) -> Result<Option<(String, String)>> {
+    let file_name = regex::escape(file_name);
+    let url_regex = regex::Regex::new(&format!(r"<a href='([^']+/{file_name})'>{file_name}</a>"))?;
+    let hash_regex = regex::Regex::new(&format!(
+        r"{file_name}</a></td><td align='right'>\d+</td><td><tt>([a-f0-9]{{64}})</tt>"
+    ))?;
+
+    let url = url_regex
+        .captures(html)
+        .and_then(|cap| cap.get(1))
+        .map(|m| {
+            let path = m.as_str();
+            if path.starts_with("http") {
+                path.to_string()
+            } else if path.starts_with('/') {
+                format!("https://releases.nixos.org{path}")
+            } else {
+                format!("https://releases.nixos.org/{path}")
+            }
+        });
+
+    let hash = hash_regex
+        .captures(html)
+        .and_then(|cap| cap.get(1))
+        .map(|m| m.as_str().to_string());
+
+    Ok(url.zip(hash))
+}
+
+/// Downloads `url` to `path`, checking it against the SHA-256 `hash` given in
+/// the release listing.
+async fn download_verified(url: &str, hash: &str, path: &Path) -> Result<()> {
+    let mut response = reqwest::get(url).await?;
+    let mut hasher = Sha256::new();
+    {
+        let mut file = std::fs::File::create(path)?;
+        while let Some(chunk) = response.chunk().await? {
+            hasher.update(&chunk);
+            file.write_all(&chunk)?;
+        }
+    }
+
+    let digest = hasher.finalize();
+    let mut computed_hash = String::with_capacity(digest.len() * 2);
+    for b in &digest {
+        write!(&mut computed_hash, "{b:02x}")?;
+    }
+
+    if computed_hash != hash {
+        let _ = std::fs::remove_file(path);
+        return Err(color_eyre::eyre::eyre!(
+            "Hash mismatch! Expected {hash}, got {computed_hash}"
+        ));
+    }
+
+    Ok(())
+}
+
+fn get_stored_hash(channel: &Channel) -> Option<String> {
+    std::fs::read_to_string(channel.hash_path()).ok()
+}
+
+fn store_hash(channel: &Channel, hash: &str) -> Result<()> {
+    std::fs::write(channel.hash_path(), hash)?;
+    Ok(())
+}
+
+/// Moves the `packages.db` and `nixpkgs.hash` of the single channel blahaj
+/// used to index over to the default channel, so an existing deployment keeps
+/// its database (and gets the next release diffed against it) instead of
+/// leaving them behind.
+pub fn migrate_legacy_database() -> Result<()> {
+    let data_dir = crate::utils::get_data_dir();
+    let channel = default_channel();
+
+    for (legacy, current) in [
+        (data_dir.join("packages.db"), channel.db_path()),
+        (data_dir.join("nixpkgs.hash"), channel.hash_path()),
+    ] {
+        if legacy.exists() && !current.exists() {
+            info!(channel = %channel.name, "migrating {}", legacy.display());
+            std::fs::rename(&legacy, &current)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Builds the package database of every channel that doesn't have one yet,
+/// so commands work from the start. Existing databases are left to
+/// [`crate::commands::nix::changes::spawn_updater`], which announces the
+/// releases they move to. A channel failing to build doesn't stop the others;
+/// the first error is returned once all of them have been tried.
+pub async fn build_missing_databases() -> Result<()> {
+    let mut first_error = None;
+
+    for channel in channels() {
+        if channel.db_path().exists() {
+            continue;
+        }
+        if let Err(err) = ensure_channel_database(channel).await {
+            error!(channel = %channel.name, "failed to build database: {err}");
+            first_error.get_or_insert(err);
+        }
+    }
+
+    first_error.map_or(Ok(()), Err)
+}
+
+/// Brings a single channel's package database up to date. When the channel
+/// moved to a new release, the changes from the previous one are recorded and
+/// returned.
+#[allow(clippy::too_many_lines)]
+pub async fn ensure_channel_database(channel: &Channel) -> Result<Option<diff::ChannelUpdate>> {
+    let db_path = channel.db_path();
+
+    info!(channel = %channel.name, "checking for updates");
+    let release = get_latest_nixpkgs_release(channel).await?;
+    let stored_hash = get_stored_hash(channel);
+
+    let has_previous = Path::new(&db_path).exists();
+    let same_release = stored_hash.as_deref() == Some(&release.hash);
+    let current_schema = schema_version(&db_path) == Some(SCHEMA_VERSION);
+
+    if has_previous && same_release && current_schema {
+        info!(channel = %channel.name, "database is up to date");
+        return Ok(None);
+    }
+
+    if has_previous && same_release {
+        info!(channel = %channel.name, "database schema is outdated, rebuilding");
+    } else if has_previous {
+        info!(channel = %channel.name, "new release detected, rebuilding database");
+    } else {
+        info!(channel = %channel.name, "database not found, building");
+    }
+
+    info!(channel = %channel.name, "downloading {}", release.url);
+
+    let temp_path =
+        crate::utils::get_data_dir().join(format!("packages-{}.json.br.tmp", channel.name));
+    download_verified(&release.url, &release.hash, &temp_path).await?;
+
+    // Build into a separate file and only swap it in once it's complete, so
+    // lookups keep working against the previous release in the meantime.
+    let build_path = channel.build_db_path();
+    let _ = std::fs::remove_file(&build_path);
+    let _ = std::fs::remove_file(build_path.with_extension("tmp-journal"));
+
+    let mut conn = rusqlite::Connection::open(&build_path)?;
+
+    // A half-built database is thrown away anyway, so there's no point paying
+    // for durability while importing.
+    conn.pragma_update(None, "journal_mode", "OFF")?;
+    conn.pragma_update(None, "synchronous", "OFF")?;
+    conn.pragma_update(None, "cache_size", "-64000")?;
+
+    create_schema(&conn)?;
+    conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+
+    info!(channel = %channel.name, "hash verified, importing packages");
+    let imported = tokio::task::block_in_place(|| -> Result<usize> {
+        let file = std::fs::File::open(&temp_path)?;
+        let buffered = BufReader::with_capacity(64 * 1024, file);
+        let decoder = brotli::Decompressor::new(buffered, 64 * 1024);
+        let json_reader = BufReader::with_capacity(64 * 1024, decoder);
+
+        let mut importer = Importer::new(&mut conn, &channel.name);
+        stream_packages(json_reader, |name, package| importer.add(name, package))?;
+        importer.finish()
+    });
+    let _ = std::fs::remove_file(&temp_path);
+    let imported = imported?;
+    info!(channel = %channel.name, "imported {imported} packages");
+
+    // Options are a nice-to-have on top of the packages, so a release whose
+    // options fail to import still gets its packages swapped in.
+    if let Some((url, hash)) = &release.options {
+        info!(channel = %channel.name, "importing options");
+        match options::import(channel, &mut conn, url, hash).await {
+            Ok(count) => info!(channel = %channel.name, "imported {count} options"),
+            Err(err) => error!(channel = %channel.name, "failed to import options: {err}"),
+        }
+    }
+
+    // Vacuuming, indexing and diffing take a while on a full channel, and
+    // this runs on a schedule while the bot is serving.
+    let update = tokio::task::block_in_place(|| -> Result<Option<diff::ChannelUpdate>> {
+        info!(channel = %channel.name, "vacuuming");
+        conn.execute("VACUUM", [])?;
+
+        // VACUUM may renumber the rowids of `packages` (it has no INTEGER
+        // PRIMARY KEY), which the external content FTS index refers to, so the
+        // index has to be built afterwards.
+        info!(channel = %channel.name, "building search index");
+        build_search_index(&conn)?;
+
+        // A rebuild of the same release only happens for schema changes and
+        // has nothing worth announcing.
+        let update = if has_previous && !same_release {
+            info!(channel = %channel.name, "comparing with the previous release");
+            // Deployments that predate the history table start it off from
+            // the previous release, so this update still shows up as changes.
+            if let Err(err) = history::seed(&channel.name, &db_path, stored_hash.as_deref()) {
+                error!(channel = %channel.name, "failed to seed package history: {err}");
+            }
+            match diff::diff_against(&conn, &db_path).and_then(|changes| {
+                diff::record(
+                    &channel.name,
+                    stored_hash.as_deref(),
+                    &release.hash,
+                    changes,
+                )
+            }) {
+                Ok(update) => Some(update),
+                Err(err) => {
+                    error!(channel = %channel.name, "failed to record changes: {err}");
+                    None
+                }
+            }
+        } else {
+            if !has_previous
+                && let Err(err) = history::seed(&channel.name, &build_path, Some(&release.hash))
+            {
+                error!(channel = %channel.name, "failed to seed package history: {err}");
+            }
+            None
+        };
+
+        drop(conn);
+        std::fs::rename(&build_path, &db_path)?;
+        store_hash(channel, &release.hash)?;
+        channel.generation.fetch_add(1, Ordering::Release);
+        Ok(update)
+    })?;
+
+    info!(channel = %channel.name, "database created: {}", db_path.display());
+    Ok(update)
+}
+
+/// The schema version of a channel database, or `None` if it can't be opened.
+fn schema_version(path: &Path) -> Option<i32> {
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY).ok()?;
+    conn.pragma_query_value(None, "user_version", |row| row.get(0))
+        .ok()
+}
+
+/// Creates the `packages`, `platform_sets`, `maintainers`, `licenses` and
+/// `options` tables, their indexes and the `packages_fts` full-text index over
+/// the packages. The FTS table is an external content table backed by
+/// `packages`, so it is only populated once every package has been inserted
+/// and the database vacuumed (see [`build_search_index`]).
+fn create_schema(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE packages (
+            package_name TEXT PRIMARY KEY,
+            pname TEXT,
+            version TEXT,
+            name TEXT,
+            system TEXT,
+            output_name TEXT,
+            available INTEGER,
+            broken INTEGER,
+            description TEXT,
+            homepage TEXT,
+            insecure INTEGER,
+            unfree INTEGER,
+            unsupported INTEGER,
+            position TEXT,
+            long_description TEXT,
+            main_program TEXT,
+            platforms INTEGER,
+            bad_platforms INTEGER,
+            FOREIGN KEY (platforms) REFERENCES platform_sets(id),
+            FOREIGN KEY (bad_platforms) REFERENCES platform_sets(id)
+        )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE maintainers (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            package_name TEXT,
+            name TEXT,
+            email TEXT,
+            github TEXT,
+            github_id INTEGER,
+            matrix TEXT,
+            FOREIGN KEY (package_name) REFERENCES packages(package_name)
+        )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE platform_sets (
+            id INTEGER PRIMARY KEY,
+            systems TEXT UNIQUE
+        )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE licenses (
+            package_name TEXT,
+            spdx_id TEXT,
+            short_name TEXT,
+            full_name TEXT,
+            free INTEGER,
+            redistributable INTEGER,
+            url TEXT,
+            FOREIGN KEY (package_name) REFERENCES packages(package_name)
+        )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE INDEX idx_package_name ON packages(package_name)",
+        [],
+    )?;
+    conn.execute("CREATE INDEX idx_pname ON packages(pname)", [])?;
+    conn.execute(
+        "CREATE INDEX idx_main_program ON packages(main_program)",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX idx_maintainers_package ON maintainers(package_name)",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX idx_licenses_package ON licenses(package_name)",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX idx_licenses_spdx_id ON licenses(spdx_id COLLATE NOCASE)",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX idx_maintainers_github ON maintainers(github COLLATE NOCASE)",
+        [],
+    )?;
+
+    options::create_schema(conn)?;
+
+    conn.execute(
+        "CREATE VIRTUAL TABLE packages_fts USING fts5(
+            package_name,
+            pname,
+            description,
+            long_description,
+            content='packages',
+            prefix='2 3'
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// Fills `packages_fts` from the contents of `packages`. Rebuilding the whole
+/// index in one go is much faster than keeping it in sync row by row while
+/// importing.
+fn build_search_index(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO packages_fts(packages_fts) VALUES('rebuild')",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Packages written to the database per transaction while importing.
+const BATCH_SIZE: usize = 5000;
+
+/// Streams the `packages` map out of a `packages.json` in `reader`, handing
+/// every package to `on_package` as soon as it has been parsed, so only one
+/// package is held in memory at a time no matter how big the channel is.
+/// Everything else in the file is skipped.
+fn stream_packages(
+    reader: impl Read,
+    on_package: impl FnMut(String, PackageJson) -> Result<()>,
+) -> Result<()> {
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    RootSeed(on_package).deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    Ok(())
+}
+
+/// Visits the top-level object of `packages.json`, see [`stream_packages`].
+struct RootSeed<F>(F);
+
+impl<'de, F: FnMut(String, PackageJson) -> Result<()>> DeserializeSeed<'de> for RootSeed<F> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, F: FnMut(String, PackageJson) -> Result<()>> Visitor<'de> for RootSeed<F> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a packages.json object")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(mut self, mut map: A) -> Result<(), A::Error> {
+        while let Some(key) = map.next_key::<String>()? {
+            if key == "packages" {
+                map.next_value_seed(EntriesSeed::<PackageJson, _>::new(&mut self.0))?;
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Streams a top-level JSON object out of `reader`, handing every entry to
+/// `on_entry` as soon as it has been parsed, like [`stream_packages`] does for
+/// the `packages` map.
+fn stream_entries<T: DeserializeOwned>(
+    reader: impl Read,
+    mut on_entry: impl FnMut(String, T) -> Result<()>,
+) -> Result<()> {
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    EntriesSeed::new(&mut on_entry).deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    Ok(())
+}
+
+/// Visits a JSON object one entry at a time, deserializing each value as a
+/// `T` and passing it on without keeping it around.
+struct EntriesSeed<'a, T, F> {
+    on_entry: &'a mut F,
+    entry: PhantomData<T>,
+}
+
+impl<'a, T, F> EntriesSeed<'a, T, F> {
+    fn new(on_entry: &'a mut F) -> Self {
+        Self {
+            on_entry,
+            entry: PhantomData,
+        }
+    }
+}
+
+impl<'de, T, F> DeserializeSeed<'de> for EntriesSeed<'_, T, F>
+where
+    T: DeserializeOwned,
+    F: FnMut(String, T) -> Result<()>,
+{
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, T, F> Visitor<'de> for EntriesSeed<'_, T, F>
+where
+    T: DeserializeOwned,
+    F: FnMut(String, T) -> Result<()>,
+{
+    type Value = ();
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a JSON object")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while let Some(name) = map.next_key::<String>()? {
+            let entry: T = map.next_value()?;
+            (self.on_entry)(name, entry).map_err(A::Error::custom)?;
+        }
+        Ok(())
+    }
+}
+
+/// Turns parsed packages into rows and writes them to the database being
+/// built, [`BATCH_SIZE`] packages per transaction.
+struct Importer<'a> {
+    conn: &'a mut Connection,
+    channel: &'a str,
+    packages: Vec<Package>,
+    maintainers: Vec<Maintainer>,
+    licenses: Vec<License>,
+    platform_sets: HashMap<String, i64>,
+    count: usize,
+}
+
+impl<'a> Importer<'a> {
+    fn new(conn: &'a mut Connection, channel: &'a str) -> Self {
+        Self {
+            conn,
+            channel,
+            packages: Vec::with_capacity(BATCH_SIZE),
+            maintainers: Vec::with_capacity(BATCH_SIZE * 4),
+            licenses: Vec::with_capacity(BATCH_SIZE),
+            platform_sets: HashMap::new(),
+            count: 0,
+        }
+    }
+
+    fn add(&mut self, pkg_name: String, pkg_data: PackageJson) -> Result<()> {
+        let PackageJson {
+            pname,
+            version,
+            name: display_name,
+            system,
+            output_name,
+            meta,
+        } = pkg_data;
+        let m = meta.unwrap_or_default();
+
+        let homepage = extract_homepage(m.homepage);
+        self.licenses.extend(extract_licenses(&pkg_name, m.license));
+        let platforms = intern_platforms(self.conn, &mut self.platform_sets, m.platforms)?;
+        let bad_platforms = intern_platforms(self.conn, &mut self.platform_sets, m.bad_platforms)?;
+
+        if let Some(maints) = m.maintainers {
+            for mt in maints {
+                self.maintainers.push(Maintainer {
+                    package_name: pkg_name.clone(),
+                    name: mt.name,
+                    email: mt.email,
+                    github: mt.github,
+                    github_id: mt.github_id,
+                    matrix: mt.matrix,
+                });
+            }
+        }
+
+        self.packages.push(Package {
+            name: pkg_name,
+            pname,
+            version,
+            display_name,
+            system,
+            output_name,
+            available: i32::from(m.available.unwrap_or(false)),
+            broken: i32::from(m.broken.unwrap_or(false)),
+            description: m.description,
+            homepage,
+            insecure: i32::from(m.insecure.unwrap_or(false)),
+            unfree: i32::from(m.unfree.unwrap_or(false)),
+            unsupported: i32::from(m.unsupported.unwrap_or(false)),
+            position: m.position,
+            long_description: m.long_description,
+            main_program: m.main_program,
+            platforms,
+            bad_platforms,
+        });
+
+        self.count += 1;
+        if self.packages.len() >= BATCH_SIZE {
+            self.flush()?;
+            info!(channel = %self.channel, "imported {} packages so far", self.count);
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        insert_batch(self.conn, &self.packages, &self.maintainers, &self.licenses)?;
+        self.packages.clear();
+        self.maintainers.clear();
+        self.licenses.clear();
+        Ok(())
+    }
+
+    /// Writes out the last partial batch, returning how many packages were
+    /// imported in total.
+    fn finish(mut self) -> Result<usize> {
+        if !self.packages.is_empty() {
+            self.flush()?;
+        }
+        Ok(self.count)
+    }
+}
+
+fn extract_homepage(homepage: Option<HomepageJson>) -> Option<String> {
+    match homepage? {
+        HomepageJson::Single(s) => Some(s),
+        HomepageJson::Multi(arr) => arr.into_iter().next(),
+    }
+}
+
+/// Stores a `meta.platforms` or `meta.badPlatforms` list in `platform_sets`,
+/// returning its id. Most packages share one of a handful of lists (all of
+/// `lib.platforms.unix`, say), so every distinct list is only stored once,
+/// space-separated, and `sets` remembers the ids handed out so far. Entries
+/// that aren't plain system strings, like `{ kernel.name = "linux"; }`
+/// patterns, are skipped.
+fn intern_platforms(
+    conn: &Connection,
+    sets: &mut HashMap<String, i64>,
+    platforms: Option<Vec<serde_json::Value>>,
+) -> rusqlite::Result<Option<i64>> {
+    let mut systems: Vec<String> = platforms
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|platform| match platform {
+            serde_json::Value::String(system) => Some(system),
+            _ => None,
+        })
+        .collect();
+    if systems.is_empty() {
+        return Ok(None);
+    }
+    systems.sort_unstable();
+    systems.dedup();
+
+    let key = systems.join(" ");
+    if let Some(id) = sets.get(&key) {
+        return Ok(Some(*id));
+    }
+
+    conn.execute("INSERT INTO platform_sets (systems) VALUES (?1)", [&key])?;
+    let id = conn.last_insert_rowid();
+    sets.insert(key, id);
+    Ok(Some(id))
+}
+
+/// One row per license a package is distributed under. A few packages give
+/// their license as a bare string rather than a license attribute; all that's
+/// known about those is the name.
+fn extract_licenses(package_name: &str, license: Option<LicenseJson>) -> Vec<License> {
+    let objects = match license {
+        None => Vec::new(),
+        Some(LicenseJson::Object(o)) => vec![o],
+        Some(LicenseJson::Array(arr)) => arr,
+        Some(LicenseJson::String(s)) => {
+            return vec![License {
+                package_name: package_name.to_string(),
+                spdx_id: None,
+                short_name: None,
+                full_name: Some(s),
+                free: None,
+                redistributable: None,
+                url: None,
+            }];
+        }
+    };
+
+    objects
+        .into_iter()
+        .map(|o| License {
+            package_name: package_name.to_string(),
+            spdx_id: o.spdx_id,
+            short_name: o.short_name,
+            full_name: o.full_name,
+            free: o.free,
+            redistributable: o.redistributable,
+            url: o.url,
+        })
+        .collect()
+}
+
+fn insert_batch(
+    conn: &mut rusqlite::Connection,
+    package_batch: &[Package],
+    maintainer_batch: &[Maintainer],
+    license_batch: &[License],
+) -> Result<()> {
+    let tx = conn.transaction()?;
+    {
+        let mut stmt = tx.prepare_cached(
+            "INSERT INTO packages VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )?;
+        for p in package_batch {
+            stmt.execute(rusqlite::params![
+                p.name,
+                p.pname,
+                p.version,
+                p.display_name,
+                p.system,
+                p.output_name,
+                p.available,
+                p.broken,
+                p.description,
+                p.homepage,
+                p.insecure,
+                p.unfree,
+                p.unsupported,
+                p.position,
+                p.long_description,
+                p.main_program,
+                p.platforms,
+                p.bad_platforms,
+            ])?;
+        }
+    }
+    {
+        let mut stmt = tx.prepare_cached("INSERT INTO maintainers (package_name, name, email, github, github_id, matrix) VALUES (?, ?, ?, ?, ?, ?)")?;
+        for m in maintainer_batch {
+            stmt.execute(rusqlite::params![
+                m.package_name,
+                m.name,
+                m.email,
+                m.github,
+                m.github_id,
+                m.matrix,
+            ])?;
+        }
+    }
+    {
+        let mut stmt = tx.prepare_cached("INSERT INTO licenses (package_name, spdx_id, short_name, full_name, free, redistributable, url) VALUES (?, ?, ?, ?, ?, ?, ?)")?;
+        for l in license_batch {
+            stmt.execute(rusqlite::params![
+                l.package_name,
+                l.spdx_id,
+                l.short_name,
+                l.full_name,
+                l.free,
+                l.redistributable,
+                l.url,
+            ])?;
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// A package database at `path` holding one package per row of `rows`, which
+/// give the values of `columns` in order. Columns left out stay NULL.
+#[cfg(test)]
+pub(crate) fn test_db_at(
+    path: &Path,
+    columns: &str,
+    rows: &[&[&dyn rusqlite::ToSql]],
+) -> Connection {
+    let conn = Connection::open(path).unwrap();
+    create_schema(&conn).unwrap();
+    for row in rows {
+        let placeholders = vec!["?"; row.len()].join(", ");
+        conn.execute(
+            &format!("INSERT INTO packages ({columns}) VALUES ({placeholders})"),
+            *row,
+        )
+        .unwrap();
+    }
+    conn
+}
+
+/// An in-memory [`test_db_at`].
+#[cfg(test)]
+pub(crate) fn test_db(columns: &str, rows: &[&[&dyn rusqlite::ToSql]]) -> Connection {
+    test_db_at(Path::new(":memory:"), columns, rows)
+}
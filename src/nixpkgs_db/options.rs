@@ -0,0 +1,325 @@
+use super::{Channel, download_verified, stream_entries};
+use color_eyre::eyre::Result;
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::Deserialize;
+use serde_json::Value;
+use std::io::{BufReader, Read};
+
+/// A NixOS module option, as indexed from a channel's `options.json`.
+#[derive(Debug)]
+pub struct NixosOption {
+    pub name: String,
+    pub option_type: Option<String>,
+    pub default: Option<String>,
+    pub example: Option<String>,
+    pub description: Option<String>,
+    /// Paths of the declaring modules relative to the nixpkgs repository, or
+    /// full URLs for modules declared elsewhere.
+    pub declarations: Vec<String>,
+    pub read_only: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct OptionJson {
+    #[serde(default, rename = "type")]
+    option_type: Option<String>,
+    #[serde(default)]
+    default: Option<Value>,
+    #[serde(default)]
+    example: Option<Value>,
+    #[serde(default)]
+    description: Option<Value>,
+    #[serde(default)]
+    declarations: Vec<DeclarationJson>,
+    #[serde(default, rename = "readOnly")]
+    read_only: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum DeclarationJson {
+    Path(String),
+    Link { url: String },
+}
+
+/// Renders a `default` or `example` value. Nix expressions are exported as
+/// `{ _type = "literalExpression"; text = ...; }` and are shown as written;
+/// anything else is a plain value, whose JSON form reads close enough to Nix.
+fn literal(value: Value) -> String {
+    match value {
+        Value::Object(mut fields) if fields.contains_key("_type") => match fields.remove("text") {
+            Some(Value::String(text)) => text,
+            other => other.map(|text| text.to_string()).unwrap_or_default(),
+        },
+        value => value.to_string(),
+    }
+}
+
+/// Descriptions are Markdown, either as a bare string or wrapped like
+/// [`literal`] values.
+fn description_text(value: Value) -> Option<String> {
+    match value {
+        Value::String(text) => Some(text),
+        Value::Object(mut fields) => match fields.remove("text")? {
+            Value::String(text) => Some(text),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Creates the `options` table, filled by [`import`].
+pub(super) fn create_schema(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE options (
+            name TEXT PRIMARY KEY,
+            type TEXT,
+            default_value TEXT,
+            example TEXT,
+            description TEXT,
+            declarations TEXT,
+            read_only INTEGER
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Downloads the release's `options.json.br` and imports every option into
+/// `conn`, returning how many there were. See [`insert`] for what happens
+/// when that fails.
+pub(super) async fn import(
+    channel: &Channel,
+    conn: &mut Connection,
+    url: &str,
+    hash: &str,
+) -> Result<usize> {
+    let temp_path =
+        crate::utils::get_data_dir().join(format!("options-{}.json.br.tmp", channel.name));
+    download_verified(url, hash, &temp_path).await?;
+
+    let imported = tokio::task::block_in_place(|| -> Result<usize> {
+        let file = std::fs::File::open(&temp_path)?;
+        let buffered = BufReader::with_capacity(64 * 1024, file);
+        let decoder = brotli::Decompressor::new(buffered, 64 * 1024);
+        insert(conn, BufReader::with_capacity(64 * 1024, decoder))
+    });
+    let _ = std::fs::remove_file(&temp_path);
+    imported
+}
+
+/// Inserts every option of the `options.json` in `reader` into `conn` in a
+/// single transaction, so a failure leaves the table empty rather than half
+/// filled. Package databases are built without a journal, under which a
+/// rollback leaves behind whatever was written, so the transaction gets one
+/// kept in memory.
+fn insert(conn: &mut Connection, reader: impl Read) -> Result<usize> {
+    let journal_mode: String = conn.pragma_query_value(None, "journal_mode", |row| row.get(0))?;
+    conn.pragma_update(None, "journal_mode", "MEMORY")?;
+
+    let inserted = insert_all(conn, reader);
+
+    conn.pragma_update(None, "journal_mode", &journal_mode)?;
+    inserted
+}
+
+fn insert_all(conn: &mut Connection, reader: impl Read) -> Result<usize> {
+    let tx = conn.transaction()?;
+    let mut count = 0;
+    {
+        let mut stmt = tx.prepare(
+            "INSERT OR REPLACE INTO options
+             (name, type, default_value, example, description, declarations, read_only)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )?;
+        stream_entries(reader, |name, option: OptionJson| {
+            let declarations: Vec<String> = option
+                .declarations
+                .into_iter()
+                .map(|declaration| match declaration {
+                    DeclarationJson::Path(path) => path,
+                    DeclarationJson::Link { url } => url,
+                })
+                .collect();
+
+            stmt.execute(params![
+                name,
+                option.option_type,
+                option.default.map(literal),
+                option.example.map(literal),
+                option.description.and_then(description_text),
+                declarations.join("\n"),
+                option.read_only,
+            ])?;
+            count += 1;
+            Ok(())
+        })?;
+    }
+    tx.commit()?;
+    Ok(count)
+}
+
+/// Whether the database has any options in it; only NixOS channels do.
+pub fn has_options(conn: &Connection) -> bool {
+    conn.query_row("SELECT 1 FROM options LIMIT 1", [], |_| Ok(()))
+        .optional()
+        .is_ok_and(|row| row.is_some())
+}
+
+/// Looks up an option by its exact name.
+pub fn find(conn: &Connection, name: &str) -> rusqlite::Result<Option<NixosOption>> {
+    conn.query_row(
+        "SELECT name, type, default_value, example, description, declarations, read_only
+         FROM options WHERE name = ?1",
+        [name.trim()],
+        |row| {
+            Ok(NixosOption {
+                name: row.get(0)?,
+                option_type: row.get(1)?,
+                default: row.get(2)?,
+                example: row.get(3)?,
+                description: row.get(4)?,
+                declarations: row
+                    .get::<_, Option<String>>(5)?
+                    .map(|declarations| {
+                        declarations
+                            .lines()
+                            .filter(|line| !line.is_empty())
+                            .map(str::to_string)
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+                read_only: row.get::<_, Option<bool>>(6)?.unwrap_or(false),
+            })
+        },
+    )
+    .optional()
+}
+
+/// Option names for autocompletion: names starting with `partial` first,
+/// then names merely containing it, shortest first.
+pub fn complete(conn: &Connection, partial: &str, limit: usize) -> rusqlite::Result<Vec<String>> {
+    let partial = partial.trim();
+    if partial.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let queries = [
+        "SELECT name FROM options
+         WHERE name >= ?1 AND name < ?1 || char(1114111)
+         ORDER BY length(name), name
+         LIMIT ?2",
+        "SELECT name FROM options
+         WHERE instr(name, ?1) > 0
+         ORDER BY length(name), name
+         LIMIT ?2",
+    ];
+
+    let mut names: Vec<String> = Vec::with_capacity(limit);
+    for query in queries {
+        if names.len() >= limit {
+            break;
+        }
+
+        let mut stmt = conn.prepare_cached(query)?;
+        let rows = stmt.query_map(params![partial, limit], |row| row.get::<_, String>(0))?;
+        for name in rows.filter_map(Result::ok) {
+            if names.len() >= limit {
+                break;
+            }
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+
+    Ok(names)
+}
+
+/// A link to where an option is declared. Paths inside nixpkgs point at the
+/// channel's branch, which has the same name as the channel.
+pub fn declaration_url(channel: &Channel, declaration: &str) -> String {
+    if declaration.starts_with("https://") || declaration.starts_with("http://") {
+        declaration.to_string()
+    } else {
+        format!(
+            "https://github.com/NixOS/nixpkgs/blob/{}/{declaration}",
+            channel.name
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn literal_unwraps_nix_expressions() {
+        assert_eq!(
+            literal(json!({ "_type": "literalExpression", "text": "pkgs.hello" })),
+            "pkgs.hello"
+        );
+        assert_eq!(literal(json!("foo")), "\"foo\"");
+        assert_eq!(literal(json!(false)), "false");
+        assert_eq!(
+            description_text(json!({ "_type": "mdDoc", "text": "Whether to *do* it." })),
+            Some("Whether to *do* it.".to_string())
+        );
+    }
+
+    #[test]
+    fn failed_import_leaves_no_options() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        create_schema(&conn).unwrap();
+        // Like the package databases are built.
+        conn.pragma_update(None, "journal_mode", "OFF").unwrap();
+
+        let truncated = r#"{ "services.nginx.enable": { "type": "boolean" }, "services.ng"#;
+        assert!(insert(&mut conn, truncated.as_bytes()).is_err());
+        assert!(!has_options(&conn));
+
+        let journal_mode: String = conn
+            .pragma_query_value(None, "journal_mode", |row| row.get(0))
+            .unwrap();
+        assert_eq!(journal_mode, "off");
+    }
+
+    #[test]
+    fn finds_and_completes_options() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_schema(&conn).unwrap();
+        assert!(!has_options(&conn));
+
+        for name in [
+            "services.nginx.enable",
+            "services.nginx.package",
+            "programs.nginx-helper.enable",
+        ] {
+            conn.execute(
+                "INSERT INTO options (name, declarations) VALUES (?1, ?2)",
+                params![name, "nixos/modules/services/web-servers/nginx/default.nix"],
+            )
+            .unwrap();
+        }
+
+        assert!(has_options(&conn));
+        let option = find(&conn, "services.nginx.enable").unwrap().unwrap();
+        assert_eq!(
+            option.declarations,
+            ["nixos/modules/services/web-servers/nginx/default.nix"]
+        );
+        assert_eq!(
+            complete(&conn, "services.nginx", 25).unwrap(),
+            ["services.nginx.enable", "services.nginx.package"]
+        );
+        assert_eq!(
+            complete(&conn, "nginx", 25).unwrap(),
+            [
+                "services.nginx.enable",
+                "services.nginx.package",
+                "programs.nginx-helper.enable"
+            ]
+        );
+    }
+}
@@ -0,0 +1,109 @@
+use rusqlite::{Connection, OptionalExtension};
+use std::collections::BTreeSet;
+
+/// The systems people ask about most, shown as a matrix in `/nixpkg`.
+pub const COMMON_SYSTEMS: [&str; 4] = [
+    "x86_64-linux",
+    "aarch64-linux",
+    "x86_64-darwin",
+    "aarch64-darwin",
+];
+
+/// The systems a package claims to build on, from `meta.platforms` and
+/// `meta.badPlatforms`. Only plain system strings are kept; platform patterns
+/// are dropped when importing.
+#[derive(Debug, Default)]
+pub struct Platforms {
+    pub supported: Vec<String>,
+    pub bad: Vec<String>,
+}
+
+impl Platforms {
+    /// Whether the package is meant to build on `system`, or `None` if it
+    /// doesn't list any platforms to go by.
+    pub fn supports(&self, system: &str) -> Option<bool> {
+        if self.supported.is_empty() {
+            return None;
+        }
+        Some(self.supported.iter().any(|s| s == system) && !self.bad.iter().any(|s| s == system))
+    }
+}
+
+/// Splits a `platform_sets.systems` value back into its systems.
+fn split(systems: Option<String>) -> Vec<String> {
+    systems
+        .map(|systems| systems.split(' ').map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+/// The platforms of `package`, or `None` if there's no such package.
+pub fn platforms_of(conn: &Connection, package: &str) -> rusqlite::Result<Option<Platforms>> {
+    conn.query_row(
+        "SELECT good.systems, bad.systems FROM packages p
+         LEFT JOIN platform_sets good ON good.id = p.platforms
+         LEFT JOIN platform_sets bad ON bad.id = p.bad_platforms
+         WHERE p.package_name = ?1",
+        [package],
+        |row| {
+            Ok(Platforms {
+                supported: split(row.get(0)?),
+                bad: split(row.get(1)?),
+            })
+        },
+    )
+    .optional()
+}
+
+/// Systems containing `partial`, for autocompletion. The common systems come
+/// first, then everything else alphabetically.
+pub fn complete_systems(
+    conn: &Connection,
+    partial: &str,
+    limit: usize,
+) -> rusqlite::Result<Vec<String>> {
+    let partial = partial.trim();
+
+    let mut stmt = conn.prepare_cached("SELECT systems FROM platform_sets")?;
+    let systems: BTreeSet<String> = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .filter_map(Result::ok)
+        .flat_map(|systems| split(Some(systems)))
+        .collect();
+
+    let matches = |system: &&str| system.contains(partial);
+    let mut completions: Vec<String> = COMMON_SYSTEMS
+        .iter()
+        .copied()
+        .filter(matches)
+        .map(str::to_string)
+        .collect();
+    completions.extend(
+        systems
+            .iter()
+            .map(String::as_str)
+            .filter(matches)
+            .filter(|system| !COMMON_SYSTEMS.contains(system))
+            .map(str::to_string),
+    );
+    completions.truncate(limit);
+
+    Ok(completions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn supports_respects_bad_platforms() {
+        let platforms = Platforms {
+            supported: vec!["x86_64-linux".to_string(), "aarch64-darwin".to_string()],
+            bad: vec!["aarch64-darwin".to_string()],
+        };
+
+        assert_eq!(platforms.supports("x86_64-linux"), Some(true));
+        assert_eq!(platforms.supports("aarch64-darwin"), Some(false));
+        assert_eq!(platforms.supports("riscv64-linux"), Some(false));
+        assert_eq!(Platforms::default().supports("x86_64-linux"), None);
+    }
+}
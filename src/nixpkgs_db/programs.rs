@@ -0,0 +1,108 @@
+use rusqlite::{Connection, params};
+
+/// A package that installs a given program.
+#[derive(Debug)]
+pub struct Provider {
+    pub package_name: String,
+    pub version: Option<String>,
+    pub broken: bool,
+    pub insecure: bool,
+}
+
+/// Finds the packages providing `program`, best candidates first.
+///
+/// Packages whose `meta.mainProgram` is `program` match, as do packages without
+/// a `mainProgram` whose pname is `program`, since plenty of packages never set
+/// it when the binary is simply named after the package. Top-level attributes
+/// rank above nested ones like `python3Packages.foo`, working packages above
+/// broken or insecure ones, and packages named after the program above ones
+/// that merely ship it.
+pub fn providers(
+    conn: &Connection,
+    program: &str,
+    limit: usize,
+) -> rusqlite::Result<Vec<Provider>> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT package_name, version, broken, insecure FROM packages
+         WHERE main_program = ?1 OR (main_program IS NULL AND pname = ?1)
+         ORDER BY instr(package_name, '.') = 0 DESC,
+                  broken = 1 ASC,
+                  insecure = 1 ASC,
+                  pname = ?1 DESC,
+                  length(package_name),
+                  package_name
+         LIMIT ?2",
+    )?;
+
+    let providers = stmt
+        .query_map(params![program, limit], |row| {
+            Ok(Provider {
+                package_name: row.get(0)?,
+                version: row.get(1)?,
+                broken: row.get::<_, Option<i32>>(2)?.unwrap_or(0) != 0,
+                insecure: row.get::<_, Option<i32>>(3)?.unwrap_or(0) != 0,
+            })
+        })?
+        .filter_map(Result::ok)
+        .collect();
+
+    Ok(providers)
+}
+
+/// How many packages in total provide `program`, for when [`providers`] was
+/// cut short by its limit.
+pub fn count_providers(conn: &Connection, program: &str) -> rusqlite::Result<usize> {
+    conn.query_row(
+        "SELECT count(*) FROM packages
+         WHERE main_program = ?1 OR (main_program IS NULL AND pname = ?1)",
+        [program],
+        |row| row.get(0),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_db() -> Connection {
+        crate::nixpkgs_db::test_db(
+            "package_name, pname, main_program, broken, insecure",
+            &[
+                params!["ripgrep", "ripgrep", Some("rg"), 0, 0],
+                params!["ripgrep-all", "ripgrep-all", Some("rga"), 0, 0],
+                params!["pkgsStatic.ripgrep", "ripgrep", Some("rg"), 0, 0],
+                params!["ripgrep-old", "ripgrep-old", Some("rg"), 1, 0],
+                params!["hello", "hello", None::<&str>, 0, 0],
+                params!["python3Packages.hello", "hello", None::<&str>, 0, 0],
+                params!["gnugrep", "gnugrep", Some("grep"), 0, 0],
+                params!["grep", "gnugrep", Some("grep"), 0, 0],
+            ],
+        )
+    }
+
+    fn names(conn: &Connection, program: &str) -> Vec<String> {
+        providers(conn, program, 10)
+            .unwrap()
+            .into_iter()
+            .map(|provider| provider.package_name)
+            .collect()
+    }
+
+    #[test]
+    fn ranks_top_level_working_packages_first() {
+        let conn = test_db();
+        assert_eq!(
+            names(&conn, "rg"),
+            ["ripgrep", "ripgrep-old", "pkgsStatic.ripgrep"]
+        );
+        assert_eq!(names(&conn, "grep"), ["grep", "gnugrep"]);
+    }
+
+    #[test]
+    fn falls_back_to_pname_without_main_program() {
+        let conn = test_db();
+        assert_eq!(names(&conn, "hello"), ["hello", "python3Packages.hello"]);
+        assert!(names(&conn, "ripgrep").is_empty());
+        assert_eq!(count_providers(&conn, "rg").unwrap(), 3);
+    }
+}
@@ -0,0 +1,395 @@
+use rusqlite::{Connection, params};
+
+/// Upper bound on how many ranked results a search returns.
+pub const MAX_RESULTS: usize = 100;
+/// How many candidates are pulled from the database before being re-ranked by
+/// edit distance when looking for "did you mean" suggestions.
+const SUGGESTION_CANDIDATES: usize = 200;
+
+/// A single ranked match from [`search`].
+#[derive(Debug)]
+pub struct SearchHit {
+    pub package_name: String,
+    pub version: Option<String>,
+    pub description: Option<String>,
+}
+
+/// Restricts [`search`] to packages under particular licenses.
+#[derive(Debug, Default)]
+pub struct LicenseFilter {
+    /// Only packages whose licenses are all free.
+    pub free_only: bool,
+    /// Only packages with this license among their licenses.
+    pub spdx_id: Option<String>,
+}
+
+/// Turns free-form user input into an FTS5 query. Every word becomes a quoted
+/// prefix term and the terms are implicitly AND-ed, so `python3 requ` matches
+/// `python3Packages.requests`. Returns `None` if the input has no searchable
+/// words.
+fn fts_query(input: &str) -> Option<String> {
+    let terms: Vec<String> = search_terms(input)
+        .map(|term| format!("\"{term}\"*"))
+        .collect();
+
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" "))
+    }
+}
+
+/// Splits input the same way the default `unicode61` tokenizer does, on
+/// anything that isn't alphanumeric, so quoting the terms can never produce an
+/// invalid FTS5 query.
+fn search_terms(input: &str) -> impl Iterator<Item = &str> {
+    input
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+}
+
+/// Searches package names, pnames and descriptions, best matches first. Exact
+/// pname matches always rank first, then hits are ordered by BM25 with name
+/// columns weighted well above the descriptions. Packages not matching
+/// `filter` are left out.
+pub fn search(
+    conn: &Connection,
+    query: &str,
+    filter: &LicenseFilter,
+) -> rusqlite::Result<Vec<SearchHit>> {
+    let Some(fts) = fts_query(query) else {
+        return Ok(Vec::new());
+    };
+
+    let mut stmt = conn.prepare_cached(
+        "SELECT p.package_name, p.version, p.description
+         FROM packages_fts
+         JOIN packages p ON p.rowid = packages_fts.rowid
+         WHERE packages_fts MATCH ?1
+           AND (?4 = 0 OR (
+               EXISTS (SELECT 1 FROM licenses l WHERE l.package_name = p.package_name AND l.free = 1)
+               AND NOT EXISTS (SELECT 1 FROM licenses l WHERE l.package_name = p.package_name AND l.free IS NOT 1)
+           ))
+           AND (?5 IS NULL OR EXISTS (
+               SELECT 1 FROM licenses l
+               WHERE l.package_name = p.package_name AND l.spdx_id = ?5 COLLATE NOCASE
+           ))
+         ORDER BY p.pname = ?2 COLLATE NOCASE DESC,
+                  bm25(packages_fts, 10.0, 10.0, 2.0, 0.5),
+                  length(p.package_name)
+         LIMIT ?3",
+    )?;
+
+    let hits = stmt
+        .query_map(
+            params![
+                fts,
+                query.trim(),
+                MAX_RESULTS,
+                filter.free_only,
+                filter.spdx_id.as_deref().map(str::trim),
+            ],
+            |row| {
+                Ok(SearchHit {
+                    package_name: row.get(0)?,
+                    version: row.get(1)?,
+                    description: row.get(2)?,
+                })
+            },
+        )?
+        .filter_map(Result::ok)
+        .collect();
+
+    Ok(hits)
+}
+
+/// Suggests package names close to `name`, for when an exact lookup misses.
+///
+/// Candidates come from a prefix search on the name columns (any word may
+/// match) and from packages sharing the first few characters of the pname;
+/// they are then ranked by edit distance, first against the last attribute
+/// path segment (so `python3Packages.foo` finds `python312Packages.foo`) and
+/// then against the whole attribute path.
+pub fn suggest(conn: &Connection, name: &str, limit: usize) -> rusqlite::Result<Vec<String>> {
+    let wanted = name.trim().to_lowercase();
+    let wanted_leaf = leaf(&wanted);
+    if wanted_leaf.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut candidates: Vec<(String, Option<String>)> = Vec::new();
+
+    let terms: Vec<String> = search_terms(&wanted)
+        .map(|term| format!("\"{term}\"*"))
+        .collect();
+    if !terms.is_empty() {
+        let fts = format!("{{package_name pname}} : ({})", terms.join(" OR "));
+        let mut stmt = conn.prepare_cached(
+            "SELECT p.package_name, p.pname
+             FROM packages_fts
+             JOIN packages p ON p.rowid = packages_fts.rowid
+             WHERE packages_fts MATCH ?1
+             ORDER BY bm25(packages_fts)
+             LIMIT ?2",
+        )?;
+        candidates.extend(
+            stmt.query_map(params![fts, SUGGESTION_CANDIDATES], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .filter_map(Result::ok),
+        );
+    }
+
+    let stem: String = wanted_leaf.chars().take(3).collect();
+    let mut stmt = conn.prepare_cached(
+        "SELECT package_name, pname FROM packages WHERE pname LIKE ?1 || '%' LIMIT ?2",
+    )?;
+    candidates.extend(
+        stmt.query_map(params![stem, SUGGESTION_CANDIDATES], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?
+        .filter_map(Result::ok),
+    );
+
+    let threshold = (wanted_leaf.chars().count() / 3).max(2);
+
+    let mut ranked: Vec<(usize, usize, String)> = candidates
+        .into_iter()
+        .filter_map(|(package_name, pname)| {
+            let lower = package_name.to_lowercase();
+            let leaf_distance = levenshtein(wanted_leaf, leaf(&lower))
+                .min(pname.map_or(usize::MAX, |p| levenshtein(wanted_leaf, &p.to_lowercase())));
+            (leaf_distance <= threshold)
+                .then(|| (leaf_distance, levenshtein(&wanted, &lower), package_name))
+        })
+        .collect();
+
+    ranked.sort();
+    ranked.dedup_by(|a, b| a.2 == b.2);
+
+    Ok(ranked
+        .into_iter()
+        .map(|(_, _, package_name)| package_name)
+        .take(limit)
+        .collect())
+}
+
+/// Completes a partially typed attribute path for slash-command autocomplete.
+/// Attribute paths starting with `partial` come first, then packages whose
+/// pname starts with it, then attribute paths with a later segment starting
+/// with it, each group shortest first. The first two are range scans over the
+/// `package_name` and `pname` indexes and the last goes through the search
+/// index, so this stays well within Discord's autocomplete deadline.
+pub fn complete(conn: &Connection, partial: &str, limit: usize) -> rusqlite::Result<Vec<String>> {
+    let partial = partial.trim();
+    if partial.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let segments = fts_query(partial).map(|terms| format!("package_name : ({terms})"));
+    let queries = [
+        (
+            "SELECT package_name FROM packages
+             WHERE package_name >= ?1 AND package_name < ?1 || char(1114111)
+             ORDER BY length(package_name), package_name
+             LIMIT ?2",
+            Some(partial.to_string()),
+        ),
+        (
+            "SELECT package_name FROM packages
+             WHERE pname >= ?1 AND pname < ?1 || char(1114111)
+             ORDER BY length(package_name), package_name
+             LIMIT ?2",
+            Some(partial.to_string()),
+        ),
+        (
+            "SELECT p.package_name FROM packages_fts
+             JOIN packages p ON p.rowid = packages_fts.rowid
+             WHERE packages_fts MATCH ?1
+             ORDER BY length(p.package_name), p.package_name
+             LIMIT ?2",
+            segments,
+        ),
+    ];
+
+    let mut names: Vec<String> = Vec::with_capacity(limit);
+    for (query, pattern) in queries {
+        if names.len() >= limit {
+            break;
+        }
+        let Some(pattern) = pattern else {
+            continue;
+        };
+
+        let mut stmt = conn.prepare_cached(query)?;
+        let rows = stmt.query_map(params![pattern, limit], |row| row.get::<_, String>(0))?;
+        for name in rows.filter_map(Result::ok) {
+            if names.len() >= limit {
+                break;
+            }
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+
+    Ok(names)
+}
+
+/// The last segment of an attribute path, e.g. `foo` for
+/// `python3Packages.foo`.
+fn leaf(attr_path: &str) -> &str {
+    attr_path.rsplit('.').next().unwrap_or(attr_path)
+}
+
+/// Levenshtein edit distance between two strings, by characters.
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+
+    for (i, ca) in a.chars().enumerate() {
+        current[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_db() -> Connection {
+        let conn = crate::nixpkgs_db::test_db(
+            "package_name, pname, description",
+            &[
+                params![
+                    "ripgrep",
+                    "ripgrep",
+                    "Utility that combines the usability of The Silver Searcher with the raw speed of grep",
+                ],
+                params![
+                    "python312Packages.requests",
+                    "requests",
+                    "HTTP library for Python",
+                ],
+                params![
+                    "python313Packages.requests",
+                    "requests",
+                    "HTTP library for Python",
+                ],
+                params![
+                    "grep",
+                    "gnugrep",
+                    "GNU implementation of the Unix grep command",
+                ],
+            ],
+        );
+        crate::nixpkgs_db::build_search_index(&conn).unwrap();
+        conn
+    }
+
+    #[test]
+    fn fts_query_quotes_prefix_terms_and_drops_punctuation() {
+        assert_eq!(
+            fts_query("python3Packages.req\"uests"),
+            Some("\"python3Packages\"* \"req\"* \"uests\"*".to_string())
+        );
+        assert_eq!(fts_query(" .-* "), None);
+    }
+
+    #[test]
+    fn levenshtein_counts_edits() {
+        assert_eq!(levenshtein("ripgrep", "ripgrep"), 0);
+        assert_eq!(levenshtein("ripgrpe", "ripgrep"), 2);
+        assert_eq!(levenshtein("", "abc"), 3);
+    }
+
+    #[test]
+    fn search_ranks_name_matches_above_descriptions() {
+        let conn = test_db();
+        let hits = search(&conn, "grep", &LicenseFilter::default()).unwrap();
+        let names: Vec<&str> = hits.iter().map(|h| h.package_name.as_str()).collect();
+
+        assert_eq!(names.first(), Some(&"grep"));
+        assert!(names.contains(&"ripgrep"));
+    }
+
+    #[test]
+    fn search_filters_on_licenses() {
+        let conn = test_db();
+        for (name, spdx_id, free) in [
+            ("ripgrep", "MIT", true),
+            ("ripgrep", "Unlicense", true),
+            ("grep", "GPL-3.0-or-later", true),
+            ("grep", "Unfree", false),
+        ] {
+            conn.execute(
+                "INSERT INTO licenses (package_name, spdx_id, free) VALUES (?1, ?2, ?3)",
+                params![name, spdx_id, free],
+            )
+            .unwrap();
+        }
+        let names = |filter: LicenseFilter| -> Vec<String> {
+            search(&conn, "grep", &filter)
+                .unwrap()
+                .into_iter()
+                .map(|hit| hit.package_name)
+                .collect()
+        };
+
+        assert_eq!(
+            names(LicenseFilter {
+                free_only: true,
+                spdx_id: None,
+            }),
+            vec!["ripgrep"]
+        );
+        assert_eq!(
+            names(LicenseFilter {
+                free_only: false,
+                spdx_id: Some("gpl-3.0-or-later".to_string()),
+            }),
+            vec!["grep"]
+        );
+    }
+
+    #[test]
+    fn suggest_finds_typos_and_other_package_sets() {
+        let conn = test_db();
+
+        assert_eq!(suggest(&conn, "ripgrpe", 5).unwrap(), vec!["ripgrep"]);
+        assert_eq!(
+            suggest(&conn, "python3Packages.requests", 5).unwrap(),
+            vec!["python312Packages.requests", "python313Packages.requests"]
+        );
+    }
+
+    #[test]
+    fn complete_prefers_attribute_prefix_then_pname_then_segments() {
+        let conn = test_db();
+        conn.execute(
+            "INSERT INTO packages (package_name) VALUES ('nodePackages.prettier')",
+            [],
+        )
+        .unwrap();
+        crate::nixpkgs_db::build_search_index(&conn).unwrap();
+
+        assert_eq!(
+            complete(&conn, "python3", 25).unwrap(),
+            vec!["python312Packages.requests", "python313Packages.requests"]
+        );
+        assert_eq!(complete(&conn, "gnu", 25).unwrap(), vec!["grep"]);
+        assert_eq!(complete(&conn, "grep", 25).unwrap(), vec!["grep"]);
+        assert_eq!(
+            complete(&conn, "prett", 25).unwrap(),
+            vec!["nodePackages.prettier"]
+        );
+        assert!(complete(&conn, "  ", 25).unwrap().is_empty());
+    }
+}
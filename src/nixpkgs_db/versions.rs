@@ -0,0 +1,73 @@
+use std::cmp::Ordering;
+
+/// Splits off the next component of a version: a run of digits or a run of
+/// anything else, skipping the `.` and `-` separators in between.
+fn next_component(version: &str) -> (&str, &str) {
+    let version = version.trim_start_matches(['.', '-']);
+    let is_digit = version.starts_with(|c: char| c.is_ascii_digit());
+    let end = version
+        .find(|c: char| c == '.' || c == '-' || c.is_ascii_digit() != is_digit)
+        .unwrap_or(version.len());
+    version.split_at(end)
+}
+
+/// Whether component `a` sorts before component `b`.
+fn component_lt(a: &str, b: &str) -> bool {
+    let a_num = a.parse::<u64>().ok();
+    let b_num = b.parse::<u64>().ok();
+
+    match (a_num, b_num) {
+        (Some(a), Some(b)) => a < b,
+        _ if a.is_empty() && b_num.is_some() => true,
+        _ if a == "pre" && b != "pre" => true,
+        _ if b == "pre" => false,
+        // `2.3a` < `2.3.1`
+        (_, Some(_)) => true,
+        (Some(_), _) => false,
+        _ => a < b,
+    }
+}
+
+/// Compares two versions the way `builtins.compareVersions` does, so that
+/// `1.10` > `1.9`, `2.3pre1` < `2.3` and `2.3a` < `2.3.1`.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    while !a.is_empty() || !b.is_empty() {
+        let (a_component, a_rest) = next_component(a);
+        let (b_component, b_rest) = next_component(b);
+
+        if component_lt(a_component, b_component) {
+            return Ordering::Less;
+        }
+        if component_lt(b_component, a_component) {
+            return Ordering::Greater;
+        }
+
+        (a, b) = (a_rest, b_rest);
+    }
+    Ordering::Equal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_like_nix() {
+        // Mostly the examples from the `builtins.compareVersions` docs.
+        assert_eq!(compare_versions("1.0", "2.3"), Ordering::Less);
+        assert_eq!(compare_versions("2.1", "2.3"), Ordering::Less);
+        assert_eq!(compare_versions("2.3", "2.3"), Ordering::Equal);
+        assert_eq!(compare_versions("2.5", "2.3"), Ordering::Greater);
+        assert_eq!(compare_versions("3.1", "2.3"), Ordering::Greater);
+        assert_eq!(compare_versions("2.3.1", "2.3"), Ordering::Greater);
+        assert_eq!(compare_versions("2.3.1", "2.3a"), Ordering::Greater);
+        assert_eq!(compare_versions("2.3pre1", "2.3"), Ordering::Less);
+        assert_eq!(compare_versions("2.3pre3", "2.3pre12"), Ordering::Less);
+        assert_eq!(compare_versions("2.3a", "2.3c"), Ordering::Less);
+        assert_eq!(compare_versions("2.3pre1", "2.3c"), Ordering::Less);
+        assert_eq!(compare_versions("2.3pre1", "2.3q"), Ordering::Less);
+        assert_eq!(compare_versions("1.10", "1.9"), Ordering::Greater);
+        assert_eq!(compare_versions("140.0.1", "140"), Ordering::Greater);
+    }
+}
@@ -7,8 +7,8 @@ pub fn get_data_dir() -> PathBuf {
 }
 
 /// The single application database. Every feature stores its state here in its
-/// own table(s); the large, auto-generated nixpkgs `packages.db` is kept
-/// separate since it is rebuilt wholesale.
+/// own table(s); the large, auto-generated nixpkgs package databases are kept
+/// separate since they are rebuilt wholesale.
 pub static DB: LazyLock<Mutex<Connection>> = LazyLock::new(|| {
     let db_path = get_data_dir().join("blahaj.db");
     let conn = Connection::open(db_path).expect("Failed to open database");
@@ -95,9 +95,13 @@ fn copy_table(conn: &Connection, table: &str) -> rusqlite::Result<()> {
 fn init_schema(conn: &Connection) -> rusqlite::Result<()> {
     init_starboard(conn)?;
     init_tracked_prs(conn)?;
+    init_tracked_versions(conn)?;
     init_avatar_emojis(conn)?;
     init_color_roles(conn)?;
     init_relationships(conn)?;
+    init_nixpkgs_updates(conn)?;
+    init_nixpkgs_history(conn)?;
+    init_grok_config(conn)?;
     Ok(())
 }
 
@@ -142,6 +146,7 @@ fn init_tracked_prs(conn: &Connection) -> rusqlite::Result<()> {
             channel_id INTEGER NOT NULL,
             created_at INTEGER NOT NULL,
             target_branch TEXT NOT NULL DEFAULT 'nixpkgs-unstable',
+            follow_backport INTEGER NOT NULL DEFAULT 0,
             PRIMARY KEY (pr_number, user_id)
         )",
         [],
@@ -154,6 +159,66 @@ fn init_tracked_prs(conn: &Connection) -> rusqlite::Result<()> {
         )?;
     }
 
+    if !column_exists(conn, "tracked_prs", "follow_backport")? {
+        conn.execute(
+            "ALTER TABLE tracked_prs ADD COLUMN follow_backport INTEGER NOT NULL DEFAULT 0",
+            [],
+        )?;
+    }
+
+    // PRs watched on behalf of a whole channel, whose progress is shown in a
+    // single status message. `reached` holds the branches the PR is already
+    // in, separated by spaces.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS watched_prs (
+            pr_number INTEGER NOT NULL,
+            channel_id INTEGER NOT NULL,
+            message_id INTEGER NOT NULL,
+            reached TEXT NOT NULL DEFAULT '',
+            created_at INTEGER NOT NULL,
+            PRIMARY KEY (pr_number, channel_id)
+        )",
+        [],
+    )?;
+
+    // What the PR poller last found out about each tracked PR, so unchanged
+    // PRs can be skipped. `reached` holds the branches the merge commit is
+    // in; `checked_heads` the `branch=sha` heads the others were last
+    // compared at.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS tracked_pr_status (
+            pr_number INTEGER PRIMARY KEY,
+            title TEXT NOT NULL,
+            html_url TEXT NOT NULL,
+            base_ref TEXT NOT NULL,
+            merged INTEGER NOT NULL DEFAULT 0,
+            merge_commit_sha TEXT,
+            etag TEXT,
+            reached TEXT NOT NULL DEFAULT '',
+            checked_heads TEXT NOT NULL DEFAULT '',
+            updated_at INTEGER NOT NULL
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+fn init_tracked_versions(conn: &Connection) -> rusqlite::Result<()> {
+    // `nixpkgs_channel` is empty for trackers happy with any configured channel.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS tracked_versions (
+            user_id INTEGER NOT NULL,
+            channel_id INTEGER NOT NULL,
+            package_name TEXT NOT NULL,
+            min_version TEXT NOT NULL,
+            nixpkgs_channel TEXT NOT NULL DEFAULT '',
+            created_at INTEGER NOT NULL,
+            PRIMARY KEY (user_id, package_name, nixpkgs_channel)
+        )",
+        [],
+    )?;
+
     Ok(())
 }
 
@@ -263,6 +328,158 @@ fn init_relationships(conn: &Connection) -> rusqlite::Result<()> {
     Ok(())
 }
 
+fn init_nixpkgs_updates(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS nixpkgs_updates (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            channel TEXT NOT NULL,
+            from_hash TEXT,
+            to_hash TEXT NOT NULL,
+            created_at INTEGER NOT NULL
+        )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS nixpkgs_changes (
+            update_id INTEGER NOT NULL,
+            package_name TEXT NOT NULL,
+            kind TEXT NOT NULL CHECK(kind IN ('added', 'removed', 'updated', 'broken', 'insecure')),
+            old_version TEXT,
+            new_version TEXT,
+            FOREIGN KEY (update_id) REFERENCES nixpkgs_updates(id)
+        )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS nixpkgs_announce_config (
+            guild_id INTEGER PRIMARY KEY,
+            channel_id INTEGER NOT NULL
+        )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_nixpkgs_updates_channel
+         ON nixpkgs_updates (channel, created_at)",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_nixpkgs_changes_update
+         ON nixpkgs_changes (update_id)",
+        [],
+    )?;
+
+    Ok(())
+}
+
+pub(crate) fn init_nixpkgs_history(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS nixpkgs_history (
+            channel TEXT NOT NULL,
+            package_name TEXT NOT NULL,
+            version TEXT NOT NULL,
+            first_hash TEXT,
+            first_seen INTEGER NOT NULL,
+            baseline INTEGER NOT NULL DEFAULT 0,
+            PRIMARY KEY (channel, package_name, version)
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+fn init_grok_config(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS grok_config (
+            guild_id INTEGER PRIMARY KEY,
+            endpoint TEXT,
+            api_key TEXT,
+            model TEXT,
+            temperature REAL,
+            max_tokens INTEGER,
+            system_prompt TEXT
+        )",
+        [],
+    )?;
+
+    if !column_exists(conn, "grok_config", "vision")? {
+        conn.execute("ALTER TABLE grok_config ADD COLUMN vision INTEGER", [])?;
+    }
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS grok_guilds (
+            guild_id INTEGER PRIMARY KEY,
+            enabled_at INTEGER NOT NULL
+        )",
+        [],
+    )?;
+
+    if !column_exists(conn, "grok_guilds", "threads")? {
+        conn.execute(
+            "ALTER TABLE grok_guilds ADD COLUMN threads INTEGER NOT NULL DEFAULT 0",
+            [],
+        )?;
+    }
+
+    // Disabling used to delete the row, so every row so far is enabled.
+    if !column_exists(conn, "grok_guilds", "enabled")? {
+        conn.execute(
+            "ALTER TABLE grok_guilds ADD COLUMN enabled INTEGER NOT NULL DEFAULT 1",
+            [],
+        )?;
+    }
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS grok_allowed (
+            guild_id INTEGER NOT NULL,
+            kind TEXT NOT NULL CHECK(kind IN ('channel', 'role')),
+            id INTEGER NOT NULL,
+            PRIMARY KEY (guild_id, kind, id)
+        )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS grok_usage (
+            day TEXT NOT NULL,
+            guild_id INTEGER NOT NULL,
+            user_id INTEGER NOT NULL,
+            requests INTEGER NOT NULL DEFAULT 0,
+            prompt_tokens INTEGER NOT NULL DEFAULT 0,
+            completion_tokens INTEGER NOT NULL DEFAULT 0,
+            PRIMARY KEY (day, guild_id, user_id)
+        )",
+        [],
+    )?;
+
+    // Threads @grok opened, and the conversation in each so far.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS grok_threads (
+            thread_id INTEGER PRIMARY KEY,
+            guild_id INTEGER NOT NULL,
+            created_at INTEGER NOT NULL
+        )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS grok_thread_messages (
+            thread_id INTEGER NOT NULL,
+            message_id INTEGER NOT NULL,
+            role TEXT NOT NULL CHECK(role IN ('user', 'assistant')),
+            content TEXT NOT NULL,
+            PRIMARY KEY (thread_id, message_id)
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
 /// Whether `table` already has a column named `column`.
 fn column_exists(conn: &Connection, table: &str, column: &str) -> rusqlite::Result<bool> {
     let columns = table_columns(conn, &format!("table_info({table})"))?;